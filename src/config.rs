@@ -0,0 +1,214 @@
+use crate::game::world::world::LevelType;
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const CONFIG_FILE: &str = "server.properties";
+
+//Settings loaded from server.properties at startup, same key=value format vanilla uses
+pub struct ServerConfig {
+    pub server_ip: String,
+    pub server_port: u16,
+    pub motd: String,
+    pub max_players: u32,
+    //How many player names the server list shows when hovering the player count
+    pub player_sample_size: usize,
+    pub gamemode: u8,
+    pub difficulty: u8,
+    pub level_name: String,
+    pub level_type: LevelType,
+    pub view_distance: u8,
+    pub online_mode: bool,
+    //Packets with at least this many bytes are compressed, a negative value disables compression
+    pub network_compression_threshold: i32,
+    //Base url of the session server used to authenticate players
+    pub session_server: String,
+    //Clients with more than this many bytes waiting to be written are considered not reading and disconnected
+    pub outbound_high_water_mark: usize,
+    //Template of chat messages, {name} and {message} are replaced and & starts a color code
+    pub chat_format: String,
+    //Players further than this many blocks on either axis don't get an entity's packets
+    pub entity_tracking_range: u32,
+    //Names of the players allowed to run the commands that need a permission level
+    pub ops: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            server_ip: "127.0.0.1".to_string(),
+            server_port: 25565,
+            motd: "Amethyst Minecraft Server".to_string(),
+            max_players: 10,
+            player_sample_size: 12,
+            gamemode: 1,
+            difficulty: 0,
+            level_name: "Mundo".to_string(),
+            level_type: LevelType::Default,
+            view_distance: 10,
+            online_mode: true,
+            network_compression_threshold: 256,
+            session_server: "https://sessionserver.mojang.com".to_string(),
+            outbound_high_water_mark: 16 * 1024 * 1024,
+            chat_format: "<{name}> {message}".to_string(),
+            entity_tracking_range: 48,
+            ops: Vec::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    //Loads the file, creating it with the defaults if it doesn't exist
+    pub fn load(path: &Path) -> ServerConfig {
+        let mut config = ServerConfig::default();
+
+        let content = match std::fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(_) => {
+                log!("{} not found, creating it with the default values", path.display());
+                if let Err(e) = std::fs::write(path, config.to_properties()) {
+                    log!("Couldn't write {}: {}", path.display(), e);
+                }
+                return config;
+            }
+        };
+
+        let mut properties = HashMap::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.find('=') {
+                Some(index) => {
+                    properties.insert(line[..index].trim(), line[index + 1..].trim());
+                }
+                None => log!("Ignoring invalid line in {}: {}", path.display(), line),
+            }
+        }
+
+        for (key, value) in properties {
+            let valid = match key {
+                "server-ip" => set(&mut config.server_ip, value),
+                "server-port" => set(&mut config.server_port, value),
+                "motd" => set(&mut config.motd, value),
+                "max-players" => set(&mut config.max_players, value),
+                "player-sample-size" => set(&mut config.player_sample_size, value),
+                "gamemode" => set(&mut config.gamemode, value),
+                "difficulty" => set(&mut config.difficulty, value),
+                "level-name" => set(&mut config.level_name, value),
+                "level-type" => match LevelType::from_name(value) {
+                    Some(t) => {
+                        config.level_type = t;
+                        true
+                    }
+                    None => false,
+                },
+                "view-distance" => set(&mut config.view_distance, value),
+                "online-mode" => set(&mut config.online_mode, value),
+                "network-compression-threshold" => {
+                    set(&mut config.network_compression_threshold, value)
+                }
+                "session-server" => set(&mut config.session_server, value),
+                "outbound-high-water-mark" => set(&mut config.outbound_high_water_mark, value),
+                "chat-format" => set(&mut config.chat_format, value),
+                "entity-tracking-range" => set(&mut config.entity_tracking_range, value),
+                "ops" => {
+                    config.ops = value
+                        .split(',')
+                        .map(|name| name.trim().to_string())
+                        .filter(|name| !name.is_empty())
+                        .collect();
+                    true
+                }
+                _ => {
+                    log!("Unknown property in {}: {}", path.display(), key);
+                    true
+                }
+            };
+
+            if !valid {
+                log!(
+                    "Invalid value for {} in {}: {}, using the default",
+                    key,
+                    path.display(),
+                    value
+                );
+            }
+        }
+
+        config
+    }
+
+    pub fn to_properties(&self) -> String {
+        format!(
+            "#Amethyst server properties\n\
+            server-ip={}\n\
+            server-port={}\n\
+            motd={}\n\
+            max-players={}\n\
+            player-sample-size={}\n\
+            gamemode={}\n\
+            difficulty={}\n\
+            level-name={}\n\
+            level-type={}\n\
+            view-distance={}\n\
+            online-mode={}\n\
+            network-compression-threshold={}\n\
+            session-server={}\n\
+            outbound-high-water-mark={}\n\
+            chat-format={}\n\
+            entity-tracking-range={}\n\
+            ops={}\n",
+            self.server_ip,
+            self.server_port,
+            self.motd,
+            self.max_players,
+            self.player_sample_size,
+            self.gamemode,
+            self.difficulty,
+            self.level_name,
+            self.level_type.to_str(),
+            self.view_distance,
+            self.online_mode,
+            self.network_compression_threshold,
+            self.session_server,
+            self.outbound_high_water_mark,
+            self.chat_format,
+            self.entity_tracking_range,
+            self.ops.join(",")
+        )
+    }
+
+    //An empty server-ip binds every interface, like vanilla
+    pub fn address(&self) -> String {
+        if self.server_ip.is_empty() {
+            format!("0.0.0.0:{}", self.server_port)
+        } else {
+            format!("{}:{}", self.server_ip, self.server_port)
+        }
+    }
+
+    pub fn is_op(&self, name: &str) -> bool {
+        self.ops.iter().any(|op| op.eq_ignore_ascii_case(name))
+    }
+
+    #[inline]
+    pub fn compression_threshold(&self) -> Option<usize> {
+        if self.network_compression_threshold < 0 {
+            None
+        } else {
+            Some(self.network_compression_threshold as usize)
+        }
+    }
+}
+
+//Parses the value into the field, leaving it untouched when invalid
+fn set<T: std::str::FromStr>(field: &mut T, value: &str) -> bool {
+    match value.parse() {
+        Ok(t) => {
+            *field = t;
+            true
+        }
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,77 @@
+use crate::net::network_manager::GameProtocol;
+use rustyline::error::ReadlineError;
+use rustyline::{Editor, ExternalPrinter};
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+
+//Set while the console reads from a terminal, it writes the logs above the prompt
+static PRINTER: Mutex<Option<Box<dyn ExternalPrinter + Send>>> = Mutex::new(None);
+
+//Prints a whole line, use it through log! instead of println! so the prompt isn't broken
+pub fn print(line: String) {
+    let mut printer = PRINTER.lock().unwrap();
+    match printer.as_mut() {
+        Some(printer) => {
+            let _ = printer.print(line + "\n");
+        }
+        None => println!("{}", line),
+    }
+}
+
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::console::print(format!($($arg)*))
+    };
+}
+
+//Reads commands from stdin and sends them to the engine, with line editing and history on a terminal
+pub fn start(game_writer: Sender<GameProtocol>) {
+    std::thread::Builder::new()
+        .name("Console Thread".to_string())
+        .spawn(move || {
+            let mut editor = match Editor::<()>::new() {
+                Ok(t) => t,
+                Err(e) => {
+                    log!("Couldn't open the console: {}", e);
+                    return;
+                }
+            };
+            //Fails when stdin or stdout isn't a terminal, then the logs are just printed
+            if let Ok(printer) = editor.create_external_printer() {
+                *PRINTER.lock().unwrap() = Some(Box::new(printer));
+            }
+
+            loop {
+                match editor.readline("> ") {
+                    Ok(line) => {
+                        let line = line.trim();
+                        if line.is_empty() {
+                            continue;
+                        }
+                        editor.add_history_entry(line);
+                        let line = line.strip_prefix('/').unwrap_or(line).to_string();
+                        if game_writer.send(GameProtocol::Console { line }).is_err() {
+                            break;
+                        }
+                    }
+                    //Ctrl+C stops the server like it would without the console
+                    Err(ReadlineError::Interrupted) => {
+                        let _ = game_writer.send(GameProtocol::Console {
+                            line: "stop".to_string(),
+                        });
+                        break;
+                    }
+                    //Stdin closed, like when running as a service, the server keeps going
+                    Err(ReadlineError::Eof) => break,
+                    Err(e) => {
+                        log!("Couldn't read the console: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            *PRINTER.lock().unwrap() = None;
+        })
+        .expect("Couldn't spawn the console thread");
+}
@@ -1,4 +1,11 @@
-use std::convert::{TryFrom, TryInto};
+use crate::game::nbt::NBTParseError;
+use crate::game::world::coords::Position;
+use crate::net::network_manager::ConnectionState;
+use std::convert::TryInto;
+use uuid::Uuid;
+
+//Longest string the protocol allows, in characters
+pub const MAX_STRING_LENGTH: usize = 32767;
 
 pub struct DataReader<'a> {
     pub data: &'a [u8],
@@ -13,126 +20,226 @@ impl DataReader<'_> {
         DataReader { data, cursor }
     }
 
-    pub fn read_data_fixed<'a>(&mut self, length: usize) -> Option<Vec<u8>> {
-        if !self.check_lenght(length) {
-            return None;
-        }
+    pub fn read_data_fixed(&mut self, length: usize) -> Result<Vec<u8>, PacketError> {
+        self.check_lenght(length)?;
 
-        let mut data = &self.data[self.cursor..self.cursor + length];
+        let data = &self.data[self.cursor..self.cursor + length];
 
         self.cursor += length;
-        Some(data.to_vec())
+        Ok(data.to_vec())
     }
 
-    pub fn read_data(&mut self) -> Option<Vec<u8>> {
-        let length = self.read_varint()?;
+    pub fn read_data(&mut self) -> Result<Vec<u8>, PacketError> {
+        let length = self.read_length()?;
 
-        self.read_data_fixed(length as usize)
+        self.read_data_fixed(length)
+    }
+
+    //Varint length prefix, which can't be negative
+    pub fn read_length(&mut self) -> Result<usize, PacketError> {
+        let length = self.read_varint()?;
+        if length < 0 {
+            return Err(PacketError::NegativeLength(length));
+        }
+        Ok(length as usize)
     }
 
-    pub fn read_varint<'a>(&mut self) -> Option<i32> {
+    pub fn read_varint(&mut self) -> Result<i32, PacketError> {
         let mut result: i32 = 0;
         let mut num_read: u8 = 0;
         let mut read: u8;
 
         loop {
+            //Checked before shifting, a longer one would shift past the width of the result
+            if num_read == 5 {
+                return Err(PacketError::VarIntTooLong);
+            }
             read = self.read_u8()?;
             result += (((read as i8) & 0b01111111) as i32) << (7 * num_read);
 
             num_read += 1;
-            if num_read > 5 {
-                return None;
-            }
             if (read & 0b10000000) == 0 {
-                return Some(result);
+                return Ok(result);
             }
         }
     }
 
-    pub fn read_varlong<'a>(&mut self) -> Option<i64> {
+    pub fn read_varlong(&mut self) -> Result<i64, PacketError> {
         let mut result: i64 = 0;
         let mut num_read: u8 = 0;
         let mut read: u8;
 
         loop {
+            //Checked before shifting, a longer one would shift past the width of the result
+            if num_read == 10 {
+                return Err(PacketError::VarIntTooLong);
+            }
             read = self.read_u8()?;
             result += ((read & 0b01111111) as i64) << (7 * num_read);
 
             num_read += 1;
-            if num_read > 10 {
-                return None;
-            }
             if (read & 0b10000000) == 0 {
-                return Some(result);
+                return Ok(result);
             }
         }
     }
 
     #[inline]
-    pub fn read_u8<'a>(&mut self) -> Option<u8> {
-        if !self.check_lenght(1) {
-            return None;
-        }
+    pub fn read_u8(&mut self) -> Result<u8, PacketError> {
+        self.check_lenght(1)?;
 
         self.cursor += 1;
 
-        return Some(self.data[self.cursor - 1]);
+        Ok(self.data[self.cursor - 1])
     }
 
-    pub fn read_u16<'a>(&mut self) -> Option<u16> {
-        if !self.check_lenght(2) {
-            return None;
-        }
+    pub fn read_u16(&mut self) -> Result<u16, PacketError> {
+        self.check_lenght(2)?;
 
-        let n = u16::from_be_bytes(match self.data[self.cursor..self.cursor + 2].try_into() {
-            Ok(t) => t,
-            Err(_e) => return None,
-        });
+        let n = u16::from_be_bytes(self.data[self.cursor..self.cursor + 2].try_into().unwrap());
 
         self.cursor += 2;
-        return Some(n);
+        Ok(n)
     }
 
-    pub fn read_i64<'a>(&mut self) -> Option<i64> {
-        if !self.check_lenght(8) {
-            return None;
-        }
+    pub fn read_i8(&mut self) -> Result<i8, PacketError> {
+        Ok(self.read_u8()? as i8)
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, PacketError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_i16(&mut self) -> Result<i16, PacketError> {
+        Ok(self.read_u16()? as i16)
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, PacketError> {
+        self.check_lenght(4)?;
+
+        let n = i32::from_be_bytes(self.data[self.cursor..self.cursor + 4].try_into().unwrap());
+
+        self.cursor += 4;
+        Ok(n)
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32, PacketError> {
+        Ok(f32::from_bits(self.read_i32()? as u32))
+    }
+
+    pub fn read_f64(&mut self) -> Result<f64, PacketError> {
+        Ok(f64::from_bits(self.read_i64()? as u64))
+    }
+
+    pub fn read_position(&mut self) -> Result<Position, PacketError> {
+        Ok(Position::decode(self.read_i64()?))
+    }
+
+    pub fn read_uuid(&mut self) -> Result<Uuid, PacketError> {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&self.read_data_fixed(16)?);
+        Ok(Uuid::from_bytes(bytes))
+    }
+
+    //Bytes left until the end of the packet
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.cursor
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64, PacketError> {
+        self.check_lenght(8)?;
 
-        let n = i64::from_be_bytes(match self.data[self.cursor..self.cursor + 8].try_into() {
-            Ok(t) => t,
-            Err(_e) => return None,
-        });
+        let n = i64::from_be_bytes(self.data[self.cursor..self.cursor + 8].try_into().unwrap());
 
         self.cursor += 8;
-        return Some(n);
+        Ok(n)
     }
 
-    pub fn read_string<'a>(&mut self) -> Option<String> {
-        let string_length = self.read_varint()? as usize;
+    pub fn read_string(&mut self) -> Result<String, PacketError> {
+        let string_length = self.read_length()?;
 
         if string_length == 0 {
-            return Some(String::new());
+            return Ok(String::new());
         }
 
-        if !self.check_lenght(string_length) {
-            return None;
+        //Every character takes up to 4 bytes
+        if string_length > MAX_STRING_LENGTH * 4 {
+            return Err(PacketError::StringTooLong(string_length));
         }
 
+        self.check_lenght(string_length)?;
+
         let vec = self.data[self.cursor..string_length + self.cursor].to_vec();
 
         self.cursor += string_length;
 
-        return match String::from_utf8(vec) {
-            Ok(t) => Some(t),
-            Err(_e) => None,
-        };
+        let string = String::from_utf8(vec).map_err(|_| PacketError::InvalidUtf8)?;
+        let characters = string.chars().count();
+        if characters > MAX_STRING_LENGTH {
+            return Err(PacketError::StringTooLong(characters));
+        }
+        Ok(string)
     }
 
     #[inline]
-    fn check_lenght(&self, lenght: usize) -> bool {
-        if lenght + self.cursor > self.data.len() {
-            return false;
+    fn check_lenght(&self, lenght: usize) -> Result<(), PacketError> {
+        if lenght > self.data.len() - self.cursor {
+            return Err(PacketError::UnexpectedEof);
+        }
+        Ok(())
+    }
+}
+
+//Why a packet couldn't be read, from its frame to its fields
+#[derive(Debug)]
+pub enum PacketError {
+    //Nothing is registered with this id in the connection state
+    UnknownId { id: i32, state: ConnectionState },
+    UnexpectedEof,
+    //More than 5 bytes for a varint, or 10 for a varlong
+    VarIntTooLong,
+    NegativeLength(i32),
+    StringTooLong(usize),
+    InvalidUtf8,
+    InvalidJson,
+    InvalidUuid,
+    //Tag that doesn't match any variant of the enum
+    InvalidEnum { name: &'static str, value: i32 },
+    BadNbt(NBTParseError),
+    //The packet was read but bytes were left
+    TrailingBytes { packet: &'static str, count: usize },
+    FrameTooLong(usize),
+    BadCompression,
+}
+
+impl PacketError {
+    //Shown to the client when it's kicked
+    pub fn reason(&self) -> String {
+        match self {
+            PacketError::UnknownId { id, state } => {
+                format!("Unknown packet 0x{:02X} in the {:?} state", id, state)
+            }
+            PacketError::UnexpectedEof => "Packet ended unexpectedly".to_string(),
+            PacketError::VarIntTooLong => "Malformed VarInt".to_string(),
+            PacketError::NegativeLength(length) => format!("Negative length {}", length),
+            PacketError::StringTooLong(length) => format!("String too long ({})", length),
+            PacketError::InvalidUtf8 => "Invalid UTF-8 string".to_string(),
+            PacketError::InvalidJson => "Invalid JSON".to_string(),
+            PacketError::InvalidUuid => "Invalid UUID".to_string(),
+            PacketError::InvalidEnum { name, value } => format!("Invalid {} {}", name, value),
+            PacketError::BadNbt(e) => format!("Invalid NBT: {}", e.reason()),
+            PacketError::TrailingBytes { packet, count } => {
+                format!("{} bytes left after {}", count, packet)
+            }
+            PacketError::FrameTooLong(length) => format!("Packet frame too long ({})", length),
+            PacketError::BadCompression => "Badly compressed packet".to_string(),
         }
-        return true;
+    }
+}
+
+impl From<NBTParseError> for PacketError {
+    fn from(e: NBTParseError) -> Self {
+        PacketError::BadNbt(e)
     }
 }
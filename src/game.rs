@@ -1,8 +1,18 @@
 pub mod chat;
+pub mod chunk_streaming;
+#[macro_use]
+pub mod codec;
+pub mod command;
+pub mod commands;
 pub mod engine;
+pub mod entity;
+pub mod entity_tracker;
 pub mod game_chat;
 pub mod nbt;
 pub mod packets;
 pub mod player;
 pub mod player_join;
+pub mod player_movement;
+pub mod scheduler;
+pub mod tick;
 pub mod world;
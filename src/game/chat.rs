@@ -1,25 +1,509 @@
 use json::JsonValue;
 
-#[derive(Clone, Debug)]
+//Text component as the client renders it, children inherit the style of their parent
+#[derive(Clone, Debug, PartialEq)]
 pub struct ChatComponent {
-    json: JsonValue,
+    pub content: Content,
+    pub color: Option<Color>,
+    pub bold: Option<bool>,
+    pub italic: Option<bool>,
+    pub underlined: Option<bool>,
+    pub strikethrough: Option<bool>,
+    pub obfuscated: Option<bool>,
+    pub click_event: Option<ClickEvent>,
+    pub hover_event: Option<HoverEvent>,
+    pub extra: Vec<ChatComponent>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Content {
+    Text(String),
+    //Key of the client language file, with the components filling its %s
+    Translate {
+        key: String,
+        with: Vec<ChatComponent>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClickEvent {
+    RunCommand(String),
+    SuggestCommand(String),
+    OpenUrl(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HoverEvent {
+    ShowText(Box<ChatComponent>),
+    //Item in the stringified NBT format, {id:"minecraft:stone",Count:1b}
+    ShowItem(String),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Color {
+    Black,
+    DarkBlue,
+    DarkGreen,
+    DarkAqua,
+    DarkRed,
+    DarkPurple,
+    Gold,
+    Gray,
+    DarkGray,
+    Blue,
+    Green,
+    Aqua,
+    Red,
+    LightPurple,
+    Yellow,
+    White,
+}
+
+//Ordered by legacy code, 0 to f
+const COLORS: [(Color, &str); 16] = [
+    (Color::Black, "black"),
+    (Color::DarkBlue, "dark_blue"),
+    (Color::DarkGreen, "dark_green"),
+    (Color::DarkAqua, "dark_aqua"),
+    (Color::DarkRed, "dark_red"),
+    (Color::DarkPurple, "dark_purple"),
+    (Color::Gold, "gold"),
+    (Color::Gray, "gray"),
+    (Color::DarkGray, "dark_gray"),
+    (Color::Blue, "blue"),
+    (Color::Green, "green"),
+    (Color::Aqua, "aqua"),
+    (Color::Red, "red"),
+    (Color::LightPurple, "light_purple"),
+    (Color::Yellow, "yellow"),
+    (Color::White, "white"),
+];
+
+impl Color {
+    pub fn name(&self) -> &'static str {
+        COLORS[*self as usize].1
+    }
+
+    pub fn from_name(name: &str) -> Option<Color> {
+        COLORS
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(color, _)| *color)
+    }
+
+    pub fn code(&self) -> char {
+        std::char::from_digit(*self as u32, 16).unwrap()
+    }
+
+    pub fn from_code(code: char) -> Option<Color> {
+        let index = code.to_ascii_lowercase().to_digit(16)?;
+        Some(COLORS[index as usize].0)
+    }
 }
 
 impl ChatComponent {
     pub fn new() -> ChatComponent {
+        ChatComponent::text("")
+    }
+    pub fn new_text(str: String) -> ChatComponent {
+        ChatComponent::with_content(Content::Text(str))
+    }
+    pub fn text(text: &str) -> ChatComponent {
+        ChatComponent::new_text(text.to_string())
+    }
+    pub fn translate(key: &str) -> ChatComponent {
+        ChatComponent::with_content(Content::Translate {
+            key: key.to_string(),
+            with: Vec::new(),
+        })
+    }
+
+    fn with_content(content: Content) -> ChatComponent {
         ChatComponent {
-            json: JsonValue::new_object(),
+            content,
+            color: None,
+            bold: None,
+            italic: None,
+            underlined: None,
+            strikethrough: None,
+            obfuscated: None,
+            click_event: None,
+            hover_event: None,
+            extra: Vec::new(),
         }
     }
-    pub fn new_text(str: String) -> ChatComponent {
-        let mut json = JsonValue::new_object();
-        json["text"] = JsonValue::String(str);
-        ChatComponent { json }
+
+    pub fn color(mut self, color: Color) -> Self {
+        self.color = Some(color);
+        self
+    }
+    pub fn click(mut self, event: ClickEvent) -> Self {
+        self.click_event = Some(event);
+        self
+    }
+    pub fn hover(mut self, event: HoverEvent) -> Self {
+        self.hover_event = Some(event);
+        self
+    }
+    pub fn extra(mut self, child: ChatComponent) -> Self {
+        self.extra.push(child);
+        self
     }
+    //Argument of a translated component, ignored by text ones
+    pub fn with(mut self, argument: ChatComponent) -> Self {
+        if let Content::Translate { with, .. } = &mut self.content {
+            with.push(argument);
+        }
+        self
+    }
+
+    //Parses a component sent by the client or read from a file
+    pub fn parse(text: &str) -> Option<ChatComponent> {
+        json::parse(text).ok().map(ChatComponent::from_json)
+    }
+
+    //Strings are plain text and arrays are the first element with the others as its extra, like vanilla
+    pub fn from_json(json: JsonValue) -> ChatComponent {
+        match json {
+            JsonValue::Array(mut elements) => {
+                if elements.is_empty() {
+                    return ChatComponent::new();
+                }
+                let mut component = ChatComponent::from_json(elements.remove(0));
+                for element in elements {
+                    component.extra.push(ChatComponent::from_json(element));
+                }
+                component
+            }
+            JsonValue::Object(_) => {
+                let content = if json["translate"].is_string() {
+                    Content::Translate {
+                        key: json["translate"].to_string(),
+                        with: json["with"]
+                            .members()
+                            .map(|t| ChatComponent::from_json(t.clone()))
+                            .collect(),
+                    }
+                } else if json["text"].is_null() {
+                    Content::Text(String::new())
+                } else {
+                    Content::Text(json["text"].to_string())
+                };
+
+                let mut component = ChatComponent::with_content(content);
+                component.color = json["color"].as_str().and_then(Color::from_name);
+                component.bold = json["bold"].as_bool();
+                component.italic = json["italic"].as_bool();
+                component.underlined = json["underlined"].as_bool();
+                component.strikethrough = json["strikethrough"].as_bool();
+                component.obfuscated = json["obfuscated"].as_bool();
+
+                let click = &json["clickEvent"];
+                let value = click["value"].to_string();
+                component.click_event = match click["action"].as_str() {
+                    Some("run_command") => Some(ClickEvent::RunCommand(value)),
+                    Some("suggest_command") => Some(ClickEvent::SuggestCommand(value)),
+                    Some("open_url") => Some(ClickEvent::OpenUrl(value)),
+                    _ => None,
+                };
+
+                let hover = &json["hoverEvent"];
+                component.hover_event = match hover["action"].as_str() {
+                    Some("show_text") => Some(HoverEvent::ShowText(Box::new(
+                        ChatComponent::from_json(hover["value"].clone()),
+                    ))),
+                    Some("show_item") => Some(HoverEvent::ShowItem(hover["value"].to_string())),
+                    _ => None,
+                };
+
+                component.extra = json["extra"]
+                    .members()
+                    .map(|t| ChatComponent::from_json(t.clone()))
+                    .collect();
+                component
+            }
+            JsonValue::Null => ChatComponent::new(),
+            other => ChatComponent::new_text(other.to_string()),
+        }
+    }
+
     pub fn to_string(&self) -> String {
-        self.json.to_string()
+        self.to_json_ref().dump()
     }
     pub fn to_json(self) -> JsonValue {
-        self.json
+        self.to_json_ref()
+    }
+
+    fn to_json_ref(&self) -> JsonValue {
+        let mut json = JsonValue::new_object();
+        match &self.content {
+            Content::Text(text) => json["text"] = text.as_str().into(),
+            Content::Translate { key, with } => {
+                json["translate"] = key.as_str().into();
+                if !with.is_empty() {
+                    json["with"] = JsonValue::Array(with.iter().map(|t| t.to_json_ref()).collect());
+                }
+            }
+        }
+        if let Some(color) = self.color {
+            json["color"] = color.name().into();
+        }
+        let styles = [
+            ("bold", self.bold),
+            ("italic", self.italic),
+            ("underlined", self.underlined),
+            ("strikethrough", self.strikethrough),
+            ("obfuscated", self.obfuscated),
+        ];
+        for (name, value) in styles.iter() {
+            if let Some(value) = value {
+                json[*name] = (*value).into();
+            }
+        }
+        if let Some(click) = &self.click_event {
+            let (action, value) = match click {
+                ClickEvent::RunCommand(t) => ("run_command", t),
+                ClickEvent::SuggestCommand(t) => ("suggest_command", t),
+                ClickEvent::OpenUrl(t) => ("open_url", t),
+            };
+            let mut event = JsonValue::new_object();
+            event["action"] = action.into();
+            event["value"] = value.as_str().into();
+            json["clickEvent"] = event;
+        }
+        if let Some(hover) = &self.hover_event {
+            let mut event = JsonValue::new_object();
+            match hover {
+                HoverEvent::ShowText(t) => {
+                    event["action"] = "show_text".into();
+                    event["value"] = t.to_json_ref();
+                }
+                HoverEvent::ShowItem(t) => {
+                    event["action"] = "show_item".into();
+                    event["value"] = t.as_str().into();
+                }
+            }
+            json["hoverEvent"] = event;
+        }
+        if !self.extra.is_empty() {
+            json["extra"] = JsonValue::Array(self.extra.iter().map(|t| t.to_json_ref()).collect());
+        }
+        json
+    }
+
+    //Splits a §-coded string in children, a color resets the formats before it like in the client
+    pub fn from_legacy(text: &str) -> ChatComponent {
+        let mut root = ChatComponent::new();
+        let mut current = ChatComponent::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c != '§' {
+                if let Content::Text(text) = &mut current.content {
+                    text.push(c);
+                }
+                continue;
+            }
+
+            let code = match chars.next() {
+                Some(t) => t.to_ascii_lowercase(),
+                None => break,
+            };
+            let mut next = current.clone();
+            next.content = Content::Text(String::new());
+            match code {
+                'k' => next.obfuscated = Some(true),
+                'l' => next.bold = Some(true),
+                'm' => next.strikethrough = Some(true),
+                'n' => next.underlined = Some(true),
+                'o' => next.italic = Some(true),
+                'r' => next = ChatComponent::new(),
+                _ => match Color::from_code(code) {
+                    Some(color) => next = ChatComponent::new().color(color),
+                    None => continue,
+                },
+            }
+
+            if current.content != Content::Text(String::new()) {
+                root.extra.push(current);
+            }
+            current = next;
+        }
+        if current.content != Content::Text(String::new()) {
+            root.extra.push(current);
+        }
+
+        //A single unstyled child is just the text
+        if root.extra.len() == 1 && root.extra[0].is_unstyled() {
+            return root.extra.remove(0);
+        }
+        root
     }
+
+    //Text with § codes, for the console and clients that don't read json. Events are lost
+    //Nothing sends legacy text yet, it's there for plugins talking to old clients
+    #[allow(dead_code)]
+    pub fn to_legacy(&self) -> String {
+        let mut result = String::new();
+        let mut last = Style::default();
+        self.write_legacy(&Style::default(), &mut last, &mut result);
+        result
+    }
+
+    fn write_legacy(&self, parent: &Style, last: &mut Style, result: &mut String) {
+        let style = Style {
+            color: self.color.or(parent.color),
+            bold: self.bold.unwrap_or(parent.bold),
+            italic: self.italic.unwrap_or(parent.italic),
+            underlined: self.underlined.unwrap_or(parent.underlined),
+            strikethrough: self.strikethrough.unwrap_or(parent.strikethrough),
+            obfuscated: self.obfuscated.unwrap_or(parent.obfuscated),
+        };
+
+        let text = match &self.content {
+            Content::Text(text) => text.clone(),
+            Content::Translate { key, with } => {
+                let arguments: Vec<String> = with.iter().map(|t| t.to_legacy()).collect();
+                if arguments.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{} {}", key, arguments.join(" "))
+                }
+            }
+        };
+        if !text.is_empty() {
+            if style != *last {
+                style.write_codes(last, result);
+                *last = style;
+            }
+            result.push_str(&text);
+        }
+
+        for child in self.extra.iter() {
+            child.write_legacy(&style, last, result);
+        }
+    }
+
+    //Text of the component and its children without any style
+    pub fn to_plain_text(&self) -> String {
+        let mut result = match &self.content {
+            Content::Text(text) => text.clone(),
+            Content::Translate { key, .. } => key.clone(),
+        };
+        for child in self.extra.iter() {
+            result.push_str(&child.to_plain_text());
+        }
+        result
+    }
+
+    fn is_unstyled(&self) -> bool {
+        let text = ChatComponent::with_content(self.content.clone());
+        *self == text
+    }
+}
+
+//Formats the server's own messages don't use yet, they're for plugins and chat formatting
+#[allow(dead_code)]
+impl ChatComponent {
+    pub fn bold(mut self, bold: bool) -> Self {
+        self.bold = Some(bold);
+        self
+    }
+    pub fn italic(mut self, italic: bool) -> Self {
+        self.italic = Some(italic);
+        self
+    }
+    pub fn underlined(mut self, underlined: bool) -> Self {
+        self.underlined = Some(underlined);
+        self
+    }
+    pub fn strikethrough(mut self, strikethrough: bool) -> Self {
+        self.strikethrough = Some(strikethrough);
+        self
+    }
+    pub fn obfuscated(mut self, obfuscated: bool) -> Self {
+        self.obfuscated = Some(obfuscated);
+        self
+    }
+}
+
+//Style of a piece of text once inherited from its parents
+#[derive(Copy, Clone, PartialEq, Default)]
+struct Style {
+    color: Option<Color>,
+    bold: bool,
+    italic: bool,
+    underlined: bool,
+    strikethrough: bool,
+    obfuscated: bool,
+}
+
+impl Style {
+    //Codes changing the last style into this one, formats can only be removed with a color or a reset
+    fn write_codes(&self, last: &Style, result: &mut String) {
+        let removed = (last.bold && !self.bold)
+            || (last.italic && !self.italic)
+            || (last.underlined && !self.underlined)
+            || (last.strikethrough && !self.strikethrough)
+            || (last.obfuscated && !self.obfuscated);
+        let reset = removed || self.color != last.color;
+        if reset {
+            result.push('§');
+            result.push(self.color.map(|t| t.code()).unwrap_or('r'));
+        }
+
+        let formats = [
+            (self.obfuscated, last.obfuscated, 'k'),
+            (self.bold, last.bold, 'l'),
+            (self.strikethrough, last.strikethrough, 'm'),
+            (self.underlined, last.underlined, 'n'),
+            (self.italic, last.italic, 'o'),
+        ];
+        for (now, before, code) in formats.iter() {
+            if *now && (reset || !*before) {
+                result.push('§');
+                result.push(*code);
+            }
+        }
+    }
+}
+
+#[test]
+fn builds_and_parses_json() {
+    let component = ChatComponent::text("Hello ")
+        .color(Color::Gold)
+        .bold(true)
+        .extra(
+            ChatComponent::text("world")
+                .click(ClickEvent::RunCommand("/spawn".to_string()))
+                .hover(HoverEvent::ShowText(Box::new(ChatComponent::text("Go")))),
+        );
+    let json = component.to_string();
+    assert_eq!(
+        json,
+        r#"{"text":"Hello ","color":"gold","bold":true,"extra":[{"text":"world","clickEvent":{"action":"run_command","value":"/spawn"},"hoverEvent":{"action":"show_text","value":{"text":"Go"}}}]}"#
+    );
+    assert_eq!(ChatComponent::parse(&json).unwrap(), component);
+
+    let translated =
+        ChatComponent::parse(r#"["",{"translate":"chat.type.text","with":["Steve","hi"]}]"#);
+    assert_eq!(
+        translated.unwrap().extra[0].to_plain_text(),
+        "chat.type.text"
+    );
+}
+
+#[test]
+fn converts_legacy_codes() {
+    let component = ChatComponent::from_legacy("§ePlain §lbold§r reset §cred");
+    assert_eq!(component.extra.len(), 4);
+    assert_eq!(component.extra[1].color, Some(Color::Yellow));
+    assert_eq!(component.extra[1].bold, Some(true));
+    assert_eq!(component.extra[3].color, Some(Color::Red));
+    assert_eq!(component.to_plain_text(), "Plain bold reset red");
+    assert_eq!(component.to_legacy(), "§ePlain §lbold§r reset §cred");
+
+    assert_eq!(
+        ChatComponent::from_legacy("no codes"),
+        ChatComponent::text("no codes")
+    );
 }
@@ -0,0 +1,196 @@
+use crate::config::ServerConfig;
+use crate::game::engine::SyncEnvironment;
+use crate::game::packets::{ExtendedPacket, Packet};
+use crate::game::player::Player;
+use crate::game::world::chunk::ChunkPos;
+use crate::game::world::world::World;
+use crate::net::network_manager::NetWriter;
+use std::collections::HashSet;
+
+//Vanilla sends at most 10 chunks in each MapChunkBulk
+const MAX_CHUNKS_PER_BULK: usize = 10;
+//Clients ask for 2 to 16 in the settings, and are never sent more than the config's
+const MIN_VIEW_DISTANCE: u8 = 2;
+
+//Chunks a player has loaded, the square of view distance chunks around the one it's in
+pub struct ChunkView {
+    pub center: Option<ChunkPos>,
+    pub view_distance: u8,
+    //From the client settings, the config's is used until they arrive
+    pub requested_view_distance: Option<u8>,
+    pub loaded: HashSet<ChunkPos>,
+}
+
+impl ChunkView {
+    pub fn new() -> Self {
+        ChunkView {
+            center: None,
+            view_distance: 0,
+            requested_view_distance: None,
+            loaded: HashSet::new(),
+        }
+    }
+}
+
+//Sends and unloads chunks for the players that crossed a chunk border or changed their view distance
+pub fn tick(net_writer: &NetWriter, environment: &mut SyncEnvironment) {
+    for player in environment.players.iter_mut() {
+        update_view(player, environment.world, environment.config, net_writer);
+    }
+}
+
+pub fn update_view(
+    player: &mut Player,
+    world: &World,
+    config: &ServerConfig,
+    net_writer: &NetWriter,
+) {
+    let center = ChunkPos::from_block_coords(
+        player.position.x.floor() as i32,
+        player.position.z.floor() as i32,
+    );
+    let view = &mut player.chunk_view;
+    let view_distance = view
+        .requested_view_distance
+        .unwrap_or(config.view_distance)
+        .min(config.view_distance)
+        .max(MIN_VIEW_DISTANCE);
+    if view.center == Some(center) && view.view_distance == view_distance {
+        return;
+    }
+    view.center = Some(center);
+    view.view_distance = view_distance;
+
+    let radius = view_distance as i32;
+    let mut visible = HashSet::new();
+    for x in center.x - radius..=center.x + radius {
+        for z in center.z - radius..=center.z + radius {
+            visible.insert(ChunkPos { x, z });
+        }
+    }
+
+    let mut unloaded: Vec<ChunkPos> = view.loaded.difference(&visible).copied().collect();
+    unloaded.sort_by_key(|pos| (pos.x, pos.z));
+    for pos in unloaded {
+        view.loaded.remove(&pos);
+        //A ground up chunk without sections is an unload
+        net_writer.send_extended_packet(
+            player.token,
+            ExtendedPacket::ChunkData {
+                x: pos.x,
+                y: pos.z,
+                ground_up_continuous: true,
+                bitmask: 0,
+                data: Vec::new(),
+            },
+        );
+    }
+
+    //Closest first, chunks that don't exist in the world aren't sent
+    let mut entering: Vec<ChunkPos> = visible
+        .into_iter()
+        .filter(|pos| !view.loaded.contains(pos) && world.chunks.contains_key(pos))
+        .collect();
+    entering.sort_by_key(|pos| {
+        (
+            (pos.x - center.x).abs().max((pos.z - center.z).abs()),
+            pos.x,
+            pos.z,
+        )
+    });
+    for batch in entering.chunks(MAX_CHUNKS_PER_BULK) {
+        net_writer.send_extended_packet(player.token, world.get_map_bulk_packet(batch));
+    }
+    view.loaded.extend(entering);
+}
+
+//The view distance the client wants, from its settings
+pub fn settings_listener(
+    packet: &Packet,
+    player_index: usize,
+    _net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) {
+    if let Packet::ClientSettings { view_distance, .. } = packet {
+        environment.players[player_index]
+            .chunk_view
+            .requested_view_distance = Some((*view_distance).max(0) as u8);
+    }
+}
+
+#[test]
+fn streams_chunks_around_the_player() {
+    use crate::game::engine::TestEnvironment;
+    use crate::game::world::coords::Point;
+    use crate::net::network_manager::NetProtocol;
+    use std::sync::mpsc::channel;
+
+    let mut test = TestEnvironment::new(ServerConfig {
+        view_distance: 2,
+        ..Default::default()
+    });
+    for x in -10..10 {
+        for z in -10..10 {
+            test.world.allocate_chunk(ChunkPos { x, z });
+        }
+    }
+    test.players.push(Player::test(
+        mio::Token(1),
+        Point {
+            x: 0.5,
+            y: 64.0,
+            z: 0.5,
+        },
+    ));
+    let (world, config) = (&test.world, test.config);
+    let player = &mut test.players[0];
+    let (writer, reader) = channel();
+    let net_writer = NetWriter { writer };
+
+    //Chunks in each bulk and unloaded chunks sent by an update
+    let update = |player: &mut Player| {
+        update_view(player, world, config, &net_writer);
+        let mut bulks = Vec::new();
+        let mut unloaded = Vec::new();
+        for message in reader.try_iter() {
+            match message {
+                NetProtocol::SendExtendedPacket {
+                    packet: ExtendedPacket::MapChunkBulk { chunks, .. },
+                    ..
+                } => bulks.push(chunks.len()),
+                NetProtocol::SendExtendedPacket {
+                    packet:
+                        ExtendedPacket::ChunkData {
+                            x, y, bitmask: 0, ..
+                        },
+                    ..
+                } => unloaded.push((x, y)),
+                _ => panic!("Unexpected message"),
+            }
+        }
+        (bulks, unloaded)
+    };
+
+    assert_eq!(update(player), (vec![10, 10, 5], vec![]));
+    assert_eq!(player.chunk_view.loaded.len(), 25);
+    //Moving inside the chunk sends nothing
+    player.position.x = 15.0;
+    assert_eq!(update(player), (vec![], vec![]));
+
+    //One chunk east, a column comes in and one goes out
+    player.position.x = 16.5;
+    let unloaded = (-2..=2).map(|z| (-2, z)).collect::<Vec<_>>();
+    assert_eq!(update(player), (vec![5], unloaded));
+
+    //The view distance stays between 2 and the config's, whatever the client asks for
+    player.chunk_view.requested_view_distance = Some(16);
+    assert_eq!(update(player), (vec![], vec![]));
+    player.chunk_view.requested_view_distance = Some(0);
+    assert_eq!(update(player), (vec![], vec![]));
+    assert_eq!(player.chunk_view.view_distance, 2);
+
+    //At the edge of the world only the chunks that exist are sent
+    player.position.x = 9.0 * 16.0;
+    update(player);
+    assert_eq!(player.chunk_view.loaded.len(), 15);
+}
@@ -0,0 +1,500 @@
+use crate::data_reader::{DataReader, PacketError};
+use crate::data_writer::DataWriter;
+use crate::game::chat::ChatComponent;
+use crate::game::nbt::NBTTag;
+use crate::game::world::angle::Angle;
+use crate::game::world::chunk::ChunkPos;
+use crate::game::world::coords::{Point, Position};
+use json::JsonValue;
+use std::marker::PhantomData;
+use uuid::Uuid;
+
+//How a packet field goes on the wire. The same type can be encoded in different ways (an i32 can be
+//an Int or a VarInt, a Vec can be prefixed by a varint or a short...), so the codec is a marker type
+//apart from the field type, picked with `field: Type as Codec` in the packet tables
+pub trait Codec<T> {
+    fn read(reader: &mut DataReader) -> Result<T, PacketError>;
+    fn write(value: &T, writer: &mut DataWriter);
+}
+
+//The natural encoding of the type, used when a field doesn't name a codec
+pub struct Plain;
+pub struct VarInt;
+pub struct VarLong;
+//Varint prefixed byte array
+pub struct Bytes;
+//Every byte until the end of the packet
+pub struct RemainingBytes;
+//Lists prefixed by their length as a varint, an i32 and an i16, each element encoded with C
+pub struct VarIntArray<C = Plain>(PhantomData<C>);
+pub struct IntArray<C = Plain>(PhantomData<C>);
+pub struct ShortArray<C = Plain>(PhantomData<C>);
+//Elements encoded with C until the end of the packet
+pub struct RemainingArray<C = Plain>(PhantomData<C>);
+//Boolean telling if the value follows
+pub struct Optional<C = Plain>(PhantomData<C>);
+//Value that is only sent in some cases and always at the end of the packet, present if there are bytes left
+pub struct Trailing<C = Plain>(PhantomData<C>);
+//Coordinates as i32 multiplied by 32
+pub struct FixedPoint;
+//Angle with the pitch first, only the spawn object packet does it
+pub struct PitchYaw;
+//Uuid as a hyphenated string, like in the login success
+pub struct HyphenatedUuid;
+//Optional NBT compound, a single TAG_End means there's none
+pub struct OptionalNbt;
+
+//Picks Plain when a field doesn't name its codec
+macro_rules! field_codec {
+    ($ty:ty) => {
+        $crate::game::codec::Plain
+    };
+    ($ty:ty, $codec:ty) => {
+        $codec
+    };
+}
+
+//Declares structs that appear inside packets, with the Plain codec reading and writing the fields in order
+macro_rules! packet_structs {
+    ($(
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $(pub $field:ident: $ty:ty $(as $codec:ty)?),* $(,)?
+        }
+    )*) => {
+        $(
+            $(#[$meta])*
+            pub struct $name {
+                $(pub $field: $ty),*
+            }
+
+            impl $crate::game::codec::Codec<$name> for $crate::game::codec::Plain {
+                fn read(reader: &mut $crate::data_reader::DataReader) -> Result<$name, $crate::data_reader::PacketError> {
+                    Ok($name {
+                        $($field: <field_codec!($ty $(, $codec)?) as $crate::game::codec::Codec<$ty>>::read(reader)?),*
+                    })
+                }
+
+                fn write(value: &$name, writer: &mut $crate::data_writer::DataWriter) {
+                    $(<field_codec!($ty $(, $codec)?) as $crate::game::codec::Codec<$ty>>::write(&value.$field, writer);)*
+                }
+            }
+        )*
+    };
+}
+
+//Declares enums that appear inside packets, where a tag tells which variant follows
+//Besides the Plain codec (tag and then the fields) it generates tag, read_body and write_body,
+//for the packets that keep the tag apart from the fields
+macro_rules! packet_enums {
+    ($(
+        $(#[$meta:meta])*
+        pub enum $name:ident ($tag_ty:ty $(as $tag_codec:ty)?) {
+            $($tag:literal => $variant:ident $({
+                $($field:ident: $ty:ty $(as $codec:ty)?),* $(,)?
+            })?),* $(,)?
+        }
+    )*) => {
+        $(
+            $(#[$meta])*
+            pub enum $name {
+                $($variant $({ $($field: $ty),* })?),*
+            }
+
+            impl $name {
+                pub fn tag(&self) -> $tag_ty {
+                    match self {
+                        $($name::$variant { .. } => $tag),*
+                    }
+                }
+
+                pub fn read_body(tag: $tag_ty, reader: &mut $crate::data_reader::DataReader) -> Result<$name, $crate::data_reader::PacketError> {
+                    match tag {
+                        $($tag => Ok($name::$variant $({
+                            $($field: <field_codec!($ty $(, $codec)?) as $crate::game::codec::Codec<$ty>>::read(reader)?),*
+                        })?),)*
+                        _ => Err($crate::data_reader::PacketError::InvalidEnum {
+                            name: stringify!($name),
+                            value: tag as i32,
+                        }),
+                    }
+                }
+
+                pub fn write_body(&self, writer: &mut $crate::data_writer::DataWriter) {
+                    match self {
+                        $($name::$variant $({ $($field),* })? => {
+                            $($(<field_codec!($ty $(, $codec)?) as $crate::game::codec::Codec<$ty>>::write($field, writer);)*)?
+                        })*
+                    }
+                }
+            }
+
+            impl $crate::game::codec::Codec<$name> for $crate::game::codec::Plain {
+                fn read(reader: &mut $crate::data_reader::DataReader) -> Result<$name, $crate::data_reader::PacketError> {
+                    let tag = <field_codec!($tag_ty $(, $tag_codec)?) as $crate::game::codec::Codec<$tag_ty>>::read(reader)?;
+                    $name::read_body(tag, reader)
+                }
+
+                fn write(value: &$name, writer: &mut $crate::data_writer::DataWriter) {
+                    <field_codec!($tag_ty $(, $tag_codec)?) as $crate::game::codec::Codec<$tag_ty>>::write(&value.tag(), writer);
+                    value.write_body(writer);
+                }
+            }
+        )*
+    };
+}
+
+impl Codec<bool> for Plain {
+    fn read(reader: &mut DataReader) -> Result<bool, PacketError> {
+        reader.read_bool()
+    }
+    fn write(value: &bool, writer: &mut DataWriter) {
+        writer.write_bool(*value)
+    }
+}
+
+impl Codec<u8> for Plain {
+    fn read(reader: &mut DataReader) -> Result<u8, PacketError> {
+        reader.read_u8()
+    }
+    fn write(value: &u8, writer: &mut DataWriter) {
+        writer.write_u8(*value)
+    }
+}
+
+impl Codec<i8> for Plain {
+    fn read(reader: &mut DataReader) -> Result<i8, PacketError> {
+        reader.read_i8()
+    }
+    fn write(value: &i8, writer: &mut DataWriter) {
+        writer.write_i8(*value)
+    }
+}
+
+impl Codec<u16> for Plain {
+    fn read(reader: &mut DataReader) -> Result<u16, PacketError> {
+        reader.read_u16()
+    }
+    fn write(value: &u16, writer: &mut DataWriter) {
+        writer.write_u16(*value)
+    }
+}
+
+impl Codec<i16> for Plain {
+    fn read(reader: &mut DataReader) -> Result<i16, PacketError> {
+        reader.read_i16()
+    }
+    fn write(value: &i16, writer: &mut DataWriter) {
+        writer.write_i16(*value)
+    }
+}
+
+impl Codec<i32> for Plain {
+    fn read(reader: &mut DataReader) -> Result<i32, PacketError> {
+        reader.read_i32()
+    }
+    fn write(value: &i32, writer: &mut DataWriter) {
+        writer.write_i32(*value)
+    }
+}
+
+impl Codec<i64> for Plain {
+    fn read(reader: &mut DataReader) -> Result<i64, PacketError> {
+        reader.read_i64()
+    }
+    fn write(value: &i64, writer: &mut DataWriter) {
+        writer.write_i64(*value)
+    }
+}
+
+impl Codec<f32> for Plain {
+    fn read(reader: &mut DataReader) -> Result<f32, PacketError> {
+        reader.read_f32()
+    }
+    fn write(value: &f32, writer: &mut DataWriter) {
+        writer.write_f32(*value)
+    }
+}
+
+impl Codec<f64> for Plain {
+    fn read(reader: &mut DataReader) -> Result<f64, PacketError> {
+        reader.read_f64()
+    }
+    fn write(value: &f64, writer: &mut DataWriter) {
+        writer.write_f64(*value)
+    }
+}
+
+impl Codec<String> for Plain {
+    fn read(reader: &mut DataReader) -> Result<String, PacketError> {
+        reader.read_string()
+    }
+    fn write(value: &String, writer: &mut DataWriter) {
+        writer.write_string(value)
+    }
+}
+
+impl Codec<Uuid> for Plain {
+    fn read(reader: &mut DataReader) -> Result<Uuid, PacketError> {
+        reader.read_uuid()
+    }
+    fn write(value: &Uuid, writer: &mut DataWriter) {
+        writer.write_data(value.as_bytes())
+    }
+}
+
+impl Codec<Position> for Plain {
+    fn read(reader: &mut DataReader) -> Result<Position, PacketError> {
+        reader.read_position()
+    }
+    fn write(value: &Position, writer: &mut DataWriter) {
+        writer.write_position(value)
+    }
+}
+
+impl Codec<ChunkPos> for Plain {
+    fn read(reader: &mut DataReader) -> Result<ChunkPos, PacketError> {
+        let x = reader.read_i32()?;
+        let z = reader.read_i32()?;
+        Ok(ChunkPos { x, z })
+    }
+    fn write(value: &ChunkPos, writer: &mut DataWriter) {
+        writer.write_i32(value.x);
+        writer.write_i32(value.z);
+    }
+}
+
+//Yaw and then pitch, the order almost every packet uses
+impl Codec<Angle> for Plain {
+    fn read(reader: &mut DataReader) -> Result<Angle, PacketError> {
+        let yaw = reader.read_u8()?;
+        let pitch = reader.read_u8()?;
+        Ok(Angle { pitch, yaw })
+    }
+    fn write(value: &Angle, writer: &mut DataWriter) {
+        writer.write_u8(value.yaw);
+        writer.write_u8(value.pitch);
+    }
+}
+
+impl Codec<JsonValue> for Plain {
+    fn read(reader: &mut DataReader) -> Result<JsonValue, PacketError> {
+        json::parse(&reader.read_string()?).map_err(|_| PacketError::InvalidJson)
+    }
+    fn write(value: &JsonValue, writer: &mut DataWriter) {
+        writer.write_string(&value.to_string())
+    }
+}
+
+impl Codec<ChatComponent> for Plain {
+    fn read(reader: &mut DataReader) -> Result<ChatComponent, PacketError> {
+        ChatComponent::parse(&reader.read_string()?).ok_or(PacketError::InvalidJson)
+    }
+    fn write(value: &ChatComponent, writer: &mut DataWriter) {
+        writer.write_string(&value.to_string())
+    }
+}
+
+//Named root compound
+impl Codec<NBTTag> for Plain {
+    fn read(reader: &mut DataReader) -> Result<NBTTag, PacketError> {
+        let mut data = &reader.data[reader.cursor..];
+        let length = data.len();
+        let (tag, _name) = NBTTag::read(&mut data, true, None)?;
+        reader.cursor += length - data.len();
+        Ok(tag)
+    }
+    fn write(value: &NBTTag, writer: &mut DataWriter) {
+        value.write(&mut writer.data, Some(&String::new()), true)
+    }
+}
+
+impl Codec<i32> for VarInt {
+    fn read(reader: &mut DataReader) -> Result<i32, PacketError> {
+        reader.read_varint()
+    }
+    fn write(value: &i32, writer: &mut DataWriter) {
+        writer.write_varint(*value)
+    }
+}
+
+impl Codec<i64> for VarLong {
+    fn read(reader: &mut DataReader) -> Result<i64, PacketError> {
+        reader.read_varlong()
+    }
+    fn write(value: &i64, writer: &mut DataWriter) {
+        writer.write_varlong(*value)
+    }
+}
+
+impl Codec<Vec<u8>> for Bytes {
+    fn read(reader: &mut DataReader) -> Result<Vec<u8>, PacketError> {
+        reader.read_data()
+    }
+    fn write(value: &Vec<u8>, writer: &mut DataWriter) {
+        writer.write_varint(value.len() as i32);
+        writer.write_data(value);
+    }
+}
+
+impl Codec<Vec<u8>> for RemainingBytes {
+    fn read(reader: &mut DataReader) -> Result<Vec<u8>, PacketError> {
+        reader.read_data_fixed(reader.remaining())
+    }
+    fn write(value: &Vec<u8>, writer: &mut DataWriter) {
+        writer.write_data(value)
+    }
+}
+
+//The length is only trusted as far as there are elements to read, nothing is allocated up front
+fn read_elements<T, C: Codec<T>>(reader: &mut DataReader, length: i32) -> Result<Vec<T>, PacketError> {
+    if length < 0 {
+        return Err(PacketError::NegativeLength(length));
+    }
+    let mut vec = Vec::new();
+    for _ in 0..length {
+        vec.push(C::read(reader)?);
+    }
+    Ok(vec)
+}
+
+impl<T, C: Codec<T>> Codec<Vec<T>> for VarIntArray<C> {
+    fn read(reader: &mut DataReader) -> Result<Vec<T>, PacketError> {
+        let length = reader.read_varint()?;
+        read_elements::<T, C>(reader, length)
+    }
+    fn write(value: &Vec<T>, writer: &mut DataWriter) {
+        writer.write_varint(value.len() as i32);
+        value.iter().for_each(|x| C::write(x, writer));
+    }
+}
+
+impl<T, C: Codec<T>> Codec<Vec<T>> for IntArray<C> {
+    fn read(reader: &mut DataReader) -> Result<Vec<T>, PacketError> {
+        let length = reader.read_i32()?;
+        read_elements::<T, C>(reader, length)
+    }
+    fn write(value: &Vec<T>, writer: &mut DataWriter) {
+        writer.write_i32(value.len() as i32);
+        value.iter().for_each(|x| C::write(x, writer));
+    }
+}
+
+impl<T, C: Codec<T>> Codec<Vec<T>> for ShortArray<C> {
+    fn read(reader: &mut DataReader) -> Result<Vec<T>, PacketError> {
+        let length = reader.read_i16()?;
+        read_elements::<T, C>(reader, length as i32)
+    }
+    fn write(value: &Vec<T>, writer: &mut DataWriter) {
+        writer.write_i16(value.len() as i16);
+        value.iter().for_each(|x| C::write(x, writer));
+    }
+}
+
+impl<T, C: Codec<T>> Codec<Vec<T>> for RemainingArray<C> {
+    fn read(reader: &mut DataReader) -> Result<Vec<T>, PacketError> {
+        let mut vec = Vec::new();
+        while reader.remaining() > 0 {
+            vec.push(C::read(reader)?);
+        }
+        Ok(vec)
+    }
+    fn write(value: &Vec<T>, writer: &mut DataWriter) {
+        value.iter().for_each(|x| C::write(x, writer));
+    }
+}
+
+impl<T, C: Codec<T>> Codec<Option<T>> for Optional<C> {
+    fn read(reader: &mut DataReader) -> Result<Option<T>, PacketError> {
+        if reader.read_bool()? {
+            Ok(Some(C::read(reader)?))
+        } else {
+            Ok(None)
+        }
+    }
+    fn write(value: &Option<T>, writer: &mut DataWriter) {
+        writer.write_bool(value.is_some());
+        if let Some(value) = value {
+            C::write(value, writer);
+        }
+    }
+}
+
+impl<T, C: Codec<T>> Codec<Option<T>> for Trailing<C> {
+    fn read(reader: &mut DataReader) -> Result<Option<T>, PacketError> {
+        if reader.remaining() == 0 {
+            return Ok(None);
+        }
+        Ok(Some(C::read(reader)?))
+    }
+    fn write(value: &Option<T>, writer: &mut DataWriter) {
+        if let Some(value) = value {
+            C::write(value, writer);
+        }
+    }
+}
+
+//Boxed fields keep the packets with big ones from growing every other packet
+impl<T> Codec<Box<T>> for Plain
+where
+    Plain: Codec<T>,
+{
+    fn read(reader: &mut DataReader) -> Result<Box<T>, PacketError> {
+        Ok(Box::new(<Plain as Codec<T>>::read(reader)?))
+    }
+    fn write(value: &Box<T>, writer: &mut DataWriter) {
+        <Plain as Codec<T>>::write(value, writer)
+    }
+}
+
+impl Codec<Point> for FixedPoint {
+    fn read(reader: &mut DataReader) -> Result<Point, PacketError> {
+        Ok(Point {
+            x: reader.read_i32()? as f64 / 32f64,
+            y: reader.read_i32()? as f64 / 32f64,
+            z: reader.read_i32()? as f64 / 32f64,
+        })
+    }
+    fn write(value: &Point, writer: &mut DataWriter) {
+        writer.write_i32(value.absolute_x());
+        writer.write_i32(value.absolute_y());
+        writer.write_i32(value.absolute_z());
+    }
+}
+
+impl Codec<Angle> for PitchYaw {
+    fn read(reader: &mut DataReader) -> Result<Angle, PacketError> {
+        let pitch = reader.read_u8()?;
+        let yaw = reader.read_u8()?;
+        Ok(Angle { pitch, yaw })
+    }
+    fn write(value: &Angle, writer: &mut DataWriter) {
+        writer.write_u8(value.pitch);
+        writer.write_u8(value.yaw);
+    }
+}
+
+impl Codec<Uuid> for HyphenatedUuid {
+    fn read(reader: &mut DataReader) -> Result<Uuid, PacketError> {
+        Uuid::parse_str(&reader.read_string()?).map_err(|_| PacketError::InvalidUuid)
+    }
+    fn write(value: &Uuid, writer: &mut DataWriter) {
+        writer.write_string(&value.to_hyphenated().to_string())
+    }
+}
+
+impl Codec<Option<NBTTag>> for OptionalNbt {
+    fn read(reader: &mut DataReader) -> Result<Option<NBTTag>, PacketError> {
+        if reader.read_u8()? == 0 {
+            return Ok(None);
+        }
+        reader.cursor -= 1;
+        Ok(Some(<Plain as Codec<NBTTag>>::read(reader)?))
+    }
+    fn write(value: &Option<NBTTag>, writer: &mut DataWriter) {
+        match value {
+            Some(tag) => <Plain as Codec<NBTTag>>::write(tag, writer),
+            None => writer.write_u8(0),
+        }
+    }
+}
@@ -0,0 +1,538 @@
+use crate::game::chat::{ChatComponent, Color};
+use crate::game::engine::SyncEnvironment;
+use crate::game::packets::Packet;
+use crate::game::world::block::{Block, Material, MATERIAL_NAMES};
+use crate::game::world::coords::Point;
+use crate::net::network_manager::NetWriter;
+
+//Who runs a command, players are referenced by their index in the player list
+#[derive(Copy, Clone)]
+pub enum CommandSender {
+    Player(usize),
+    Console,
+}
+
+//Level ops and the console get, vanilla's default op-permission-level
+pub const OP_PERMISSION_LEVEL: u8 = 4;
+
+impl CommandSender {
+    pub fn permission_level(&self, environment: &SyncEnvironment) -> u8 {
+        match self {
+            CommandSender::Player(index) => {
+                if environment
+                    .config
+                    .is_op(&environment.players[*index].nickname)
+                {
+                    OP_PERMISSION_LEVEL
+                } else {
+                    0
+                }
+            }
+            CommandSender::Console => OP_PERMISSION_LEVEL,
+        }
+    }
+}
+
+//What a parameter accepts, used both to parse it and to complete it
+#[derive(Copy, Clone)]
+pub enum ArgumentKind {
+    //Name of an online player
+    Player,
+    Integer,
+    //Three words, each a number or ~ followed by an optional offset
+    Coordinates,
+    //Block name, with or without minecraft:, or its id
+    Block,
+    //Everything left in the line
+    Text,
+    //One of the listed words
+    Literal(&'static [&'static str]),
+}
+
+pub struct Parameter {
+    pub name: &'static str,
+    pub kind: ArgumentKind,
+    pub optional: bool,
+}
+
+impl Parameter {
+    pub const fn required(name: &'static str, kind: ArgumentKind) -> Parameter {
+        Parameter {
+            name,
+            kind,
+            optional: false,
+        }
+    }
+
+    pub const fn optional(name: &'static str, kind: ArgumentKind) -> Parameter {
+        Parameter {
+            name,
+            kind,
+            optional: true,
+        }
+    }
+}
+
+pub enum Argument {
+    Player(usize),
+    Integer(i32),
+    Coordinates(Coordinates),
+    Block(Block),
+    Text(String),
+    Literal(&'static str),
+}
+
+//Coordinates as typed, the relative ones are resolved against a player by the command
+pub struct Coordinates {
+    pub values: [f64; 3],
+    pub relative: [bool; 3],
+}
+
+impl Coordinates {
+    pub fn resolve(&self, base: Option<&Point>) -> Result<Point, String> {
+        let origin = match base {
+            Some(t) => [t.x, t.y, t.z],
+            None if self.relative.iter().any(|t| *t) => {
+                return Err("Relative coordinates need a player".to_string())
+            }
+            None => [0.0; 3],
+        };
+        let mut point = [0.0; 3];
+        for i in 0..3 {
+            point[i] = if self.relative[i] {
+                origin[i] + self.values[i]
+            } else {
+                self.values[i]
+            };
+        }
+        Ok(Point {
+            x: point[0],
+            y: point[1],
+            z: point[2],
+        })
+    }
+}
+
+//Returns a message for the sender when the command can't be done
+pub type CommandExecutor =
+    fn(&[Argument], CommandSender, &NetWriter, &mut SyncEnvironment) -> Result<(), String>;
+
+pub struct Command {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub description: &'static str,
+    //Lowest level the sender needs to run it, 0 lets every player run it
+    pub permission_level: u8,
+    //Each syntax is tried in order and the first one that parses runs the executor,
+    //which tells them apart by the arguments it gets
+    pub syntaxes: &'static [&'static [Parameter]],
+    pub executor: CommandExecutor,
+}
+
+impl Command {
+    pub fn usage(&self) -> Vec<String> {
+        self.syntaxes
+            .iter()
+            .map(|syntax| {
+                let mut usage = format!("/{}", self.name);
+                for parameter in syntax.iter() {
+                    let name = match parameter.kind {
+                        ArgumentKind::Coordinates => "x> <y> <z".to_string(),
+                        ArgumentKind::Literal(options) => options.join("|"),
+                        _ => parameter.name.to_string(),
+                    };
+                    if parameter.optional {
+                        usage.push_str(&format!(" [{}]", name));
+                    } else {
+                        usage.push_str(&format!(" <{}>", name));
+                    }
+                }
+                usage
+            })
+            .collect()
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self.aliases.iter().any(|t| t.eq_ignore_ascii_case(name))
+    }
+}
+
+pub struct CommandRegistry {
+    pub commands: Vec<Command>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        CommandRegistry {
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn register(&mut self, command: Command) {
+        if self.find(command.name).is_some() {
+            log!("Command /{} is registered twice, ignoring it", command.name);
+            return;
+        }
+        self.commands.push(command);
+    }
+
+    //Looks the command up by its name or one of its aliases
+    pub fn find(&self, name: &str) -> Option<&Command> {
+        self.commands.iter().find(|command| command.matches(name))
+    }
+}
+
+//Runs a command line without its /
+pub fn execute(
+    line: &str,
+    sender: CommandSender,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let name = match words.first() {
+        Some(t) => *t,
+        None => return,
+    };
+    let commands = environment.commands;
+    let command = match commands.find(name) {
+        Some(t) => t,
+        None => {
+            send_error(
+                "Unknown command. Type /help for a list of commands".to_string(),
+                sender,
+                net_writer,
+                environment,
+            );
+            return;
+        }
+    };
+
+    if sender.permission_level(environment) < command.permission_level {
+        send_error(
+            "You do not have permission to use this command".to_string(),
+            sender,
+            net_writer,
+            environment,
+        );
+        return;
+    }
+
+    let mut error = None;
+    for syntax in command.syntaxes.iter() {
+        match parse_syntax(syntax, &words[1..], environment) {
+            Ok(arguments) => {
+                if let Err(e) = (command.executor)(&arguments, sender, net_writer, environment) {
+                    send_error(e, sender, net_writer, environment);
+                }
+                return;
+            }
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+    }
+
+    //With more than one syntax the first error may not be the one the sender meant
+    if command.syntaxes.len() == 1 {
+        send_error(error.unwrap(), sender, net_writer, environment);
+    }
+    for usage in command.usage() {
+        send_error(format!("Usage: {}", usage), sender, net_writer, environment);
+    }
+}
+
+fn parse_syntax(
+    syntax: &[Parameter],
+    words: &[&str],
+    environment: &SyncEnvironment,
+) -> Result<Vec<Argument>, String> {
+    let mut arguments = Vec::with_capacity(syntax.len());
+    let mut index = 0;
+    for parameter in syntax.iter() {
+        if index >= words.len() {
+            if parameter.optional {
+                break;
+            }
+            return Err(format!("Missing {}", parameter.name));
+        }
+
+        let argument = match parameter.kind {
+            ArgumentKind::Player => {
+                let name = words[index];
+                let player = environment
+                    .players
+                    .iter()
+                    .position(|player| player.nickname.eq_ignore_ascii_case(name));
+                match player {
+                    Some(t) => Argument::Player(t),
+                    None => return Err(format!("Player {} not found", name)),
+                }
+            }
+            ArgumentKind::Integer => match words[index].parse() {
+                Ok(t) => Argument::Integer(t),
+                Err(_) => return Err(format!("Invalid number: {}", words[index])),
+            },
+            ArgumentKind::Coordinates => {
+                if index + 3 > words.len() {
+                    return Err("Missing coordinates".to_string());
+                }
+                let mut coordinates = Coordinates {
+                    values: [0.0; 3],
+                    relative: [false; 3],
+                };
+                for i in 0..3 {
+                    let word = words[index + i];
+                    let (relative, number) = match word.strip_prefix('~') {
+                        Some(t) => (true, t),
+                        None => (false, word),
+                    };
+                    coordinates.relative[i] = relative;
+                    coordinates.values[i] = match number {
+                        "" if relative => 0.0,
+                        //Rust parses NaN and inf too, which no position can hold
+                        _ => match number.parse::<f64>() {
+                            Ok(t) if t.is_finite() => t,
+                            _ => return Err(format!("Invalid coordinate: {}", word)),
+                        },
+                    };
+                }
+                index += 2;
+                Argument::Coordinates(coordinates)
+            }
+            ArgumentKind::Block => match Material::from_name(words[index]) {
+                Some(t) => Argument::Block(Block::from_material(t)),
+                None => return Err(format!("Unknown block: {}", words[index])),
+            },
+            ArgumentKind::Text => {
+                let text = words[index..].join(" ");
+                index = words.len() - 1;
+                Argument::Text(text)
+            }
+            ArgumentKind::Literal(options) => {
+                match options
+                    .iter()
+                    .find(|t| t.eq_ignore_ascii_case(words[index]))
+                {
+                    Some(t) => Argument::Literal(t),
+                    None => return Err(format!("Expected {}", options.join(", "))),
+                }
+            }
+        };
+        arguments.push(argument);
+        index += 1;
+    }
+
+    if index < words.len() {
+        return Err("Too many arguments".to_string());
+    }
+    Ok(arguments)
+}
+
+//Suggestions for the last word of the text, like vanilla only command names keep their /
+pub fn tab_complete(text: &str, environment: &SyncEnvironment) -> Vec<String> {
+    let words: Vec<&str> = text.split(' ').collect();
+    let last = words[words.len() - 1];
+
+    let mut matches = if !text.starts_with('/') {
+        player_names(last, environment)
+    } else if words.len() == 1 {
+        let prefix = last[1..].to_ascii_lowercase();
+        environment
+            .commands
+            .commands
+            .iter()
+            .flat_map(|command| std::iter::once(&command.name).chain(command.aliases.iter()))
+            .filter(|name| name.starts_with(&prefix))
+            .map(|name| format!("/{}", name))
+            .collect()
+    } else {
+        match environment.commands.find(&words[0][1..]) {
+            Some(command) => {
+                let position = words.len() - 2;
+                let mut matches = Vec::new();
+                for syntax in command.syntaxes.iter() {
+                    if let Some(kind) = kind_at(syntax, position) {
+                        matches.append(&mut complete_argument(kind, last, environment));
+                    }
+                }
+                matches
+            }
+            None => Vec::new(),
+        }
+    };
+
+    matches.sort();
+    matches.dedup();
+    matches
+}
+
+//Kind of the parameter the word at this position belongs to
+fn kind_at(syntax: &[Parameter], position: usize) -> Option<ArgumentKind> {
+    let mut start = 0;
+    for parameter in syntax.iter() {
+        let width = match parameter.kind {
+            ArgumentKind::Coordinates => 3,
+            ArgumentKind::Text => return Some(parameter.kind),
+            _ => 1,
+        };
+        if position < start + width {
+            return Some(parameter.kind);
+        }
+        start += width;
+    }
+    None
+}
+
+fn complete_argument(kind: ArgumentKind, word: &str, environment: &SyncEnvironment) -> Vec<String> {
+    let prefix = word.to_ascii_lowercase();
+    match kind {
+        ArgumentKind::Player | ArgumentKind::Text => player_names(word, environment),
+        ArgumentKind::Coordinates if word.is_empty() => vec!["~".to_string()],
+        ArgumentKind::Block => {
+            let prefix = prefix.strip_prefix("minecraft:").unwrap_or(&prefix);
+            MATERIAL_NAMES
+                .iter()
+                .filter(|name| name.starts_with(prefix))
+                .map(|name| name.to_string())
+                .collect()
+        }
+        ArgumentKind::Literal(options) => options
+            .iter()
+            .filter(|option| option.starts_with(&prefix))
+            .map(|option| option.to_string())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn player_names(word: &str, environment: &SyncEnvironment) -> Vec<String> {
+    let prefix = word.to_ascii_lowercase();
+    environment
+        .players
+        .iter()
+        .filter(|player| player.nickname.to_ascii_lowercase().starts_with(&prefix))
+        .map(|player| player.nickname.clone())
+        .collect()
+}
+
+pub fn tab_complete_listener(
+    packet: &Packet,
+    player_index: usize,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) {
+    if let Packet::ClientTabComplete { text, .. } = packet {
+        let matches = tab_complete(text, environment);
+        net_writer.send_packet(
+            environment.players[player_index].token,
+            Packet::ServerTabComplete { matches },
+        );
+    }
+}
+
+//Shows the message in the sender's chat, or in the console
+pub fn send_message(
+    component: ChatComponent,
+    sender: CommandSender,
+    net_writer: &NetWriter,
+    environment: &SyncEnvironment,
+) {
+    match sender {
+        CommandSender::Player(index) => net_writer.send_packet(
+            environment.players[index].token,
+            Packet::ServerChatMessage { component, pos: 0 },
+        ),
+        CommandSender::Console => log!("{}", component.to_plain_text()),
+    }
+}
+
+pub fn send_error(
+    message: String,
+    sender: CommandSender,
+    net_writer: &NetWriter,
+    environment: &SyncEnvironment,
+) {
+    let component = ChatComponent::new_text(message).color(Color::Red);
+    send_message(component, sender, net_writer, environment);
+}
+
+#[test]
+fn parses_arguments_and_completes_them() {
+    use crate::config::ServerConfig;
+    use crate::game::engine::TestEnvironment;
+    use crate::game::player::Player;
+
+    let mut test = TestEnvironment::new(ServerConfig::default());
+    test.players.push(Player::test(
+        mio::Token(1),
+        Point {
+            x: 1.0,
+            y: 64.0,
+            z: 1.0,
+        },
+    ));
+    let mut environment = test.environment();
+    let commands = environment.commands;
+
+    let tp = commands.find("teleport").unwrap();
+    match parse_syntax(tp.syntaxes[1], &["steve", "~", "~2", "-3"], &environment).as_deref() {
+        Ok([Argument::Player(0), Argument::Coordinates(coordinates)]) => {
+            let point = coordinates
+                .resolve(Some(&environment.players[0].position))
+                .unwrap();
+            assert!(
+                point
+                    == Point {
+                        x: 1.0,
+                        y: 66.0,
+                        z: -3.0
+                    }
+            );
+            assert!(coordinates.resolve(None).is_err());
+        }
+        _ => panic!("/tp steve ~ ~2 -3 wasn't parsed"),
+    }
+    assert!(parse_syntax(tp.syntaxes[1], &["Alex", "0", "0", "0"], &environment).is_err());
+    assert!(parse_syntax(tp.syntaxes[0], &["0", "0"], &environment).is_err());
+    assert!(parse_syntax(tp.syntaxes[0], &["NaN", "64", "0"], &environment).is_err());
+    assert!(parse_syntax(tp.syntaxes[0], &["0", "~inf", "0"], &environment).is_err());
+    assert_eq!(tp.usage()[0], "/tp <x> <y> <z>");
+
+    assert_eq!(tab_complete("/ti", &environment), vec!["/time"]);
+    assert_eq!(tab_complete("/tp ", &environment), vec!["Steve", "~"]);
+    assert_eq!(tab_complete("/gamemode c", &environment), vec!["creative"]);
+    assert_eq!(
+        tab_complete("/setblock 0 0 0 minecraft:diamond_b", &environment),
+        vec!["diamond_block"]
+    );
+    assert_eq!(tab_complete("hi st", &environment), vec!["Steve"]);
+
+    //Steve isn't an op
+    let (writer, _reader) = std::sync::mpsc::channel();
+    let net_writer = NetWriter { writer };
+    let time = environment.world.time_of_day;
+    execute(
+        "time set 1000",
+        CommandSender::Player(0),
+        &net_writer,
+        &mut environment,
+    );
+    assert_eq!(environment.world.time_of_day, time);
+    execute(
+        "time set 1000",
+        CommandSender::Console,
+        &net_writer,
+        &mut environment,
+    );
+    assert_eq!(environment.world.time_of_day, 1000);
+
+    execute(
+        "summon zombie 0 64 0",
+        CommandSender::Console,
+        &net_writer,
+        &mut environment,
+    );
+    assert_eq!(environment.world.entities.entities.len(), 1);
+}
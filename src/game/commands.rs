@@ -0,0 +1,509 @@
+use crate::game::chat::{ChatComponent, ClickEvent, Color, HoverEvent};
+use crate::game::command::{
+    send_message, Argument, ArgumentKind, Command, CommandRegistry, CommandSender, Parameter,
+};
+use crate::game::engine::SyncEnvironment;
+use crate::game::entity::{mob_type, EntityKind, MOB_NAMES};
+use crate::game::game_chat::broadcast_chat;
+use crate::game::packets::{Packet, PlayerInfoAction, PlayerInfoPlayer};
+use crate::game::player_movement;
+use crate::game::world::coords::Position;
+use crate::net::network_manager::NetWriter;
+
+const GAMEMODES: &[&str] = &[
+    "survival",
+    "creative",
+    "adventure",
+    "spectator",
+    "0",
+    "1",
+    "2",
+    "3",
+];
+
+const TELEPORT_SYNTAXES: &[&[Parameter]] = &[
+    &[Parameter::required("position", ArgumentKind::Coordinates)],
+    &[
+        Parameter::required("player", ArgumentKind::Player),
+        Parameter::required("position", ArgumentKind::Coordinates),
+    ],
+    &[
+        Parameter::required("player", ArgumentKind::Player),
+        Parameter::required("target", ArgumentKind::Player),
+    ],
+];
+
+const GAMEMODE_SYNTAXES: &[&[Parameter]] = &[&[
+    Parameter::required("mode", ArgumentKind::Literal(GAMEMODES)),
+    Parameter::optional("player", ArgumentKind::Player),
+]];
+
+const TIME_SYNTAXES: &[&[Parameter]] = &[
+    &[
+        Parameter::required("action", ArgumentKind::Literal(&["set", "add"])),
+        Parameter::required("value", ArgumentKind::Integer),
+    ],
+    &[Parameter::required(
+        "action",
+        ArgumentKind::Literal(&["query"]),
+    )],
+];
+
+const KICK_SYNTAXES: &[&[Parameter]] = &[&[
+    Parameter::required("player", ArgumentKind::Player),
+    Parameter::optional("reason", ArgumentKind::Text),
+]];
+
+const SAY_SYNTAXES: &[&[Parameter]] = &[&[Parameter::required("message", ArgumentKind::Text)]];
+
+const SET_BLOCK_SYNTAXES: &[&[Parameter]] = &[&[
+    Parameter::required("position", ArgumentKind::Coordinates),
+    Parameter::required("block", ArgumentKind::Block),
+]];
+
+const SUMMON_SYNTAXES: &[&[Parameter]] = &[&[
+    Parameter::required("entity", ArgumentKind::Literal(&MOB_NAMES)),
+    Parameter::optional("position", ArgumentKind::Coordinates),
+]];
+
+pub fn register_built_ins(registry: &mut CommandRegistry) {
+    registry.register(Command {
+        name: "help",
+        aliases: &["?"],
+        description: "Lists the commands",
+        permission_level: 0,
+        syntaxes: &[&[]],
+        executor: help,
+    });
+    registry.register(Command {
+        name: "tp",
+        aliases: &["teleport"],
+        description: "Teleports a player to a position or to another player",
+        permission_level: 2,
+        syntaxes: TELEPORT_SYNTAXES,
+        executor: teleport,
+    });
+    registry.register(Command {
+        name: "gamemode",
+        aliases: &["gm"],
+        description: "Changes the game mode of a player",
+        permission_level: 2,
+        syntaxes: GAMEMODE_SYNTAXES,
+        executor: gamemode,
+    });
+    registry.register(Command {
+        name: "time",
+        aliases: &[],
+        description: "Changes or shows the time of the day",
+        permission_level: 2,
+        syntaxes: TIME_SYNTAXES,
+        executor: time,
+    });
+    registry.register(Command {
+        name: "kick",
+        aliases: &[],
+        description: "Disconnects a player",
+        permission_level: 3,
+        syntaxes: KICK_SYNTAXES,
+        executor: kick,
+    });
+    registry.register(Command {
+        name: "list",
+        aliases: &["online"],
+        description: "Lists the online players",
+        permission_level: 0,
+        syntaxes: &[&[]],
+        executor: list,
+    });
+    registry.register(Command {
+        name: "say",
+        aliases: &[],
+        description: "Broadcasts a message",
+        permission_level: 1,
+        syntaxes: SAY_SYNTAXES,
+        executor: say,
+    });
+    registry.register(Command {
+        name: "tps",
+        aliases: &["mspt"],
+        description: "Shows the ticks per second and milliseconds per tick",
+        permission_level: 0,
+        syntaxes: &[&[]],
+        executor: tps,
+    });
+    registry.register(Command {
+        name: "stop",
+        aliases: &[],
+        description: "Saves the world and stops the server",
+        permission_level: 4,
+        syntaxes: &[&[]],
+        executor: stop,
+    });
+    registry.register(Command {
+        name: "setblock",
+        aliases: &[],
+        description: "Places a block",
+        permission_level: 2,
+        syntaxes: SET_BLOCK_SYNTAXES,
+        executor: set_block,
+    });
+    registry.register(Command {
+        name: "summon",
+        aliases: &[],
+        description: "Spawns a mob",
+        permission_level: 2,
+        syntaxes: SUMMON_SYNTAXES,
+        executor: summon,
+    });
+}
+
+//Index of the player running the command, for the ones that act on the sender
+fn sender_player(sender: CommandSender) -> Result<usize, String> {
+    match sender {
+        CommandSender::Player(index) => Ok(index),
+        CommandSender::Console => Err("You must specify a player from the console".to_string()),
+    }
+}
+
+fn sender_name(sender: CommandSender, environment: &SyncEnvironment) -> String {
+    match sender {
+        CommandSender::Player(index) => environment.players[index].nickname.clone(),
+        CommandSender::Console => "Server".to_string(),
+    }
+}
+
+fn reply(
+    message: String,
+    sender: CommandSender,
+    net_writer: &NetWriter,
+    environment: &SyncEnvironment,
+) {
+    send_message(
+        ChatComponent::new_text(message),
+        sender,
+        net_writer,
+        environment,
+    );
+}
+
+fn help(
+    _arguments: &[Argument],
+    sender: CommandSender,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) -> Result<(), String> {
+    //Only the commands the sender can run
+    let level = sender.permission_level(environment);
+    for command in environment.commands.commands.iter() {
+        if command.permission_level > level {
+            continue;
+        }
+        //Clicking a line starts typing the command, hovering shows how to use it
+        let usage = ChatComponent::new_text(command.usage().join("\n"));
+        let component = ChatComponent::text(&format!("/{}", command.name))
+            .color(Color::Gold)
+            .click(ClickEvent::SuggestCommand(format!("/{} ", command.name)))
+            .hover(HoverEvent::ShowText(Box::new(usage)))
+            .extra(ChatComponent::text(&format!(" - {}", command.description)).color(Color::White));
+        send_message(component, sender, net_writer, environment);
+    }
+    Ok(())
+}
+
+fn teleport(
+    arguments: &[Argument],
+    sender: CommandSender,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) -> Result<(), String> {
+    //Relative coordinates are from the teleported player, like vanilla
+    let (index, position) = match arguments {
+        [Argument::Coordinates(coordinates)] => {
+            let index = sender_player(sender)?;
+            (
+                index,
+                coordinates.resolve(Some(&environment.players[index].position))?,
+            )
+        }
+        [Argument::Player(index), Argument::Coordinates(coordinates)] => (
+            *index,
+            coordinates.resolve(Some(&environment.players[*index].position))?,
+        ),
+        [Argument::Player(index), Argument::Player(target)] => {
+            (*index, environment.players[*target].position.clone())
+        }
+        _ => return Err("Invalid arguments".to_string()),
+    };
+
+    let player = &mut environment.players[index];
+    let message = format!(
+        "Teleported {} to {:.2}, {:.2}, {:.2}",
+        player.nickname, position.x, position.y, position.z
+    );
+    player_movement::teleport(player, position, net_writer);
+    reply(message, sender, net_writer, environment);
+    Ok(())
+}
+
+fn gamemode(
+    arguments: &[Argument],
+    sender: CommandSender,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) -> Result<(), String> {
+    let (mode, index) = match arguments {
+        [Argument::Literal(mode)] => (*mode, sender_player(sender)?),
+        [Argument::Literal(mode), Argument::Player(index)] => (*mode, *index),
+        _ => return Err("Invalid arguments".to_string()),
+    };
+    //Names and numbers are listed in the same order
+    let gamemode = (GAMEMODES.iter().position(|t| *t == mode).unwrap() % 4) as u8;
+
+    let player = &mut environment.players[index];
+    player.gamemode = gamemode;
+    net_writer.send_packet(
+        player.token,
+        Packet::ChangeGameState {
+            reason: 3,
+            value: gamemode as f32,
+        },
+    );
+    let uuid = player.uuid;
+    let message = format!(
+        "Set {}'s game mode to {}",
+        player.nickname, GAMEMODES[gamemode as usize]
+    );
+
+    //Other players see it in the tab list
+    environment.broadcast(
+        &Packet::PlayerInfo {
+            players: vec![PlayerInfoPlayer {
+                uuid,
+                action: PlayerInfoAction::UpdateGameMode {
+                    gamemode: gamemode as i32,
+                },
+            }],
+        },
+        net_writer,
+    );
+    reply(message, sender, net_writer, environment);
+    Ok(())
+}
+
+fn time(
+    arguments: &[Argument],
+    sender: CommandSender,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) -> Result<(), String> {
+    let world = &mut *environment.world;
+    match arguments {
+        [Argument::Literal("set"), Argument::Integer(value)] => {
+            world.time_of_day = (*value as i64).rem_euclid(24000)
+        }
+        [Argument::Literal("add"), Argument::Integer(value)] => {
+            world.time_of_day = (world.time_of_day + *value as i64).rem_euclid(24000)
+        }
+        [Argument::Literal("query")] => {
+            let message = format!("The time is {}", world.time_of_day);
+            reply(message, sender, net_writer, environment);
+            return Ok(());
+        }
+        _ => return Err("Invalid arguments".to_string()),
+    }
+
+    let packet = Packet::TimeUpdate {
+        world_age: world.age,
+        time_of_day: world.time_of_day,
+    };
+    let message = format!("Set the time to {}", world.time_of_day);
+    environment.broadcast(&packet, net_writer);
+    reply(message, sender, net_writer, environment);
+    Ok(())
+}
+
+fn kick(
+    arguments: &[Argument],
+    sender: CommandSender,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) -> Result<(), String> {
+    let (index, reason) = match arguments {
+        [Argument::Player(index)] => (*index, "Kicked by an operator".to_string()),
+        [Argument::Player(index), Argument::Text(reason)] => (*index, reason.clone()),
+        _ => return Err("Invalid arguments".to_string()),
+    };
+
+    //The player is removed once the network thread closes the connection
+    let player = &environment.players[index];
+    net_writer.disconnect(player.token, ChatComponent::new_text(reason.clone()));
+    let message = format!("Kicked {}: {}", player.nickname, reason);
+    reply(message, sender, net_writer, environment);
+    Ok(())
+}
+
+fn list(
+    _arguments: &[Argument],
+    sender: CommandSender,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) -> Result<(), String> {
+    let names: Vec<&str> = environment
+        .players
+        .iter()
+        .map(|player| player.nickname.as_str())
+        .collect();
+    let message = format!(
+        "There are {}/{} players online: {}",
+        names.len(),
+        environment.config.max_players,
+        names.join(", ")
+    );
+    reply(message, sender, net_writer, environment);
+    Ok(())
+}
+
+fn say(
+    arguments: &[Argument],
+    sender: CommandSender,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) -> Result<(), String> {
+    let message = match arguments {
+        [Argument::Text(message)] => message,
+        _ => return Err("Invalid arguments".to_string()),
+    };
+
+    let name = sender_name(sender, environment);
+    log!("[Chat] [{}] {}", name, message);
+    //Shown by the client as [name] message, like the vanilla /say
+    let component = ChatComponent::translate("chat.type.announcement")
+        .color(Color::LightPurple)
+        .with(ChatComponent::new_text(name))
+        .with(ChatComponent::text(message));
+    broadcast_chat(component, net_writer, environment);
+    Ok(())
+}
+
+fn set_block(
+    arguments: &[Argument],
+    sender: CommandSender,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) -> Result<(), String> {
+    let (coordinates, block) = match arguments {
+        [Argument::Coordinates(coordinates), Argument::Block(block)] => (coordinates, *block),
+        _ => return Err("Invalid arguments".to_string()),
+    };
+    let base = match sender {
+        CommandSender::Player(index) => Some(&environment.players[index].position),
+        CommandSender::Console => None,
+    };
+    let point = coordinates.resolve(base)?;
+    let position = Position {
+        x: point.x.floor() as i32,
+        y: point.y.floor() as i16,
+        z: point.z.floor() as i32,
+    };
+    if position.y < 0 || position.y > 255 {
+        return Err("Can't place blocks outside of the world".to_string());
+    }
+
+    environment
+        .world
+        .set_block(block, position.x, position.y, position.z);
+    let message = format!(
+        "Placed {} at {}, {}, {}",
+        block.material.name().unwrap_or("block"),
+        position.x,
+        position.y,
+        position.z
+    );
+    environment.broadcast(
+        &Packet::BlockChange {
+            location: position,
+            block_id: block.get_encoded() as i32,
+        },
+        net_writer,
+    );
+    reply(message, sender, net_writer, environment);
+    Ok(())
+}
+
+fn summon(
+    arguments: &[Argument],
+    sender: CommandSender,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) -> Result<(), String> {
+    let base = match sender {
+        CommandSender::Player(index) => Some(&environment.players[index].position),
+        CommandSender::Console => None,
+    };
+    let (name, position) = match arguments {
+        [Argument::Literal(name)] => (
+            *name,
+            base.ok_or("You must specify a position from the console")?
+                .clone(),
+        ),
+        [Argument::Literal(name), Argument::Coordinates(coordinates)] => {
+            (*name, coordinates.resolve(base)?)
+        }
+        _ => return Err("Invalid arguments".to_string()),
+    };
+    let mob_type = mob_type(name).unwrap();
+
+    //The entity tracker spawns it for the players in range on the next tick
+    environment
+        .world
+        .entities
+        .spawn(EntityKind::Mob { mob_type }, position);
+    let message = format!("Summoned {}", name);
+    reply(message, sender, net_writer, environment);
+    Ok(())
+}
+
+fn tps(
+    _arguments: &[Argument],
+    sender: CommandSender,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) -> Result<(), String> {
+    let stats = environment.tick_stats;
+    let mut tps = ChatComponent::text("TPS from last 1m, 5m, 15m:").color(Color::Gold);
+    let mut mspt = ChatComponent::text("MSPT from last 1m, 5m, 15m:").color(Color::Gold);
+    for i in 0..3 {
+        //Green while the server keeps up, yellow when a bit behind and red when lagging
+        let color = match stats.tps[i] {
+            t if t >= 19.0 => Color::Green,
+            t if t >= 15.0 => Color::Yellow,
+            _ => Color::Red,
+        };
+        tps = tps.extra(ChatComponent::text(&format!(" {:.2}", stats.tps[i])).color(color));
+
+        let color = match stats.mspt[i] {
+            t if t <= 40.0 => Color::Green,
+            t if t <= 50.0 => Color::Yellow,
+            _ => Color::Red,
+        };
+        mspt = mspt.extra(ChatComponent::text(&format!(" {:.2}", stats.mspt[i])).color(color));
+    }
+    send_message(tps, sender, net_writer, environment);
+    send_message(mspt, sender, net_writer, environment);
+    Ok(())
+}
+
+fn stop(
+    _arguments: &[Argument],
+    sender: CommandSender,
+    _net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) -> Result<(), String> {
+    match sender {
+        CommandSender::Console => {
+            environment.stopping = true;
+            Ok(())
+        }
+        CommandSender::Player(_) => Err("Only the console can stop the server".to_string()),
+    }
+}
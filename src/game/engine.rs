@@ -1,68 +1,129 @@
+use crate::config::ServerConfig;
 use crate::game::chat::ChatComponent;
+use crate::game::chunk_streaming::{self, ChunkView};
+use crate::game::command::{self, CommandRegistry, CommandSender};
+use crate::game::commands;
+use crate::game::entity_tracker;
 use crate::game::game_chat;
-use crate::game::packets::Packet;
-use crate::game::player::{Player, PlayerList};
+use crate::game::packets::{Packet, PacketKind};
+use crate::game::player::{publish_snapshot, Player, PlayerList, PlayerListSnapshot};
 use crate::game::player_join;
+use crate::game::player_movement::{self, MovementState};
+use crate::game::scheduler::{self, Scheduler};
+use crate::game::tick::{TickStats, TickStatsSnapshot, TickTimer};
 use crate::game::world::chunk::ChunkPos;
+use crate::game::world::coords::Point;
 use crate::game::world::generator::generate;
+use crate::game::world::region;
 use crate::game::world::world::{LevelType, World};
+use crate::net::compression;
 use crate::net::network_manager::{GameProtocol, NetWriter};
-use crate::net::packet_listener::PacketListenerStruct;
+use crate::net::packet_listener::{self, PacketListenerStruct};
 use std::ops::DerefMut;
+use std::path::Path;
 use std::sync::mpsc::Receiver;
-use std::sync::MutexGuard;
+use std::sync::Arc;
 use std::thread::JoinHandle;
 use std::time::Duration;
 
+//Ticks between each world save, 5 minutes at 20 TPS
+const AUTOSAVE_INTERVAL: u64 = 6000;
+
 pub fn start(
+    config: &'static ServerConfig,
     players: PlayerList,
+    player_snapshot: PlayerListSnapshot,
+    tick_stats: TickStatsSnapshot,
     net_writer: NetWriter,
     game_reader: Receiver<GameProtocol>,
 ) -> ! {
     let packet_listeners = [
-        // PacketListenerStruct {packet_id: 0x00, listener: network_manager::keep_alive_listener},
+        // PacketListenerStruct {kind: PacketKind::KeepAlive, listener: network_manager::keep_alive_listener},
         PacketListenerStruct {
-            packet_id: 0x01,
+            kind: PacketKind::ClientChatMessage,
             listener: game_chat::chat_listener,
         },
+        PacketListenerStruct {
+            kind: PacketKind::ClientTabComplete,
+            listener: command::tab_complete_listener,
+        },
+        PacketListenerStruct {
+            kind: PacketKind::ClientSettings,
+            listener: chunk_streaming::settings_listener,
+        },
+        PacketListenerStruct {
+            kind: PacketKind::Player,
+            listener: player_movement::movement_listener,
+        },
+        PacketListenerStruct {
+            kind: PacketKind::PlayerPosition,
+            listener: player_movement::movement_listener,
+        },
+        PacketListenerStruct {
+            kind: PacketKind::PlayerLook,
+            listener: player_movement::movement_listener,
+        },
+        PacketListenerStruct {
+            kind: PacketKind::ClientPlayerPositionAndLook,
+            listener: player_movement::movement_listener,
+        },
     ];
-    let mut keep_alive_ticks = 0u8;
+    let mut commands = CommandRegistry::new();
+    commands::register_built_ins(&mut commands);
+
+    let mut world = World::new(config.level_name.clone(), config.difficulty, config.level_type);
 
-    let mut world = World::new("Mundo".to_string(), 0, LevelType::Default);
-    generate(&mut world);
+    //Load an existing Anvil world if there is one, otherwise generate a new one
+    let region_directory = Path::new(&world.name).join("region");
+    if region_directory.is_dir() {
+        match region::load_world(&mut world, &region_directory) {
+            Ok(loaded) => log!("Loaded {} chunks from {}", loaded, region_directory.display()),
+            Err(e) => log!("An error occured while loading the world: {}", e.reason()),
+        }
+    } else {
+        generate(&mut world);
+    }
+
+    let mut scheduler = Scheduler::new();
+    let autosave_directory = region_directory.clone();
+    scheduler.run_repeating(
+        AUTOSAVE_INTERVAL,
+        AUTOSAVE_INTERVAL,
+        move |_, environment| save(environment.world, &autosave_directory),
+    );
 
     //Ticks
+    let mut timer = TickTimer::new(tick_stats);
     loop {
+        timer.start_tick();
+
         //Locks for sync environment
-        {
+        let stopping = {
             let mut players = players.lock().unwrap();
 
             let mut sync_environment = SyncEnvironment {
                 players: players.deref_mut(),
                 world: &mut world,
+                config,
+                commands: &commands,
+                scheduler: &mut scheduler,
+                stopping: false,
+                tick_stats: timer.stats,
             };
-            // network_manager::tick(&mut sync_environment, &packet_listeners, &mut keep_alive_ticks);
 
             for message in game_reader.try_iter() {
                 match message {
                     //Disconnect order from the net thread, it may be because of socket errors/disconnect, keep alive not sent, etc.
                     GameProtocol::ForcedDisconnect { token, reason } => {
-                        let index = sync_environment
-                            .players
-                            .iter()
-                            .position(|player| player.token.eq(&token));
-                        match index {
-                            Some(t) => {
-                                let player = sync_environment.players.remove(t);
-                                println!("Player {} disconnected", player.nickname)
-                            }
-                            None => {}
-                        };
+                        if player_join::handle_leave(token, &net_writer, &mut sync_environment) {
+                            publish_snapshot(sync_environment.players, player_snapshot);
+                        }
                     }
                     GameProtocol::Login {
                         token,
                         nickname,
                         uuid,
+                        properties,
                     } => {
                         //Check if another player with the same UUID is already on the server
                         let already_logged_in = sync_environment
@@ -77,24 +138,156 @@ pub fn start(
                             continue;
                         }
 
+                        let position = Point {
+                            x: 0.0,
+                            y: 51.0,
+                            z: 0.0,
+                        };
+                        let entity_id = sync_environment
+                            .world
+                            .entities
+                            .spawn_player(uuid, position.clone())
+                            .id;
                         let mut player = Player {
                             token,
                             nickname,
                             uuid,
+                            entity_id,
+                            properties,
+                            movement: MovementState::new(position.clone()),
+                            position,
+                            yaw: 0.0,
+                            pitch: 0.0,
+                            on_ground: false,
+                            dimension: 0,
+                            gamemode: config.gamemode,
+                            chunk_view: ChunkView::new(),
                         };
                         player_join::handle_join(&mut player, &net_writer, &mut sync_environment);
                         sync_environment.players.push(player);
+                        publish_snapshot(sync_environment.players, player_snapshot);
+                    }
+                    GameProtocol::Console { line } => {
+                        command::execute(
+                            &line,
+                            CommandSender::Console,
+                            &net_writer,
+                            &mut sync_environment,
+                        );
+                    }
+                    GameProtocol::Packet { token, packet } => {
+                        //Packets can still arrive from a player that was just removed
+                        let index = sync_environment
+                            .players
+                            .iter()
+                            .position(|player| player.token.eq(&token));
+                        if let Some(index) = index {
+                            packet_listener::dispatch(
+                                &packet_listeners,
+                                &packet,
+                                index,
+                                &net_writer,
+                                &mut sync_environment,
+                            );
+                        }
                     }
-                    GameProtocol::Packet { token, packet } => {}
                 }
             }
+
+            scheduler::run_tasks(&net_writer, &mut sync_environment);
+            entity_tracker::tick(&net_writer, &mut sync_environment);
+            chunk_streaming::tick(&net_writer, &mut sync_environment);
+
+            if sync_environment.stopping {
+                for player in sync_environment.players.iter() {
+                    net_writer.disconnect(player.token, ChatComponent::text("Server closed"));
+                }
+            }
+            sync_environment.stopping
+        };
+
+        if stopping {
+            log!("Stopping the server");
+            save(&mut world, &region_directory);
+            //Give the network thread time to send the disconnects
+            std::thread::sleep(Duration::from_secs(1));
+            std::process::exit(0);
         }
+
+        world.age += 1;
+        world.time_of_day = (world.time_of_day + 1) % 24000;
+
         //You need to drop everything before this sleep
-        std::thread::sleep(Duration::from_millis(50));
+        timer.finish_tick();
+    }
+}
+
+pub fn save(world: &mut World, region_directory: &Path) {
+    match region::save_world(world, region_directory) {
+        Ok(0) => {}
+        Ok(saved) => log!("Saved {} chunks to {}", saved, region_directory.display()),
+        Err(e) => log!("An error occured while saving the world: {}", e.reason()),
     }
 }
 
 pub struct SyncEnvironment<'a> {
     pub players: &'a mut Vec<Player>,
     pub world: &'a mut World,
+    pub config: &'static ServerConfig,
+    pub commands: &'a CommandRegistry,
+    pub scheduler: &'a mut Scheduler,
+    //Set by /stop, the server shuts down at the end of the tick
+    pub stopping: bool,
+    pub tick_stats: TickStats,
+}
+
+impl SyncEnvironment<'_> {
+    //Sends the packet to every player, it's serialized once and the same bytes are queued for all of them
+    pub fn broadcast(&self, packet: &Packet, net_writer: &NetWriter) {
+        let data = Arc::new(compression::frame(
+            packet.serialize().unwrap(),
+            self.config.compression_threshold(),
+        ));
+        for player in self.players.iter() {
+            net_writer.send_data(player.token, data.clone());
+        }
+    }
+}
+
+//Owns what a SyncEnvironment borrows, so tests can build one without the engine
+#[cfg(test)]
+pub struct TestEnvironment {
+    pub players: Vec<Player>,
+    pub world: World,
+    pub config: &'static ServerConfig,
+    pub commands: CommandRegistry,
+    pub scheduler: Scheduler,
+}
+
+#[cfg(test)]
+impl TestEnvironment {
+    //World without chunks and the built-in commands, the config is leaked like the server's
+    pub fn new(config: ServerConfig) -> TestEnvironment {
+        let mut commands = CommandRegistry::new();
+        commands::register_built_ins(&mut commands);
+        TestEnvironment {
+            players: Vec::new(),
+            world: World::new("test".to_string(), 0, LevelType::Default),
+            config: Box::leak(Box::new(config)),
+            commands,
+            scheduler: Scheduler::new(),
+        }
+    }
+
+    pub fn environment(&mut self) -> SyncEnvironment<'_> {
+        SyncEnvironment {
+            players: &mut self.players,
+            world: &mut self.world,
+            config: self.config,
+            commands: &self.commands,
+            scheduler: &mut self.scheduler,
+            stopping: false,
+            tick_stats: Default::default(),
+        }
+    }
 }
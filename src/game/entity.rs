@@ -0,0 +1,184 @@
+use crate::game::packets::{MetadataEntry, MetadataValue};
+use crate::game::world::coords::Point;
+use mio::Token;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+//Metadata index of the skin layers a player shows
+pub const SKIN_PARTS_METADATA: u8 = 10;
+
+pub enum EntityKind {
+    Player { uuid: Uuid },
+    Mob { mob_type: u8 },
+}
+
+//Names /summon takes, with the type id of each in the same position of MOB_TYPES
+pub const MOB_NAMES: [&str; 32] = [
+    "Creeper",
+    "Skeleton",
+    "Spider",
+    "Giant",
+    "Zombie",
+    "Slime",
+    "Ghast",
+    "PigZombie",
+    "Enderman",
+    "CaveSpider",
+    "Silverfish",
+    "Blaze",
+    "LavaSlime",
+    "EnderDragon",
+    "WitherBoss",
+    "Bat",
+    "Witch",
+    "Endermite",
+    "Guardian",
+    "Pig",
+    "Sheep",
+    "Cow",
+    "Chicken",
+    "Squid",
+    "Wolf",
+    "MushroomCow",
+    "SnowMan",
+    "Ozelot",
+    "VillagerGolem",
+    "EntityHorse",
+    "Rabbit",
+    "Villager",
+];
+pub const MOB_TYPES: [u8; 32] = [
+    50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 90, 91, 92, 93, 94,
+    95, 96, 97, 98, 99, 100, 101, 120,
+];
+
+pub fn mob_type(name: &str) -> Option<u8> {
+    MOB_NAMES
+        .iter()
+        .position(|t| t.eq_ignore_ascii_case(name))
+        .map(|index| MOB_TYPES[index])
+}
+
+pub struct Entity {
+    pub id: i32,
+    pub kind: EntityKind,
+    pub position: Point,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub head_yaw: f32,
+    //Blocks per tick
+    pub velocity: Point,
+    pub on_ground: bool,
+    pub metadata: Vec<MetadataEntry>,
+    //Set by Entities::remove, the tracker destroys it for its viewers and drops it
+    pub removed: bool,
+    pub tracked: TrackedState,
+}
+
+//What the players tracking the entity were last sent
+pub struct TrackedState {
+    pub viewers: Vec<Token>,
+    //In 1/32 of a block, like in the packets
+    pub position: (i32, i32, i32),
+    pub yaw: u8,
+    pub pitch: u8,
+    pub head_yaw: u8,
+    pub velocity: (i16, i16, i16),
+    //Indexes of the metadata entries changed since the last tick
+    pub changed_metadata: Vec<u8>,
+    //Relative moves drift a bit, so a teleport is sent every now and then
+    pub ticks_since_teleport: u32,
+}
+
+impl Entity {
+    pub fn new(id: i32, kind: EntityKind, position: Point) -> Self {
+        Entity {
+            id,
+            kind,
+            tracked: TrackedState {
+                viewers: Vec::new(),
+                position: (
+                    position.absolute_x(),
+                    position.absolute_y(),
+                    position.absolute_z(),
+                ),
+                yaw: 0,
+                pitch: 0,
+                head_yaw: 0,
+                velocity: (0, 0, 0),
+                changed_metadata: Vec::new(),
+                ticks_since_teleport: 0,
+            },
+            position,
+            yaw: 0.0,
+            pitch: 0.0,
+            head_yaw: 0.0,
+            velocity: Point {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            on_ground: false,
+            metadata: Vec::new(),
+            removed: false,
+        }
+    }
+
+    //Sets the entry at the index, the tracker sends it to the viewers on the next tick
+    pub fn set_metadata(&mut self, index: u8, value: MetadataValue) {
+        match self.metadata.iter_mut().find(|entry| entry.index == index) {
+            Some(entry) => entry.value = value,
+            None => self.metadata.push(MetadataEntry { index, value }),
+        }
+        if !self.tracked.changed_metadata.contains(&index) {
+            self.tracked.changed_metadata.push(index);
+        }
+    }
+}
+
+//Every entity in the world, by id
+pub struct Entities {
+    next_id: i32,
+    pub entities: HashMap<i32, Entity>,
+}
+
+impl Entities {
+    pub fn new() -> Self {
+        Entities {
+            next_id: 0,
+            entities: HashMap::new(),
+        }
+    }
+
+    //Ids are never reused, the client could still have an entity with an old one
+    pub fn allocate_id(&mut self) -> i32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn spawn(&mut self, kind: EntityKind, position: Point) -> &mut Entity {
+        let id = self.allocate_id();
+        self.entities
+            .entry(id)
+            .or_insert(Entity::new(id, kind, position))
+    }
+
+    //The id is also the one the player gets in JoinGame
+    pub fn spawn_player(&mut self, uuid: Uuid, position: Point) -> &mut Entity {
+        let entity = self.spawn(EntityKind::Player { uuid }, position);
+        //The client crashes on a SpawnPlayer without metadata, this shows every skin layer
+        entity.set_metadata(SKIN_PARTS_METADATA, MetadataValue::Byte { value: 0x7F });
+        entity
+    }
+
+    pub fn get_mut(&mut self, id: i32) -> Option<&mut Entity> {
+        self.entities.get_mut(&id)
+    }
+
+    pub fn remove(&mut self, id: i32) {
+        if let Some(entity) = self.entities.get_mut(&id) {
+            entity.removed = true;
+        }
+    }
+}
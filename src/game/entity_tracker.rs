@@ -0,0 +1,336 @@
+use crate::game::engine::SyncEnvironment;
+use crate::game::entity::{Entity, EntityKind};
+use crate::game::packets::Packet;
+use crate::game::player::Player;
+use crate::game::world::angle::{to_steps, Angle};
+use crate::net::compression;
+use crate::net::network_manager::NetWriter;
+use mio::Token;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+//Ticks between the teleports that correct the drift of relative moves, same as vanilla
+const TELEPORT_INTERVAL: u32 = 400;
+//Velocity is sent in 1/8000 of a block per tick, and the client caps it at 3.9 blocks
+const MAX_VELOCITY: f64 = 3.9;
+
+//Spawns the entities for the players in range and sends what changed since the last tick
+//to the ones already tracking them, run by the engine once per tick
+pub fn tick(net_writer: &NetWriter, environment: &mut SyncEnvironment) {
+    //Players move through their packets, their entities follow them
+    for player in environment.players.iter() {
+        if let Some(entity) = environment.world.entities.get_mut(player.entity_id) {
+            entity.position = player.position.clone();
+            entity.yaw = player.yaw;
+            entity.pitch = player.pitch;
+            entity.head_yaw = player.yaw;
+            entity.on_ground = player.on_ground;
+        }
+    }
+
+    let range = environment.config.entity_tracking_range as f64;
+    let threshold = environment.config.compression_threshold();
+    let players = &*environment.players;
+    //Ids to destroy for each player, so they get a single packet
+    let mut destroyed: HashMap<Token, Vec<i32>> = HashMap::new();
+
+    environment.world.entities.entities.retain(|_, entity| {
+        entity
+            .tracked
+            .viewers
+            .retain(|token| players.iter().any(|player| player.token == *token));
+
+        if entity.removed {
+            for token in &entity.tracked.viewers {
+                destroyed.entry(*token).or_default().push(entity.id);
+            }
+            return false;
+        }
+
+        for packet in updates(entity) {
+            send_to(&entity.tracked.viewers, &packet, net_writer, threshold);
+        }
+
+        for player in players {
+            if player.entity_id == entity.id {
+                continue;
+            }
+            let tracking = entity.tracked.viewers.contains(&player.token);
+            if in_range(entity, player, range) {
+                if !tracking {
+                    for packet in spawn_packets(entity) {
+                        net_writer.send_packet(player.token, packet);
+                    }
+                    entity.tracked.viewers.push(player.token);
+                }
+            } else if tracking {
+                entity
+                    .tracked
+                    .viewers
+                    .retain(|token| *token != player.token);
+                destroyed.entry(player.token).or_default().push(entity.id);
+            }
+        }
+        true
+    });
+
+    for (token, entity_ids) in destroyed {
+        net_writer.send_packet(token, Packet::DestroyEntities { entity_ids });
+    }
+}
+
+//Same square around the player vanilla uses
+fn in_range(entity: &Entity, player: &Player, range: f64) -> bool {
+    (entity.position.x - player.position.x).abs() <= range
+        && (entity.position.z - player.position.z).abs() <= range
+}
+
+//Packets that show the entity as it is now to a player that wasn't tracking it
+fn spawn_packets(entity: &Entity) -> Vec<Packet> {
+    let tracked = &entity.tracked;
+    let angle = Angle {
+        pitch: tracked.pitch,
+        yaw: tracked.yaw,
+    };
+    let (vel_x, vel_y, vel_z) = tracked.velocity;
+
+    let mut packets = Vec::new();
+    match &entity.kind {
+        EntityKind::Player { uuid } => packets.push(Packet::SpawnPlayer {
+            entity_id: entity.id,
+            uuid: *uuid,
+            point: entity.position.clone(),
+            angle,
+            current_item: 0,
+            metadata: entity.metadata.clone(),
+        }),
+        EntityKind::Mob { mob_type } => packets.push(Packet::SpawnMob {
+            entity_id: entity.id,
+            mob_type: *mob_type,
+            point: entity.position.clone(),
+            angle,
+            head_pitch: tracked.head_yaw,
+            vel_x,
+            vel_y,
+            vel_z,
+            metadata: entity.metadata.clone(),
+        }),
+    }
+
+    packets.push(Packet::EntityHeadLook {
+        entity_id: entity.id,
+        head_yaw: tracked.head_yaw,
+    });
+    packets
+}
+
+//Packets for the changes since the last tick, updating what was sent
+fn updates(entity: &mut Entity) -> Vec<Packet> {
+    let id = entity.id;
+    let tracked = &mut entity.tracked;
+    let mut packets = Vec::new();
+
+    let position = (
+        entity.position.absolute_x(),
+        entity.position.absolute_y(),
+        entity.position.absolute_z(),
+    );
+    let delta = (
+        position.0 - tracked.position.0,
+        position.1 - tracked.position.1,
+        position.2 - tracked.position.2,
+    );
+    let moved = delta != (0, 0, 0);
+    let fits = [delta.0, delta.1, delta.2]
+        .iter()
+        .all(|delta| *delta >= i8::MIN as i32 && *delta <= i8::MAX as i32);
+
+    let angle = Angle::from_degrees(entity.yaw, entity.pitch);
+    let rotated = angle.yaw != tracked.yaw || angle.pitch != tracked.pitch;
+
+    tracked.ticks_since_teleport += 1;
+    if (moved && !fits) || tracked.ticks_since_teleport >= TELEPORT_INTERVAL {
+        tracked.ticks_since_teleport = 0;
+        packets.push(Packet::EntityTeleport {
+            entity_id: id,
+            point: entity.position.clone(),
+            angle: angle.clone(),
+            on_ground: entity.on_ground,
+        });
+    } else if moved && rotated {
+        packets.push(Packet::EntityLookAndRelativeMove {
+            entity_id: id,
+            delta_x: delta.0 as i8,
+            delta_y: delta.1 as i8,
+            delta_z: delta.2 as i8,
+            angle: angle.clone(),
+            on_ground: entity.on_ground,
+        });
+    } else if moved {
+        packets.push(Packet::EntityRelativeMove {
+            entity_id: id,
+            delta_x: delta.0 as i8,
+            delta_y: delta.1 as i8,
+            delta_z: delta.2 as i8,
+            on_ground: entity.on_ground,
+        });
+    } else if rotated {
+        packets.push(Packet::EntityLook {
+            entity_id: id,
+            angle: angle.clone(),
+            on_ground: entity.on_ground,
+        });
+    }
+    tracked.position = position;
+    tracked.yaw = angle.yaw;
+    tracked.pitch = angle.pitch;
+
+    let head_yaw = to_steps(entity.head_yaw);
+    if head_yaw != tracked.head_yaw {
+        tracked.head_yaw = head_yaw;
+        packets.push(Packet::EntityHeadLook {
+            entity_id: id,
+            head_yaw,
+        });
+    }
+
+    let velocity = (
+        velocity_units(entity.velocity.x),
+        velocity_units(entity.velocity.y),
+        velocity_units(entity.velocity.z),
+    );
+    if velocity != tracked.velocity {
+        tracked.velocity = velocity;
+        packets.push(Packet::EntityVelocity {
+            entity_id: id,
+            vel_x: velocity.0,
+            vel_y: velocity.1,
+            vel_z: velocity.2,
+        });
+    }
+
+    if !tracked.changed_metadata.is_empty() {
+        let metadata = entity
+            .metadata
+            .iter()
+            .filter(|entry| tracked.changed_metadata.contains(&entry.index))
+            .cloned()
+            .collect();
+        tracked.changed_metadata.clear();
+        packets.push(Packet::EntityMetadata {
+            entity_id: id,
+            metadata,
+        });
+    }
+    packets
+}
+
+fn velocity_units(blocks_per_tick: f64) -> i16 {
+    (blocks_per_tick.clamp(-MAX_VELOCITY, MAX_VELOCITY) * 8000.0) as i16
+}
+
+//Serializes the packet once for all the viewers
+fn send_to(tokens: &[Token], packet: &Packet, net_writer: &NetWriter, threshold: Option<usize>) {
+    if tokens.is_empty() {
+        return;
+    }
+    let data = Arc::new(compression::frame(packet.serialize().unwrap(), threshold));
+    for token in tokens {
+        net_writer.send_data(*token, data.clone());
+    }
+}
+
+#[test]
+fn tracks_entities_in_range() {
+    use crate::config::ServerConfig;
+    use crate::game::engine::TestEnvironment;
+    use crate::game::world::coords::Point;
+    use crate::net::network_manager::NetProtocol;
+    use std::sync::mpsc::{channel, Receiver};
+
+    let mut test = TestEnvironment::new(ServerConfig {
+        network_compression_threshold: -1,
+        ..Default::default()
+    });
+    let mut steve = Player::test(
+        Token(1),
+        Point {
+            x: 0.0,
+            y: 64.0,
+            z: 0.0,
+        },
+    );
+    steve.entity_id = test.world.entities.allocate_id();
+    test.players.push(steve);
+    let mut environment = test.environment();
+    let (writer, reader) = channel();
+    let net_writer = NetWriter { writer };
+
+    //Ids of the packets sent this tick, every one of them to Steve
+    let tick_ids = |environment: &mut SyncEnvironment, reader: &Receiver<NetProtocol>| {
+        tick(&net_writer, environment);
+        reader
+            .try_iter()
+            .map(|message| match message {
+                NetProtocol::SendPacket { token, packet } => {
+                    assert_eq!(token, Token(1));
+                    packet.serialize().unwrap()[0]
+                }
+                //Uncompressed frames, the length and then the id
+                NetProtocol::SendData { token, packet } => {
+                    assert_eq!(token, Token(1));
+                    packet[1]
+                }
+                _ => panic!("Unexpected message"),
+            })
+            .collect::<Vec<u8>>()
+    };
+
+    let zombie = {
+        let entity = environment.world.entities.spawn(
+            EntityKind::Mob { mob_type: 54 },
+            Point {
+                x: 10.0,
+                y: 64.0,
+                z: 10.0,
+            },
+        );
+        entity.id
+    };
+    //Spawn mob and head look
+    assert_eq!(tick_ids(&mut environment, &reader), [0x0F, 0x19]);
+    assert!(tick_ids(&mut environment, &reader).is_empty());
+
+    let entity = environment.world.entities.get_mut(zombie).unwrap();
+    entity.position.x += 1.0;
+    assert_eq!(tick_ids(&mut environment, &reader), [0x15]);
+    let entity = environment.world.entities.get_mut(zombie).unwrap();
+    entity.position.x += 1.0;
+    entity.yaw = 90.0;
+    assert_eq!(tick_ids(&mut environment, &reader), [0x17]);
+    let entity = environment.world.entities.get_mut(zombie).unwrap();
+    entity.position.z += 10.0;
+    assert_eq!(tick_ids(&mut environment, &reader), [0x18]);
+
+    //Out of range it's destroyed, and spawned again when it comes back
+    environment
+        .world
+        .entities
+        .get_mut(zombie)
+        .unwrap()
+        .position
+        .x = 100.0;
+    assert_eq!(tick_ids(&mut environment, &reader), [0x18, 0x13]);
+    environment
+        .world
+        .entities
+        .get_mut(zombie)
+        .unwrap()
+        .position
+        .x = 0.0;
+    assert_eq!(tick_ids(&mut environment, &reader), [0x0F, 0x19]);
+
+    environment.world.entities.remove(zombie);
+    assert_eq!(tick_ids(&mut environment, &reader), [0x13]);
+    assert!(!environment.world.entities.entities.contains_key(&zombie));
+}
@@ -1,20 +1,134 @@
 use crate::game::chat::ChatComponent;
+use crate::game::command::{self, CommandSender};
 use crate::game::engine::SyncEnvironment;
 use crate::game::packets::Packet;
-use crate::game::player::Player;
-
-pub fn chat_listener(packet: &Packet, player_index: usize, environment: &mut SyncEnvironment) {
-    match packet {
-        Packet::ClientChatMessage { message } => {
-            // let name = environment.players[player_index].nickname.clone();
-            // let packet = Packet::ServerChatMessage {
-            //     component: ChatComponent::new_text(format!("§d{}: §f{}", name, message)),
-            //     pos: 0
-            // };
-            // for x in environment.players.iter_mut() {
-            //     x.connection.send_packet(&packet);
-            // }
+use crate::net::network_manager::NetWriter;
+
+//Longest message the vanilla client sends
+pub const MAX_MESSAGE_LENGTH: usize = 100;
+
+pub fn chat_listener(
+    packet: &Packet,
+    player_index: usize,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) {
+    if let Packet::ClientChatMessage { message } = packet {
+        let player = &environment.players[player_index];
+        let message = match validate_message(message) {
+            Ok(t) => t,
+            Err(e) => {
+                log!("Kicking {} for sending an invalid chat message", player.nickname);
+                net_writer.disconnect(player.token, ChatComponent::new_text(e.reason()));
+                return;
+            }
+        };
+        if message.is_empty() {
+            return;
+        }
+
+        if let Some(line) = message.strip_prefix('/') {
+            log!("{} issued server command: {}", player.nickname, message);
+            command::execute(line, CommandSender::Player(player_index), net_writer, environment);
+            return;
+        }
+
+        let text = format_message(&environment.config.chat_format, &player.nickname, &message);
+        let component = ChatComponent::from_legacy(&text);
+        log!("[Chat] {}", component.to_plain_text());
+        broadcast_chat(component, net_writer, environment);
+    }
+}
+
+pub enum ChatError {
+    TooLong(usize),
+    IllegalCharacter(char),
+}
+
+impl ChatError {
+    pub fn reason(&self) -> String {
+        match self {
+            ChatError::TooLong(length) => format!("Chat message too long ({})", length),
+            ChatError::IllegalCharacter(c) => {
+                format!("Illegal character U+{:04X} in chat", *c as u32)
+            }
         }
-        _ => {}
-    };
+    }
+}
+
+//Same checks as vanilla, control characters and § can't be typed, whitespace is collapsed
+pub fn validate_message(message: &str) -> Result<String, ChatError> {
+    let length = message.chars().count();
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(ChatError::TooLong(length));
+    }
+
+    if let Some(c) = message
+        .chars()
+        .find(|c| *c == '§' || *c < ' ' || *c == '\u{7F}')
+    {
+        return Err(ChatError::IllegalCharacter(c));
+    }
+
+    Ok(message.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+//Fills the template in a single pass, so the name and message can't inject placeholders or colors
+pub fn format_message(template: &str, name: &str, message: &str) -> String {
+    let template = translate_colors(template);
+    let mut result = String::with_capacity(template.len() + name.len() + message.len());
+    let mut rest = template.as_str();
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("{name}") {
+            result.push_str(name);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{message}") {
+            result.push_str(message);
+            rest = after;
+        } else {
+            result.push('{');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+//Turns &-prefixed color and style codes into the § ones the client understands
+pub fn translate_colors(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(code) if c == '&' && is_color_code(*code) => result.push('§'),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+fn is_color_code(c: char) -> bool {
+    matches!(c.to_ascii_lowercase(), '0'..='9' | 'a'..='f' | 'k'..='o' | 'r')
+}
+
+pub fn broadcast_chat(component: ChatComponent, net_writer: &NetWriter, environment: &SyncEnvironment) {
+    environment.broadcast(&Packet::ServerChatMessage { component, pos: 0 }, net_writer);
+}
+
+#[test]
+fn chat_messages_are_validated_and_formatted() {
+    assert_eq!(validate_message("  hello   world ").ok().unwrap(), "hello world");
+    assert!(validate_message("§cred").is_err());
+    assert!(validate_message(&"a".repeat(MAX_MESSAGE_LENGTH + 1)).is_err());
+
+    let text = format_message("&e{name}&r: {message}", "Steve", "&c{name}");
+    assert_eq!(text, "§eSteve§r: &c{name}");
+    assert_eq!(format_message("{name}: {message}", "{message}", "hi"), "{message}: hi");
+    assert_eq!(format_message("{} {name", "Steve", "hi"), "{} {name");
+    assert_eq!(
+        ChatComponent::from_legacy(&text).to_plain_text(),
+        "Steve: &c{name}"
+    );
 }
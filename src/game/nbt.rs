@@ -1,13 +1,13 @@
 use crate::data_writer::DataWriter;
 use std::any::Any;
 use std::collections::HashMap;
-use std::io::{Error, Read};
+use std::io::{Error, ErrorKind, Read};
 use std::iter::Map;
 use std::string::FromUtf8Error;
 use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
 use fxhash::{FxBuildHasher, FxHashMap};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum NBTTag {
     End,
     Byte { byte: i8 },
@@ -48,6 +48,13 @@ impl NBTTag {
         }
     }
 
+    pub fn get(&self, name: &str) -> Option<&NBTTag> {
+        match self {
+            NBTTag::Compound { compound } => compound.get(name),
+            _ => None,
+        }
+    }
+
     pub fn write<'a>(&self, data: &'a mut Vec<u8>, name: Option<&String>, include_type_id: bool) {
         if include_type_id {
             data.push(self.type_id());
@@ -102,6 +109,14 @@ impl NBTTag {
     }
 
     pub fn read<R: Read>(data: &mut R, read_name: bool, type_id: Option<u8>) -> Result<(NBTTag, Option<String>), NBTParseError> {
+        NBTTag::read_nested(data, read_name, type_id, 0)
+    }
+
+    fn read_nested<R: Read>(data: &mut R, read_name: bool, type_id: Option<u8>, depth: usize) -> Result<(NBTTag, Option<String>), NBTParseError> {
+        if depth > MAX_DEPTH {
+            return Err(NBTParseError::TooDeep);
+        }
+
         let type_id = match type_id {
             None => data.read_u8()?,
             Some(x) => x
@@ -114,10 +129,8 @@ impl NBTTag {
         let mut string = None;
 
         if read_name {
-            let name_size = data.read_i16::<BigEndian>()?;
-            let mut vec = vec![0u8; name_size as usize];
-            data.read(&mut vec);
-            string = Some(String::from_utf8(vec)?);
+            let name_size = data.read_u16::<BigEndian>()?;
+            string = Some(String::from_utf8(read_bytes(data, name_size as usize)?)?);
         }
 
         match type_id {
@@ -128,25 +141,27 @@ impl NBTTag {
             5 => Ok((NBTTag::Float {float: data.read_f32::<BigEndian>()?}, string)),
             6 => Ok((NBTTag::Double {double: data.read_f64::<BigEndian>()?}, string)),
             7 => {
-                let size = data.read_i32::<BigEndian>()?;
-                let mut bytes = vec![0u8; size as usize];
-                data.read(&mut bytes);
-                Ok((NBTTag::ByteArray {bytes}, string))
+                let size = read_length(data)?;
+                Ok((NBTTag::ByteArray {bytes: read_bytes(data, size)?}, string))
             }
             8 => {
-                let name_size = data.read_i16::<BigEndian>()?;
-                let mut vec = vec![0u8; name_size as usize];
-                data.read(&mut vec);
-                Ok((NBTTag::String {string: String::from_utf8(vec)?}, string))
+                let size = data.read_u16::<BigEndian>()?;
+                Ok((NBTTag::String {string: String::from_utf8(read_bytes(data, size as usize)?)?}, string))
             }
             9 => {
                 let type_id = data.read_u8()?;
-                let length = data.read_i32::<BigEndian>()?;
+                let length = read_length(data)?;
 
-                let mut list = Vec::with_capacity(length as usize);
+                //End tags take no bytes, so a long list of them would never run out of input
+                if type_id == 0 && length > 0 {
+                    return Err(NBTParseError::InvalidTypeId);
+                }
+
+                //Not preallocated, the length comes from the input and is only trusted as far as the bytes go
+                let mut list = Vec::new();
 
                 for _ in 0..length {
-                    let element = NBTTag::read(data, false, Some(type_id))?;
+                    let element = NBTTag::read_nested(data, false, Some(type_id), depth + 1)?;
                     list.push(element.0);
                 }
 
@@ -156,7 +171,7 @@ impl NBTTag {
                 let mut map = FxHashMap::default();
 
                 loop {
-                    let element = NBTTag::read(data, true, None)?;
+                    let element = NBTTag::read_nested(data, true, None, depth + 1)?;
 
                     if let NBTTag::End = element.0 {
                         break;
@@ -168,21 +183,21 @@ impl NBTTag {
                 Ok((NBTTag::Compound {compound: map}, string))
             }
             11 => {
-                let length = data.read_i32::<BigEndian>()?;
-                let mut array = vec![0i32; length as usize];
+                let length = read_length(data)?;
+                let mut array = Vec::new();
 
-                for i in 0..length as usize {
-                    array[i] = data.read_i32::<BigEndian>()?;
+                for _ in 0..length {
+                    array.push(data.read_i32::<BigEndian>()?);
                 }
 
                 Ok((NBTTag::IntArray {array}, string))
             }
             12 => {
-                let length = data.read_i32::<BigEndian>()?;
-                let mut array = vec![0i64; length as usize];
+                let length = read_length(data)?;
+                let mut array = Vec::new();
 
-                for i in 0..length as usize {
-                    array[i] = data.read_i64::<BigEndian>()?;
+                for _ in 0..length {
+                    array.push(data.read_i64::<BigEndian>()?);
                 }
 
                 Ok((NBTTag::LongArray {array}, string))
@@ -192,14 +207,53 @@ impl NBTTag {
     }
 }
 
+//Compounds and lists nested deeper than this are rejected, vanilla allows 512 but that much
+//recursion doesn't fit in a worker thread's stack
+pub const MAX_DEPTH: usize = 64;
+
+//Array and list lengths are signed ints, but can't be negative
+fn read_length<R: Read>(data: &mut R) -> Result<usize, NBTParseError> {
+    let length = data.read_i32::<BigEndian>()?;
+    if length < 0 {
+        return Err(NBTParseError::NegativeLength(length));
+    }
+    Ok(length as usize)
+}
+
+//Reads up to what's actually there instead of allocating the claimed length up front
+fn read_bytes<R: Read>(data: &mut R, length: usize) -> Result<Vec<u8>, NBTParseError> {
+    let mut bytes = Vec::new();
+    data.take(length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < length {
+        return Err(NBTParseError::IOError(Error::from(ErrorKind::UnexpectedEof)));
+    }
+    Ok(bytes)
+}
+
 #[derive(Debug)]
 pub enum NBTParseError {
     InvalidTypeId,
+    NegativeLength(i32),
+    //Compounds and lists nested more than MAX_DEPTH levels
+    TooDeep,
     IOError(std::io::Error),
     UTF8Error(FromUtf8Error),
     Test
 }
 
+impl NBTParseError {
+    pub fn reason(&self) -> String {
+        match self {
+            NBTParseError::InvalidTypeId => "Invalid tag type".to_string(),
+            NBTParseError::NegativeLength(length) => format!("Negative length {}", length),
+            NBTParseError::TooDeep => "Tags nested too deep".to_string(),
+            NBTParseError::IOError(e) => e.to_string(),
+            NBTParseError::UTF8Error(e) => e.to_string(),
+            NBTParseError::Test => "Test".to_string(),
+        }
+    }
+}
+
 impl From<std::io::Error> for NBTParseError {
     fn from(e: Error) -> Self {
         NBTParseError::IOError(e)
@@ -210,4 +264,48 @@ impl From<FromUtf8Error> for NBTParseError {
     fn from(e: FromUtf8Error) -> Self {
         NBTParseError::UTF8Error(e)
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn rejects_malformed_nbt() {
+    //Named root compound holding a single tag named "a"
+    fn compound(tag: &[u8]) -> Vec<u8> {
+        let mut data = vec![10, 0, 0, tag[0], 0, 1, b'a'];
+        data.extend_from_slice(&tag[1..]);
+        data.push(0);
+        data
+    }
+
+    //Byte array claiming 2^31 - 1 bytes
+    let data = compound(&[7, 0x7F, 0xFF, 0xFF, 0xFF, 1, 2]);
+    assert!(matches!(NBTTag::read(&mut &data[..], true, None), Err(NBTParseError::IOError(_))));
+
+    let data = compound(&[7, 0xFF, 0xFF, 0xFF, 0xFF]);
+    assert!(matches!(NBTTag::read(&mut &data[..], true, None), Err(NBTParseError::NegativeLength(-1))));
+
+    let data = compound(&[11, 0x80, 0, 0, 0]);
+    assert!(matches!(NBTTag::read(&mut &data[..], true, None), Err(NBTParseError::NegativeLength(_))));
+
+    let data = compound(&[12, 0x7F, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 1]);
+    assert!(matches!(NBTTag::read(&mut &data[..], true, None), Err(NBTParseError::IOError(_))));
+
+    //List of end tags that would never consume any input
+    let data = compound(&[9, 0, 0x7F, 0xFF, 0xFF, 0xFF]);
+    assert!(matches!(NBTTag::read(&mut &data[..], true, None), Err(NBTParseError::InvalidTypeId)));
+
+    //Lists of lists nested past the limit
+    let mut data = vec![9, 0, 0];
+    for _ in 0..MAX_DEPTH + 1 {
+        data.extend_from_slice(&[9, 0, 0, 0, 1]);
+    }
+    assert!(matches!(NBTTag::read(&mut &data[..], true, None), Err(NBTParseError::TooDeep)));
+
+    let mut tag = FxHashMap::default();
+    tag.insert("a".to_string(), NBTTag::IntArray {array: vec![1, -2, 3]});
+    let mut data = Vec::new();
+    NBTTag::Compound {compound: tag}.write(&mut data, Some(&String::new()), true);
+    match NBTTag::read(&mut &data[..], true, None).unwrap().0.get("a") {
+        Some(NBTTag::IntArray {array}) => assert_eq!(array, &vec![1, -2, 3]),
+        _ => panic!("IntArray not read back"),
+    }
+}
@@ -1,63 +1,158 @@
-use std::time::Instant;
-use crate::data_reader::DataReader;
+use crate::data_reader::{DataReader, PacketError};
 use crate::data_writer::DataWriter;
 use crate::game::chat::ChatComponent;
+use crate::game::codec::{
+    Bytes, Codec, FixedPoint, HyphenatedUuid, IntArray, Optional, OptionalNbt, PitchYaw, Plain,
+    RemainingArray, RemainingBytes, ShortArray, Trailing, VarInt, VarIntArray, VarLong,
+};
 use crate::game::nbt::NBTTag;
 use crate::game::world::angle::Angle;
+use crate::game::world::chunk::ChunkPos;
 use crate::game::world::coords::{Point, Position};
 use crate::net::network_manager::{ConnectionState, PlayerClient};
 use json::JsonValue;
-use regex::internal::Inst;
 use uuid::Uuid;
-use crate::game::world::chunk::ChunkPos;
-use crate::game::world::world::World;
 
-pub enum Packet {
-    Handshake {
-        protocol_version: i32,
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    //Sent by the server
+    Clientbound,
+    //Sent by the client
+    Serverbound,
+}
+
+//Generates the Packet enum and its decoder and encoder from the table below
+//Each packet lists where it's registered ([state direction id], some are in more than one place)
+//and its fields in the order they go on the wire, the codec defaults to Plain
+macro_rules! packets {
+    ($(
+        $name:ident [$($state:ident $direction:ident $id:literal),+] $({
+            $($field:ident: $ty:ty $(as $codec:ty)?),* $(,)?
+        })?
+    ),* $(,)?) => {
+        pub enum Packet {
+            $($name $({ $($field: $ty),* })?),*
+        }
+
+        //Variant of a packet without its fields, to match on it without building one
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        pub enum PacketKind {
+            $($name),*
+        }
+
+        impl Packet {
+            //Name of every packet in the table, so the tests can check all of them have a sample
+            #[cfg(test)]
+            pub const NAMES: &'static [&'static str] = &[$(stringify!($name)),*];
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Packet::$name { .. } => stringify!($name)),*
+                }
+            }
+
+            pub fn kind(&self) -> PacketKind {
+                match self {
+                    $(Packet::$name { .. } => PacketKind::$name),*
+                }
+            }
+
+            //Id of the packet in this state and direction, the server goes through registration
+            #[cfg(test)]
+            pub fn id(&self, state: ConnectionState, direction: Direction) -> Option<i32> {
+                match (self, state, direction) {
+                    $($((Packet::$name { .. }, ConnectionState::$state, Direction::$direction) => Some($id),)+)*
+                    _ => None,
+                }
+            }
+
+            //First place where the packet is registered for this direction
+            pub fn registration(&self, direction: Direction) -> Option<(ConnectionState, i32)> {
+                match self {
+                    $(Packet::$name { .. } => {
+                        $(if direction == Direction::$direction {
+                            return Some((ConnectionState::$state, $id));
+                        })+
+                        None
+                    })*
+                }
+            }
+
+            pub fn decode(
+                id: i32,
+                reader: &mut DataReader,
+                state: ConnectionState,
+                direction: Direction,
+            ) -> Result<Packet, PacketError> {
+                match (state, direction, id) {
+                    $($((ConnectionState::$state, Direction::$direction, $id))|+ => Ok(Packet::$name $({
+                        $($field: <field_codec!($ty $(, $codec)?) as Codec<$ty>>::read(reader)?),*
+                    })?),)*
+                    _ => Err(PacketError::UnknownId { id, state }),
+                }
+            }
+
+            pub fn write_fields(&self, writer: &mut DataWriter) {
+                match self {
+                    $(Packet::$name $({ $($field),* })? => {
+                        $($(<field_codec!($ty $(, $codec)?) as Codec<$ty>>::write($field, writer);)*)?
+                    })*
+                }
+            }
+        }
+    };
+}
+
+packets! {
+    //Handshaking
+    Handshake [Handshaking Serverbound 0x00] {
+        protocol_version: i32 as VarInt,
         server_address: String,
         server_port: u16,
         next_state: u8,
     },
 
     //Status
-    StatusRequest,
-    Ping {
+    StatusRequest [Status Serverbound 0x00],
+    Ping [Status Serverbound 0x01] {
         ping: i64,
     },
-    StatusResponse {
+    StatusResponse [Status Clientbound 0x00] {
         json: JsonValue,
     },
-    Pong {
+    Pong [Status Clientbound 0x01] {
         pong: i64,
     },
 
     //Login
-    LoginStart {
+    LoginStart [Login Serverbound 0x00] {
         nickname: String,
     },
-    EncryptionRequest {
-        server: String,
-        public_key: Vec<u8>,
-        verify_token: [u8; 4],
+    EncryptionResponse [Login Serverbound 0x01] {
+        shared_secret: Vec<u8> as Bytes,
+        verify_token: Vec<u8> as Bytes,
+    },
+    DisconnectLogin [Login Clientbound 0x00] {
+        reason: ChatComponent,
     },
-    EncryptionResponse {
-        shared_secret: Vec<u8>,
-        verify_token: Vec<u8>,
+    EncryptionRequest [Login Clientbound 0x01] {
+        server: String,
+        public_key: Vec<u8> as Bytes,
+        verify_token: Vec<u8> as Bytes,
     },
-    LoginSuccess {
-        uuid: Uuid,
+    LoginSuccess [Login Clientbound 0x02] {
+        uuid: Uuid as HyphenatedUuid,
         nickname: String,
     },
-    DisconnectLogin {
-        reason: ChatComponent,
+    SetCompression [Login Clientbound 0x03, Play Clientbound 0x46] {
+        threshold: i32 as VarInt,
     },
 
-    //Play
-    KeepAlive {
-        id: i32,
+    //Play, sent by the server
+    KeepAlive [Play Clientbound 0x00, Play Serverbound 0x00] {
+        id: i32 as VarInt,
     },
-    JoinGame {
+    JoinGame [Play Clientbound 0x01] {
         entity_id: i32,
         gamemode: u8,
         dimension: i8,
@@ -66,20 +161,34 @@ pub enum Packet {
         level_type: String,
         reduced_debug_info: bool,
     },
-    SpawnPosition {
-        location: Position,
+    ServerChatMessage [Play Clientbound 0x02] {
+        component: ChatComponent,
+        pos: u8,
     },
-    HeldItemChange {
-        slot: u8,
+    TimeUpdate [Play Clientbound 0x03] {
+        world_age: i64,
+        time_of_day: i64,
     },
-    PlayerInfo {
-        action_id: i32,
-        players: Vec<PlayerInfoPlayer>,
+    EntityEquipment [Play Clientbound 0x04] {
+        entity_id: i32 as VarInt,
+        slot: i16,
+        item: Slot,
     },
-    DisconnectPlay {
-        reason: ChatComponent,
+    SpawnPosition [Play Clientbound 0x05] {
+        location: Position,
+    },
+    UpdateHealth [Play Clientbound 0x06] {
+        health: f32,
+        food: i32 as VarInt,
+        food_saturation: f32,
     },
-    PlayerPositionAndLook {
+    Respawn [Play Clientbound 0x07] {
+        dimension: i32,
+        difficulty: u8,
+        gamemode: u8,
+        level_type: String,
+    },
+    PlayerPositionAndLook [Play Clientbound 0x08] {
         x: f64,
         y: f64,
         z: f64,
@@ -87,427 +196,811 @@ pub enum Packet {
         pitch: f32,
         flags: u8,
     },
-    WorldBorder {
-        action: WorldBorderAction,
+    HeldItemChange [Play Clientbound 0x09] {
+        slot: u8,
     },
-    TimeUpdate {
-        world_age: i64,
-        time_of_day: i64,
+    UseBed [Play Clientbound 0x0A] {
+        entity_id: i32 as VarInt,
+        location: Position,
     },
-    WindowItems {
-        window_id: u8,
-        slots: Vec<Slot>,
-    },
-    //ChunkData and MapChunkBulk were moved to ExtendedPacket's
-    // ChunkData {
-    //     x: i32,
-    //     y: i32,
-    //     ground_up_continuous: bool,
-    //     bitmask: u16,
-    //     //Tests only
-    //     data: Vec<u8>,
-    // },
-    // MapChunkBulk {
-    //     world: &'a World,
-    //     center: ChunkPos,
-    //     radius: u8
-    // },
-    ClientChatMessage {
-        message: String,
+    ServerAnimation [Play Clientbound 0x0B] {
+        entity_id: i32 as VarInt,
+        animation: u8,
     },
-    ServerChatMessage {
-        component: ChatComponent,
-        pos: u8,
+    SpawnPlayer [Play Clientbound 0x0C] {
+        entity_id: i32 as VarInt,
+        uuid: Uuid,
+        point: Point as FixedPoint,
+        angle: Angle,
+        current_item: i16,
+        metadata: Vec<MetadataEntry> as Metadata,
     },
-    SpawnObject {
-        id: i32,
+    CollectItem [Play Clientbound 0x0D] {
+        collected_entity_id: i32 as VarInt,
+        collector_entity_id: i32 as VarInt,
+    },
+    SpawnObject [Play Clientbound 0x0E] {
+        id: i32 as VarInt,
         object: u8,
-        point: Point,
+        point: Point as FixedPoint,
+        angle: Angle as PitchYaw,
+        data: i32,
+        //Only sent when data isn't 0
+        vel_x: Option<i16> as Trailing,
+        vel_y: Option<i16> as Trailing,
+        vel_z: Option<i16> as Trailing,
+    },
+    SpawnMob [Play Clientbound 0x0F] {
+        entity_id: i32 as VarInt,
+        mob_type: u8,
+        point: Point as FixedPoint,
+        angle: Angle,
+        head_pitch: u8,
+        vel_x: i16,
+        vel_y: i16,
+        vel_z: i16,
+        metadata: Vec<MetadataEntry> as Metadata,
+    },
+    SpawnPainting [Play Clientbound 0x10] {
+        entity_id: i32 as VarInt,
+        title: String,
+        location: Position,
+        direction: u8,
+    },
+    SpawnExperienceOrb [Play Clientbound 0x11] {
+        entity_id: i32 as VarInt,
+        point: Point as FixedPoint,
+        count: i16,
+    },
+    EntityVelocity [Play Clientbound 0x12] {
+        entity_id: i32 as VarInt,
+        vel_x: i16,
+        vel_y: i16,
+        vel_z: i16,
+    },
+    DestroyEntities [Play Clientbound 0x13] {
+        entity_ids: Vec<i32> as VarIntArray<VarInt>,
+    },
+    Entity [Play Clientbound 0x14] {
+        entity_id: i32 as VarInt,
+    },
+    EntityRelativeMove [Play Clientbound 0x15] {
+        entity_id: i32 as VarInt,
+        delta_x: i8,
+        delta_y: i8,
+        delta_z: i8,
+        on_ground: bool,
+    },
+    EntityLook [Play Clientbound 0x16] {
+        entity_id: i32 as VarInt,
+        angle: Angle,
+        on_ground: bool,
+    },
+    EntityLookAndRelativeMove [Play Clientbound 0x17] {
+        entity_id: i32 as VarInt,
+        delta_x: i8,
+        delta_y: i8,
+        delta_z: i8,
         angle: Angle,
+        on_ground: bool,
+    },
+    EntityTeleport [Play Clientbound 0x18] {
+        entity_id: i32 as VarInt,
+        point: Point as FixedPoint,
+        angle: Angle,
+        on_ground: bool,
+    },
+    EntityHeadLook [Play Clientbound 0x19] {
+        entity_id: i32 as VarInt,
+        head_yaw: u8,
+    },
+    EntityStatus [Play Clientbound 0x1A] {
+        entity_id: i32,
+        status: i8,
+    },
+    AttachEntity [Play Clientbound 0x1B] {
+        entity_id: i32,
+        vehicle_id: i32,
+        leash: bool,
+    },
+    EntityMetadata [Play Clientbound 0x1C] {
+        entity_id: i32 as VarInt,
+        metadata: Vec<MetadataEntry> as Metadata,
+    },
+    EntityEffect [Play Clientbound 0x1D] {
+        entity_id: i32 as VarInt,
+        effect_id: i8,
+        amplifier: i8,
+        duration: i32 as VarInt,
+        hide_particles: bool,
+    },
+    RemoveEntityEffect [Play Clientbound 0x1E] {
+        entity_id: i32 as VarInt,
+        effect_id: i8,
+    },
+    SetExperience [Play Clientbound 0x1F] {
+        experience_bar: f32,
+        level: i32 as VarInt,
+        total_experience: i32 as VarInt,
+    },
+    EntityProperties [Play Clientbound 0x20] {
+        entity_id: i32 as VarInt,
+        properties: Vec<EntityProperty> as IntArray,
+    },
+    //Chunks are usually sent with ExtendedPacket, that doesn't copy the data
+    ChunkData [Play Clientbound 0x21] {
+        x: i32,
+        z: i32,
+        ground_up_continuous: bool,
+        bitmask: u16,
+        data: Vec<u8> as Bytes,
+    },
+    MultiBlockChange [Play Clientbound 0x22] {
+        chunk: ChunkPos,
+        records: Vec<BlockChangeRecord> as VarIntArray,
+    },
+    BlockChange [Play Clientbound 0x23] {
+        location: Position,
+        block_id: i32 as VarInt,
+    },
+    BlockAction [Play Clientbound 0x24] {
+        location: Position,
+        action_id: u8,
+        action_param: u8,
+        block_type: i32 as VarInt,
+    },
+    BlockBreakAnimation [Play Clientbound 0x25] {
+        entity_id: i32 as VarInt,
+        location: Position,
+        destroy_stage: i8,
+    },
+    MapChunkBulk [Play Clientbound 0x26] {
+        sky_light: bool,
+        chunks: Vec<ChunkMeta> as VarIntArray,
+        data: Vec<u8> as RemainingBytes,
+    },
+    Explosion [Play Clientbound 0x27] {
+        x: f32,
+        y: f32,
+        z: f32,
+        radius: f32,
+        records: Vec<ExplosionRecord> as IntArray,
+        motion_x: f32,
+        motion_y: f32,
+        motion_z: f32,
+    },
+    Effect [Play Clientbound 0x28] {
+        effect_id: i32,
+        location: Position,
         data: i32,
-        vel_x: Option<i16>,
-        vel_y: Option<i16>,
-        vel_z: Option<i16>,
+        disable_relative_volume: bool,
+    },
+    SoundEffect [Play Clientbound 0x29] {
+        sound_name: String,
+        //Multiplied by 8
+        x: i32,
+        y: i32,
+        z: i32,
+        volume: f32,
+        pitch: u8,
+    },
+    Particle [Play Clientbound 0x2A] {
+        particle_id: i32,
+        long_distance: bool,
+        x: f32,
+        y: f32,
+        z: f32,
+        offset_x: f32,
+        offset_y: f32,
+        offset_z: f32,
+        particle_data: f32,
+        count: i32,
+        //Only iconcrack, blockcrack and blockdust have it
+        data: Vec<i32> as RemainingArray<VarInt>,
+    },
+    ChangeGameState [Play Clientbound 0x2B] {
+        reason: u8,
+        value: f32,
+    },
+    SpawnGlobalEntity [Play Clientbound 0x2C] {
+        entity_id: i32 as VarInt,
+        entity_type: i8,
+        point: Point as FixedPoint,
+    },
+    OpenWindow [Play Clientbound 0x2D] {
+        window_id: u8,
+        window_type: String,
+        title: ChatComponent,
+        slot_count: u8,
+        //Only for EntityHorse
+        entity_id: Option<i32> as Trailing,
+    },
+    CloseWindow [Play Clientbound 0x2E, Play Serverbound 0x0D] {
+        window_id: u8,
+    },
+    SetSlot [Play Clientbound 0x2F] {
+        window_id: i8,
+        slot: i16,
+        item: Slot,
+    },
+    WindowItems [Play Clientbound 0x30] {
+        window_id: u8,
+        slots: Vec<Slot> as ShortArray,
+    },
+    WindowProperty [Play Clientbound 0x31] {
+        window_id: u8,
+        property: i16,
+        value: i16,
+    },
+    ConfirmTransaction [Play Clientbound 0x32, Play Serverbound 0x0F] {
+        window_id: i8,
+        action_number: i16,
+        accepted: bool,
+    },
+    UpdateSign [Play Clientbound 0x33, Play Serverbound 0x12] {
+        location: Position,
+        line_1: Box<ChatComponent>,
+        line_2: Box<ChatComponent>,
+        line_3: Box<ChatComponent>,
+        line_4: Box<ChatComponent>,
+    },
+    Map [Play Clientbound 0x34] {
+        item_damage: i32 as VarInt,
+        scale: i8,
+        icons: Vec<MapIcon> as VarIntArray,
+        update: Option<MapUpdate>,
+    },
+    UpdateBlockEntity [Play Clientbound 0x35] {
+        location: Position,
+        action: u8,
+        nbt: Option<NBTTag> as OptionalNbt,
+    },
+    OpenSignEditor [Play Clientbound 0x36] {
+        location: Position,
+    },
+    Statistics [Play Clientbound 0x37] {
+        statistics: Vec<Statistic> as VarIntArray,
+    },
+    PlayerInfo [Play Clientbound 0x38] {
+        //Every player must have the same action, it's only sent once
+        players: Vec<PlayerInfoPlayer>,
+    },
+    PlayerAbilities [Play Clientbound 0x39, Play Serverbound 0x13] {
+        flags: i8,
+        flying_speed: f32,
+        //Field of view modifier when sent by the server, walking speed when sent by the client
+        walking_speed: f32,
+    },
+    ServerTabComplete [Play Clientbound 0x3A] {
+        matches: Vec<String> as VarIntArray,
+    },
+    ScoreboardObjective [Play Clientbound 0x3B] {
+        name: String,
+        action: ObjectiveAction,
+    },
+    UpdateScore [Play Clientbound 0x3C] {
+        score_name: String,
+        action: ScoreAction,
+    },
+    DisplayScoreboard [Play Clientbound 0x3D] {
+        position: i8,
+        score_name: String,
+    },
+    Teams [Play Clientbound 0x3E] {
+        team_name: String,
+        action: TeamAction,
+    },
+    PluginMessage [Play Clientbound 0x3F, Play Serverbound 0x17] {
+        channel: String,
+        data: Vec<u8> as RemainingBytes,
+    },
+    DisconnectPlay [Play Clientbound 0x40] {
+        reason: ChatComponent,
+    },
+    ServerDifficulty [Play Clientbound 0x41] {
+        difficulty: u8,
+    },
+    CombatEvent [Play Clientbound 0x42] {
+        event: CombatEventKind,
+    },
+    Camera [Play Clientbound 0x43] {
+        camera_id: i32 as VarInt,
+    },
+    WorldBorder [Play Clientbound 0x44] {
+        action: WorldBorderAction,
+    },
+    Title [Play Clientbound 0x45] {
+        action: TitleAction,
+    },
+    PlayerListHeaderFooter [Play Clientbound 0x47] {
+        header: ChatComponent,
+        footer: ChatComponent,
+    },
+    ResourcePackSend [Play Clientbound 0x48] {
+        url: String,
+        hash: String,
+    },
+    UpdateEntityNBT [Play Clientbound 0x49] {
+        entity_id: i32 as VarInt,
+        tag: NBTTag,
     },
-}
-
-pub struct Slot {
-    pub item_id: i16,
-    pub item_count: Option<i8>,
-    pub item_damage: Option<i16>,
-    pub nbt: Option<NBTTag>,
-}
 
-pub enum WorldBorderAction {
-    SetSize {
-        radius: f64,
+    //Play, sent by the client
+    ClientChatMessage [Play Serverbound 0x01] {
+        message: String,
+    },
+    UseEntity [Play Serverbound 0x02] {
+        target: i32 as VarInt,
+        action: UseEntityAction,
     },
-    LerpSize {
-        old_radius: f64,
-        new_radius: f64,
-        speed: i32,
+    Player [Play Serverbound 0x03] {
+        on_ground: bool,
     },
-    SetCenter {
+    PlayerPosition [Play Serverbound 0x04] {
         x: f64,
+        y: f64,
         z: f64,
+        on_ground: bool,
+    },
+    PlayerLook [Play Serverbound 0x05] {
+        yaw: f32,
+        pitch: f32,
+        on_ground: bool,
     },
-    Initialize {
+    ClientPlayerPositionAndLook [Play Serverbound 0x06] {
         x: f64,
+        y: f64,
         z: f64,
-        old_radius: f64,
-        new_radius: f64,
-        speed: i64,
-        portal_teleport_boundary: i32,
-        warning_time: i32,
-        warning_blocks: i32,
+        yaw: f32,
+        pitch: f32,
+        on_ground: bool,
+    },
+    PlayerDigging [Play Serverbound 0x07] {
+        status: i8,
+        location: Position,
+        face: i8,
+    },
+    PlayerBlockPlacement [Play Serverbound 0x08] {
+        location: Position,
+        face: i8,
+        held_item: Slot,
+        cursor_x: i8,
+        cursor_y: i8,
+        cursor_z: i8,
+    },
+    ClientHeldItemChange [Play Serverbound 0x09] {
+        slot: i16,
+    },
+    ClientAnimation [Play Serverbound 0x0A],
+    EntityAction [Play Serverbound 0x0B] {
+        entity_id: i32 as VarInt,
+        action_id: i32 as VarInt,
+        jump_boost: i32 as VarInt,
+    },
+    SteerVehicle [Play Serverbound 0x0C] {
+        sideways: f32,
+        forward: f32,
+        flags: u8,
+    },
+    ClickWindow [Play Serverbound 0x0E] {
+        window_id: u8,
+        slot: i16,
+        button: i8,
+        action_number: i16,
+        mode: i8,
+        clicked_item: Slot,
+    },
+    CreativeInventoryAction [Play Serverbound 0x10] {
+        slot: i16,
+        clicked_item: Slot,
+    },
+    EnchantItem [Play Serverbound 0x11] {
+        window_id: i8,
+        enchantment: i8,
+    },
+    ClientTabComplete [Play Serverbound 0x14] {
+        text: String,
+        looked_at_block: Option<Position> as Optional,
+    },
+    ClientSettings [Play Serverbound 0x15] {
+        locale: String,
+        view_distance: i8,
+        chat_mode: i8,
+        chat_colors: bool,
+        displayed_skin_parts: u8,
+    },
+    ClientStatus [Play Serverbound 0x16] {
+        action_id: i32 as VarInt,
     },
-    SetWarningTime {
-        warning_time: i32,
+    Spectate [Play Serverbound 0x18] {
+        target_player: Uuid,
     },
-    SetWarningBlocks {
-        warning_blocks: i32,
+    ResourcePackStatus [Play Serverbound 0x19] {
+        hash: String,
+        result: i32 as VarInt,
     },
 }
 
+packet_structs! {
+    #[derive(Clone)]
+    pub struct PlayerInfoProperties {
+        pub name: String,
+        pub value: String,
+        pub signature: Option<String> as Optional,
+    }
+
+    pub struct ChunkMeta {
+        pub pos: ChunkPos,
+        pub bitmask: u16,
+    }
+
+    pub struct EntityProperty {
+        pub key: String,
+        pub value: f64,
+        pub modifiers: Vec<AttributeModifier> as VarIntArray,
+    }
+
+    pub struct AttributeModifier {
+        pub uuid: Uuid,
+        pub amount: f64,
+        pub operation: i8,
+    }
+
+    pub struct BlockChangeRecord {
+        //Chunk relative, x in the high nibble and z in the low one
+        pub horizontal_position: u8,
+        pub y: u8,
+        pub block_id: i32 as VarInt,
+    }
+
+    pub struct ExplosionRecord {
+        pub x: i8,
+        pub y: i8,
+        pub z: i8,
+    }
+
+    pub struct MapIcon {
+        //Direction in the high nibble and type in the low one
+        pub direction_and_type: i8,
+        pub x: i8,
+        pub z: i8,
+    }
+
+    pub struct MapUpdate {
+        pub columns: u8,
+        pub rows: u8,
+        pub x: u8,
+        pub z: u8,
+        pub data: Vec<u8> as Bytes,
+    }
+
+    pub struct Statistic {
+        pub name: String,
+        pub value: i32 as VarInt,
+    }
+
+    pub struct TeamInfo {
+        pub display_name: String,
+        pub prefix: String,
+        pub suffix: String,
+        pub friendly_fire: i8,
+        pub name_tag_visibility: String,
+        pub color: i8,
+    }
+}
+
+packet_enums! {
+    pub enum WorldBorderAction (i32 as VarInt) {
+        0 => SetSize {
+            radius: f64,
+        },
+        1 => LerpSize {
+            old_radius: f64,
+            new_radius: f64,
+            speed: i64 as VarLong,
+        },
+        2 => SetCenter {
+            x: f64,
+            z: f64,
+        },
+        3 => Initialize {
+            x: f64,
+            z: f64,
+            old_radius: f64,
+            new_radius: f64,
+            speed: i64 as VarLong,
+            portal_teleport_boundary: i32 as VarInt,
+            warning_time: i32 as VarInt,
+            warning_blocks: i32 as VarInt,
+        },
+        4 => SetWarningTime {
+            warning_time: i32 as VarInt,
+        },
+        5 => SetWarningBlocks {
+            warning_blocks: i32 as VarInt,
+        },
+    }
+
+    pub enum PlayerInfoAction (i32 as VarInt) {
+        0 => AddPlayer {
+            name: String,
+            properties: Vec<PlayerInfoProperties> as VarIntArray,
+            gamemode: i32 as VarInt,
+            ping: i32 as VarInt,
+            display_name: Option<ChatComponent> as Optional,
+        },
+        1 => UpdateGameMode {
+            gamemode: i32 as VarInt,
+        },
+        2 => UpdateLatency {
+            ping: i32 as VarInt,
+        },
+        3 => UpdateDisplayName {
+            display_name: Option<ChatComponent> as Optional,
+        },
+        4 => RemovePlayer,
+    }
+
+    pub enum UseEntityAction (i32 as VarInt) {
+        0 => Interact,
+        1 => Attack,
+        2 => InteractAt {
+            x: f32,
+            y: f32,
+            z: f32,
+        },
+    }
+
+    pub enum ObjectiveAction (i8) {
+        0 => Create {
+            value: String,
+            objective_type: String,
+        },
+        1 => Remove,
+        2 => Update {
+            value: String,
+            objective_type: String,
+        },
+    }
+
+    pub enum ScoreAction (i8) {
+        0 => Update {
+            objective_name: String,
+            value: i32 as VarInt,
+        },
+        1 => Remove {
+            objective_name: String,
+        },
+    }
+
+    pub enum TeamAction (i8) {
+        0 => Create {
+            info: TeamInfo,
+            players: Vec<String> as VarIntArray,
+        },
+        1 => Remove,
+        2 => UpdateInfo {
+            info: TeamInfo,
+        },
+        3 => AddPlayers {
+            players: Vec<String> as VarIntArray,
+        },
+        4 => RemovePlayers {
+            players: Vec<String> as VarIntArray,
+        },
+    }
+
+    pub enum CombatEventKind (i32 as VarInt) {
+        0 => EnterCombat,
+        1 => EndCombat {
+            duration: i32 as VarInt,
+            entity_id: i32,
+        },
+        2 => EntityDead {
+            player_id: i32 as VarInt,
+            entity_id: i32,
+            message: String,
+        },
+    }
+
+    pub enum TitleAction (i32 as VarInt) {
+        0 => SetTitle {
+            text: ChatComponent,
+        },
+        1 => SetSubtitle {
+            text: ChatComponent,
+        },
+        2 => SetTimes {
+            fade_in: i32,
+            stay: i32,
+            fade_out: i32,
+        },
+        3 => Hide,
+        4 => Reset,
+    }
+
+    //The type goes in the 3 high bits of the entry key, so the tag is written by MetadataEntry
+    #[derive(Clone)]
+    pub enum MetadataValue (u8) {
+        0 => Byte {
+            value: i8,
+        },
+        1 => Short {
+            value: i16,
+        },
+        2 => Int {
+            value: i32,
+        },
+        3 => Float {
+            value: f32,
+        },
+        4 => String {
+            value: String,
+        },
+        5 => Slot {
+            value: Slot,
+        },
+        6 => Position {
+            x: i32,
+            y: i32,
+            z: i32,
+        },
+        7 => Rotation {
+            pitch: f32,
+            yaw: f32,
+            roll: f32,
+        },
+    }
+}
+
+#[derive(Clone)]
+pub struct Slot {
+    pub item_id: i16,
+    pub item_count: Option<i8>,
+    pub item_damage: Option<i16>,
+    pub nbt: Option<NBTTag>,
+}
+
+//An item id of -1 is an empty slot and nothing else follows
+impl Codec<Slot> for Plain {
+    fn read(reader: &mut DataReader) -> Result<Slot, PacketError> {
+        let item_id = reader.read_i16()?;
+        if item_id < 0 {
+            return Ok(Slot {
+                item_id,
+                item_count: None,
+                item_damage: None,
+                nbt: None,
+            });
+        }
+        Ok(Slot {
+            item_id,
+            item_count: Some(reader.read_i8()?),
+            item_damage: Some(reader.read_i16()?),
+            nbt: OptionalNbt::read(reader)?,
+        })
+    }
+
+    fn write(value: &Slot, writer: &mut DataWriter) {
+        writer.write_i16(value.item_id);
+        if value.item_id >= 0 {
+            writer.write_i8(value.item_count.unwrap_or(1));
+            writer.write_i16(value.item_damage.unwrap_or(0));
+            OptionalNbt::write(&value.nbt, writer);
+        }
+    }
+}
+
 pub struct PlayerInfoPlayer {
     pub uuid: Uuid,
     pub action: PlayerInfoAction,
 }
 
-pub enum PlayerInfoAction {
-    AddPlayer {
-        name: String,
-        properties: Vec<PlayerInfoProperties>,
-        gamemode: i32,
-        ping: i32,
-        display_name: Option<ChatComponent>,
-    },
-    UpdateGameMode {
-        gamemode: i32,
-    },
-    UpdateLatency {
-        ping: i32,
-    },
-    UpdateDisplayName {
-        display_name: Option<ChatComponent>,
-    },
-    RemovePlayer,
-}
+//The action id goes before the list, taken from the first player
+impl Codec<Vec<PlayerInfoPlayer>> for Plain {
+    fn read(reader: &mut DataReader) -> Result<Vec<PlayerInfoPlayer>, PacketError> {
+        let action = reader.read_varint()?;
+        let length = reader.read_length()?;
+        let mut players = Vec::new();
+        for _ in 0..length {
+            players.push(PlayerInfoPlayer {
+                uuid: reader.read_uuid()?,
+                action: PlayerInfoAction::read_body(action, reader)?,
+            });
+        }
+        Ok(players)
+    }
 
-pub struct PlayerInfoProperties {
-    pub name: String,
-    pub value: String,
-    pub signature: Option<String>,
+    fn write(value: &Vec<PlayerInfoPlayer>, writer: &mut DataWriter) {
+        writer.write_varint(value.first().map(|x| x.action.tag()).unwrap_or(0));
+        writer.write_varint(value.len() as i32);
+        for player in value {
+            writer.write_data(player.uuid.as_bytes());
+            player.action.write_body(writer);
+        }
+    }
 }
 
-impl Packet {
-    pub fn read<'a>(id: i32, reader: &mut DataReader, state: ConnectionState) -> Option<Packet> {
-        match state {
-            ConnectionState::Play => match id {
-                0x00 => Some(Packet::KeepAlive {
-                    id: reader.read_varint()?,
-                }),
-                0x01 => Some(Packet::ClientChatMessage {
-                    message: reader.read_string()?,
-                }),
-                _ => None,
-            },
-            ConnectionState::Login => match id {
-                0x00 => Some(Packet::LoginStart {
-                    nickname: reader.read_string()?,
-                }),
-                0x01 => {
-                    let shared_secret_length = reader.read_varint()?;
-                    let shared_secret = reader.read_data_fixed(shared_secret_length as usize)?;
-                    let verify_token_length = reader.read_varint()?;
-                    let verify_token = reader.read_data_fixed(verify_token_length as usize)?;
-
-                    Some(Packet::EncryptionResponse {
-                        shared_secret,
-                        verify_token,
-                    })
-                }
-                _ => None,
-            },
-            ConnectionState::Handshaking => match id {
-                0x00 => Some(Packet::Handshake {
-                    protocol_version: reader.read_varint()?,
-                    server_address: reader.read_string()?,
-                    server_port: reader.read_u16()?,
-                    next_state: reader.read_u8()?,
-                }),
-                _ => None,
-            },
-            ConnectionState::Status => match id {
-                0x00 => Some(Packet::StatusRequest),
-                0x01 => Some(Packet::Ping {
-                    ping: reader.read_i64()?,
-                }),
-                _ => None,
-            },
+//The columns are 0 when the map has no data to update
+impl Codec<Option<MapUpdate>> for Plain {
+    fn read(reader: &mut DataReader) -> Result<Option<MapUpdate>, PacketError> {
+        if reader.read_u8()? == 0 {
+            return Ok(None);
         }
+        reader.cursor -= 1;
+        Ok(Some(<Plain as Codec<MapUpdate>>::read(reader)?))
     }
 
-    pub fn serialize<'a>(&self) -> Option<Vec<u8>> {
-        let mut writer = DataWriter::new();
-        match self {
-            Packet::EncryptionRequest {
-                public_key,
-                server,
-                verify_token,
-            } => {
-                writer.write_u8(0x01);
-                writer.write_string(server);
-                writer.write_varint(public_key.len() as i32);
-                writer.write_vec_data(public_key);
-                writer.write_varint(verify_token.len() as i32);
-                writer.write_data(verify_token);
-            }
-            Packet::DisconnectLogin { reason } => {
-                writer.write_u8(0x00);
-                writer.write_string(&reason.to_string());
-            }
-            Packet::DisconnectPlay { reason } => {
-                writer.write_u8(0x40);
-                writer.write_string(&reason.to_string());
-            }
-            Packet::StatusResponse { json } => {
-                writer.write_u8(0x00);
-                writer.write_string(&json.to_string());
-            }
-            Packet::Pong { pong } => {
-                writer.write_u8(0x01);
-                writer.write_i64(*pong);
-            }
-            Packet::LoginSuccess { nickname, uuid } => {
-                writer.write_u8(0x02);
-                writer.write_string(&uuid.to_hyphenated().to_string());
-                writer.write_string(nickname)
-            }
-            Packet::KeepAlive { id } => {
-                writer.write_u8(0x00);
-                writer.write_varint(*id);
-            }
-            Packet::JoinGame {
-                entity_id,
-                gamemode,
-                dimension,
-                difficulty,
-                max_players,
-                level_type,
-                reduced_debug_info,
-            } => {
-                writer.write_u8(0x01);
-                writer.write_i32(*entity_id);
-                writer.write_u8(*gamemode);
-                writer.write_i8(*dimension);
-                writer.write_u8(*difficulty);
-                writer.write_u8(*max_players);
-                writer.write_string(level_type);
-                writer.write_bool(*reduced_debug_info);
-            }
-            Packet::SpawnPosition { location } => {
-                writer.write_u8(0x05);
-                writer.write_position(location);
-            }
-            Packet::HeldItemChange { slot } => {
-                writer.write_u8(0x09);
-                writer.write_u8(*slot);
-            }
-            Packet::PlayerInfo { action_id, players } => {
-                writer.write_u8(0x38);
-                writer.write_varint(*action_id);
-                writer.write_varint(players.len() as i32);
-                for player in players {
-                    writer.write_vec_data(&player.uuid.as_bytes().to_vec());
-                    match &player.action {
-                        PlayerInfoAction::AddPlayer {
-                            name,
-                            properties,
-                            gamemode,
-                            ping,
-                            display_name,
-                        } => {
-                            writer.write_string(name);
-                            writer.write_varint(properties.len() as i32);
-                            for property in properties {
-                                writer.write_string(&property.name);
-                                writer.write_string(&property.value);
-                                if property.signature.is_some() {
-                                    writer.write_bool(true);
-                                    writer.write_string(property.signature.as_ref().unwrap());
-                                } else {
-                                    writer.write_bool(false);
-                                }
-                            }
-                            writer.write_varint(*gamemode);
-                            writer.write_varint(*ping);
-                            if display_name.is_some() {
-                                writer.write_bool(true);
-                                writer.write_string(&display_name.as_ref().unwrap().to_string());
-                            } else {
-                                writer.write_bool(false);
-                            }
-                        }
-                        PlayerInfoAction::UpdateGameMode { gamemode } => {
-                            writer.write_varint(*gamemode);
-                        }
-                        PlayerInfoAction::UpdateLatency { ping } => {
-                            writer.write_varint(*ping);
-                        }
-                        PlayerInfoAction::UpdateDisplayName { display_name } => {
-                            if display_name.is_some() {
-                                writer.write_bool(true);
-                                writer.write_string(&display_name.as_ref().unwrap().to_string());
-                            } else {
-                                writer.write_bool(false);
-                            }
-                        }
-                        PlayerInfoAction::RemovePlayer => {}
-                    };
-                }
-            }
-            Packet::PlayerPositionAndLook {
-                x,
-                y,
-                z,
-                yaw,
-                pitch,
-                flags,
-            } => {
-                writer.write_u8(0x08);
-                writer.write_f64(*x);
-                writer.write_f64(*y);
-                writer.write_f64(*z);
-                writer.write_f32(*yaw);
-                writer.write_f32(*pitch);
-                writer.write_u8(*flags);
-            }
-            Packet::WorldBorder { action } => {
-                writer.write_u8(0x44);
-                match action {
-                    WorldBorderAction::SetSize { radius } => {
-                        writer.write_varint(0);
-                        writer.write_f64(*radius);
-                    }
-                    WorldBorderAction::LerpSize {
-                        old_radius,
-                        new_radius,
-                        speed,
-                    } => {
-                        writer.write_varint(1);
-                        writer.write_f64(*old_radius);
-                        writer.write_f64(*new_radius);
-                        writer.write_varint(*speed);
-                    }
-                    WorldBorderAction::SetCenter { x, z } => {
-                        writer.write_varint(2);
-                        writer.write_f64(*x);
-                        writer.write_f64(*z);
-                    }
-                    WorldBorderAction::Initialize {
-                        x,
-                        z,
-                        old_radius,
-                        new_radius,
-                        speed,
-                        portal_teleport_boundary,
-                        warning_time,
-                        warning_blocks,
-                    } => {
-                        writer.write_varint(3);
-                        writer.write_f64(*x);
-                        writer.write_f64(*z);
-                        writer.write_f64(*old_radius);
-                        writer.write_f64(*new_radius);
-                        writer.write_varlong(*speed);
-                        writer.write_varint(*portal_teleport_boundary);
-                        writer.write_varint(*warning_time);
-                        writer.write_varint(*warning_blocks);
-                    }
-                    WorldBorderAction::SetWarningTime { warning_time } => {
-                        writer.write_varint(4);
-                        writer.write_varint(*warning_time);
-                    }
-                    WorldBorderAction::SetWarningBlocks { warning_blocks } => {
-                        writer.write_varint(5);
-                        writer.write_varint(*warning_blocks);
-                    }
-                }
-            }
-            Packet::TimeUpdate {
-                world_age,
-                time_of_day,
-            } => {
-                writer.write_varint(0x03);
-                writer.write_i64(*world_age);
-                writer.write_i64(*time_of_day)
-            }
-            Packet::WindowItems { window_id, slots } => {
-                writer.write_varint(0x30);
-                writer.write_u8(*window_id);
-                writer.write_i16(slots.len() as i16);
-                for slot in slots {
-                    writer.write_i16(slot.item_id);
-                    if slot.item_id > 0 {
-                        writer.write_i8(slot.item_count.unwrap());
-                        writer.write_i16(slot.item_damage.unwrap());
-                        if slot.nbt.is_none() {
-                            writer.write_u8(0);
-                        } else {
-                            slot.nbt
-                                .as_ref()
-                                .unwrap()
-                                .write(&mut writer.data, Some(&String::new()), true);
-                        }
-                    }
-                }
-            }
-            Packet::ServerChatMessage { component, pos } => {
-                writer.write_u8(0x02);
-                writer.write_string(&component.to_string());
-                writer.write_u8(*pos);
-            }
-            Packet::SpawnObject {
-                id,
-                object,
-                point,
-                angle,
-                data,
-                vel_x,
-                vel_y,
-                vel_z,
-            } => {
-                writer.write_varint(0x0E);
-                writer.write_varint(*id);
-                writer.write_u8(*object);
-                let pos = point.absolute();
-                writer.write_i32(pos.x);
-                writer.write_i32(pos.y as i32);
-                writer.write_i32(pos.z);
-                writer.write_u8(angle.pitch);
-                writer.write_u8(angle.yaw);
-                writer.write_i32(*data);
-                if let Some(x) = vel_x {
-                    writer.write_i16(*x);
-                }
-                if let Some(y) = vel_y {
-                    writer.write_i16(*y);
-                }
-                if let Some(z) = vel_z {
-                    writer.write_i16(*z);
-                }
+    fn write(value: &Option<MapUpdate>, writer: &mut DataWriter) {
+        match value {
+            Some(update) => <Plain as Codec<MapUpdate>>::write(update, writer),
+            None => writer.write_u8(0),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MetadataEntry {
+    pub index: u8,
+    pub value: MetadataValue,
+}
+
+//Entity metadata entries, each key has the type and the index, ending with 0x7F
+pub struct Metadata;
+
+impl Codec<Vec<MetadataEntry>> for Metadata {
+    fn read(reader: &mut DataReader) -> Result<Vec<MetadataEntry>, PacketError> {
+        let mut entries = Vec::new();
+        loop {
+            let key = reader.read_u8()?;
+            if key == 0x7F {
+                return Ok(entries);
             }
-            _ => return None,
+            entries.push(MetadataEntry {
+                index: key & 0x1F,
+                value: MetadataValue::read_body(key >> 5, reader)?,
+            });
         }
+    }
 
+    fn write(value: &Vec<MetadataEntry>, writer: &mut DataWriter) {
+        for entry in value {
+            writer.write_u8((entry.value.tag() << 5) | (entry.index & 0x1F));
+            entry.value.write_body(writer);
+        }
+        writer.write_u8(0x7F);
+    }
+}
+
+impl Packet {
+    //Reads a packet sent by the client, which must use every byte of it
+    pub fn read(
+        id: i32,
+        reader: &mut DataReader,
+        state: ConnectionState,
+    ) -> Result<Packet, PacketError> {
+        let packet = Packet::decode(id, reader, state, Direction::Serverbound)?;
+        if reader.remaining() > 0 {
+            return Err(PacketError::TrailingBytes {
+                packet: packet.name(),
+                count: reader.remaining(),
+            });
+        }
+        Ok(packet)
+    }
+
+    //Id and fields of the packet, in the direction and state it's registered in
+    pub fn encode(&self, direction: Direction) -> Option<Vec<u8>> {
+        let (_state, id) = self.registration(direction)?;
+        let mut writer = DataWriter::new();
+        writer.write_varint(id);
+        self.write_fields(&mut writer);
         Some(writer.data)
     }
 
-    pub fn serialize_length(&self) -> Option<Vec<u8>> {
-        let mut data = self.serialize()?;
-        data.splice(0..0, DataWriter::var_num(data.len() as u64));
-        Some(data)
+    //Id and fields of a packet sent by the server, None if the client can't receive it
+    pub fn serialize(&self) -> Option<Vec<u8>> {
+        self.encode(Direction::Clientbound)
     }
 }
 
@@ -522,33 +1015,36 @@ pub enum ExtendedPacket {
     MapChunkBulk {
         sky_light: bool,
         chunks: Vec<ChunkMeta>,
-        data: Vec<u8>
-    }
-}
-
-pub struct ChunkMeta {
-    pub pos: ChunkPos,
-    pub bitmask: u16
+        data: Vec<u8>,
+    },
 }
 
 impl ExtendedPacket {
     pub fn send(self, client: &mut PlayerClient) {
         match self {
-            ExtendedPacket::ChunkData {x, y, ground_up_continuous, bitmask, data} => {
+            ExtendedPacket::ChunkData {
+                x,
+                y,
+                ground_up_continuous,
+                bitmask,
+                data,
+            } => {
                 let mut writer = DataWriter::new();
 
-                writer.write_u8(0x21);
+                writer.write_varint(0x21);
                 writer.write_i32(x);
                 writer.write_i32(y);
                 writer.write_bool(ground_up_continuous);
                 writer.write_u16(bitmask);
-                writer.write_varint((data.len() as i32));
+                writer.write_varint(data.len() as i32);
 
-                client.write_mut_slice(DataWriter::var_num((writer.data.len() + data.len()) as u64).as_mut_slice());
-                client.write_data_owned(writer.data);
-                client.write_data_owned(data);
+                client.write_split(writer.data, data);
             }
-            ExtendedPacket::MapChunkBulk { sky_light, chunks, data } => {
+            ExtendedPacket::MapChunkBulk {
+                sky_light,
+                chunks,
+                data,
+            } => {
                 let mut writer = DataWriter::new();
 
                 writer.write_varint(0x26);
@@ -560,11 +1056,918 @@ impl ExtendedPacket {
                     writer.write_u16(chunk.bitmask);
                 }
 
-                client.write_mut_slice(DataWriter::var_num((writer.data.len() + data.len()) as u64).as_mut_slice());
-                client.write_data_owned(writer.data);
-                client.write_data_owned(data);
+                client.write_split(writer.data, data);
             }
-            _ => panic!("Serialization not implemented for this packet")
         }
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn every_packet_round_trips() {
+    let position = || Position {
+        x: -1234,
+        y: 64,
+        z: 5678,
+    };
+    let point = || Point {
+        x: 10.5,
+        y: 64.0,
+        z: -3.25,
+    };
+    let angle = || Angle {
+        pitch: 12,
+        yaw: 200,
+    };
+    let chat = |text: &str| ChatComponent::new_text(text.to_string());
+    let nbt = || {
+        let mut compound = fxhash::FxHashMap::default();
+        compound.insert("Damage".to_string(), NBTTag::Short { short: 3 });
+        NBTTag::Compound { compound }
+    };
+    let item = || Slot {
+        item_id: 276,
+        item_count: Some(1),
+        item_damage: Some(0),
+        nbt: Some(nbt()),
+    };
+    let empty = || Slot {
+        item_id: -1,
+        item_count: None,
+        item_damage: None,
+        nbt: None,
+    };
+    let metadata = || {
+        vec![
+            MetadataEntry {
+                index: 0,
+                value: MetadataValue::Byte { value: 0x02 },
+            },
+            MetadataEntry {
+                index: 1,
+                value: MetadataValue::Short { value: 300 },
+            },
+            MetadataEntry {
+                index: 2,
+                value: MetadataValue::String {
+                    value: "Steve".to_string(),
+                },
+            },
+            MetadataEntry {
+                index: 6,
+                value: MetadataValue::Float { value: 20.0 },
+            },
+            MetadataEntry {
+                index: 7,
+                value: MetadataValue::Int { value: 0 },
+            },
+            MetadataEntry {
+                index: 10,
+                value: MetadataValue::Slot { value: item() },
+            },
+            MetadataEntry {
+                index: 11,
+                value: MetadataValue::Position { x: 1, y: 2, z: 3 },
+            },
+            MetadataEntry {
+                index: 12,
+                value: MetadataValue::Rotation {
+                    pitch: 1.0,
+                    yaw: 2.0,
+                    roll: 3.0,
+                },
+            },
+        ]
+    };
+    let team_info = || TeamInfo {
+        display_name: "Red".to_string(),
+        prefix: "[R]".to_string(),
+        suffix: String::new(),
+        friendly_fire: 3,
+        name_tag_visibility: "always".to_string(),
+        color: 12,
+    };
+    let uuid = Uuid::parse_str("0ecda338-9eeb-413e-9629-58bd0d552e5e").unwrap();
+    let mut status = JsonValue::new_object();
+    status["description"] = JsonValue::String("Amethyst".to_string());
+
+    let packets = vec![
+        Packet::Handshake {
+            protocol_version: 47,
+            server_address: "localhost".to_string(),
+            server_port: 25565,
+            next_state: 2,
+        },
+        Packet::StatusRequest,
+        Packet::Ping { ping: 123456789 },
+        Packet::StatusResponse { json: status },
+        Packet::Pong { pong: 123456789 },
+        Packet::LoginStart {
+            nickname: "Steve".to_string(),
+        },
+        Packet::EncryptionResponse {
+            shared_secret: vec![1; 128],
+            verify_token: vec![2; 128],
+        },
+        Packet::DisconnectLogin {
+            reason: chat("Bye"),
+        },
+        Packet::EncryptionRequest {
+            server: String::new(),
+            public_key: vec![3; 162],
+            verify_token: vec![1, 2, 3, 4],
+        },
+        Packet::LoginSuccess {
+            uuid,
+            nickname: "Steve".to_string(),
+        },
+        Packet::SetCompression { threshold: 256 },
+        Packet::KeepAlive { id: 987654 },
+        Packet::JoinGame {
+            entity_id: 1,
+            gamemode: 1,
+            dimension: -1,
+            difficulty: 2,
+            max_players: 20,
+            level_type: "default".to_string(),
+            reduced_debug_info: false,
+        },
+        Packet::ServerChatMessage {
+            component: chat("Hello"),
+            pos: 0,
+        },
+        Packet::TimeUpdate {
+            world_age: 24000,
+            time_of_day: -6000,
+        },
+        Packet::EntityEquipment {
+            entity_id: 5,
+            slot: 0,
+            item: item(),
+        },
+        Packet::SpawnPosition {
+            location: position(),
+        },
+        Packet::UpdateHealth {
+            health: 20.0,
+            food: 18,
+            food_saturation: 5.0,
+        },
+        Packet::Respawn {
+            dimension: 0,
+            difficulty: 1,
+            gamemode: 0,
+            level_type: "flat".to_string(),
+        },
+        Packet::PlayerPositionAndLook {
+            x: 0.5,
+            y: 65.0,
+            z: -0.5,
+            yaw: 90.0,
+            pitch: -10.0,
+            flags: 0,
+        },
+        Packet::HeldItemChange { slot: 4 },
+        Packet::UseBed {
+            entity_id: 5,
+            location: position(),
+        },
+        Packet::ServerAnimation {
+            entity_id: 5,
+            animation: 0,
+        },
+        Packet::SpawnPlayer {
+            entity_id: 5,
+            uuid,
+            point: point(),
+            angle: angle(),
+            current_item: 0,
+            metadata: metadata(),
+        },
+        Packet::CollectItem {
+            collected_entity_id: 8,
+            collector_entity_id: 5,
+        },
+        Packet::SpawnObject {
+            id: 9,
+            object: 2,
+            point: point(),
+            angle: angle(),
+            data: 1,
+            vel_x: Some(100),
+            vel_y: Some(-200),
+            vel_z: Some(300),
+        },
+        Packet::SpawnMob {
+            entity_id: 10,
+            mob_type: 50,
+            point: point(),
+            angle: angle(),
+            head_pitch: 30,
+            vel_x: 0,
+            vel_y: -100,
+            vel_z: 0,
+            metadata: metadata(),
+        },
+        Packet::SpawnPainting {
+            entity_id: 11,
+            title: "Kebab".to_string(),
+            location: position(),
+            direction: 2,
+        },
+        Packet::SpawnExperienceOrb {
+            entity_id: 12,
+            point: point(),
+            count: 7,
+        },
+        Packet::EntityVelocity {
+            entity_id: 5,
+            vel_x: 1,
+            vel_y: 2,
+            vel_z: 3,
+        },
+        Packet::DestroyEntities {
+            entity_ids: vec![1, 300, 70000],
+        },
+        Packet::Entity { entity_id: 5 },
+        Packet::EntityRelativeMove {
+            entity_id: 5,
+            delta_x: 1,
+            delta_y: -2,
+            delta_z: 3,
+            on_ground: true,
+        },
+        Packet::EntityLook {
+            entity_id: 5,
+            angle: angle(),
+            on_ground: false,
+        },
+        Packet::EntityLookAndRelativeMove {
+            entity_id: 5,
+            delta_x: -1,
+            delta_y: 0,
+            delta_z: 1,
+            angle: angle(),
+            on_ground: true,
+        },
+        Packet::EntityTeleport {
+            entity_id: 5,
+            point: point(),
+            angle: angle(),
+            on_ground: true,
+        },
+        Packet::EntityHeadLook {
+            entity_id: 5,
+            head_yaw: 64,
+        },
+        Packet::EntityStatus {
+            entity_id: 5,
+            status: 2,
+        },
+        Packet::AttachEntity {
+            entity_id: 5,
+            vehicle_id: 6,
+            leash: false,
+        },
+        Packet::EntityMetadata {
+            entity_id: 5,
+            metadata: metadata(),
+        },
+        Packet::EntityEffect {
+            entity_id: 5,
+            effect_id: 1,
+            amplifier: 0,
+            duration: 600,
+            hide_particles: false,
+        },
+        Packet::RemoveEntityEffect {
+            entity_id: 5,
+            effect_id: 1,
+        },
+        Packet::SetExperience {
+            experience_bar: 0.5,
+            level: 30,
+            total_experience: 1395,
+        },
+        Packet::EntityProperties {
+            entity_id: 5,
+            properties: vec![EntityProperty {
+                key: "generic.movementSpeed".to_string(),
+                value: 0.1,
+                modifiers: vec![AttributeModifier {
+                    uuid,
+                    amount: 0.3,
+                    operation: 2,
+                }],
+            }],
+        },
+        Packet::ChunkData {
+            x: 1,
+            z: -1,
+            ground_up_continuous: true,
+            bitmask: 0b1,
+            data: vec![7; 300],
+        },
+        Packet::MultiBlockChange {
+            chunk: ChunkPos { x: 2, z: 3 },
+            records: vec![BlockChangeRecord {
+                horizontal_position: 0x4F,
+                y: 70,
+                block_id: 16,
+            }],
+        },
+        Packet::BlockChange {
+            location: position(),
+            block_id: 1 << 4,
+        },
+        Packet::BlockAction {
+            location: position(),
+            action_id: 1,
+            action_param: 2,
+            block_type: 54,
+        },
+        Packet::BlockBreakAnimation {
+            entity_id: 5,
+            location: position(),
+            destroy_stage: 3,
+        },
+        Packet::MapChunkBulk {
+            sky_light: true,
+            chunks: vec![
+                ChunkMeta {
+                    pos: ChunkPos { x: 0, z: 0 },
+                    bitmask: 0b11,
+                },
+                ChunkMeta {
+                    pos: ChunkPos { x: -1, z: 0 },
+                    bitmask: 0b1,
+                },
+            ],
+            data: vec![9; 500],
+        },
+        Packet::Explosion {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+            radius: 4.0,
+            records: vec![ExplosionRecord { x: 1, y: -1, z: 0 }],
+            motion_x: 0.1,
+            motion_y: 0.2,
+            motion_z: 0.3,
+        },
+        Packet::Effect {
+            effect_id: 2001,
+            location: position(),
+            data: 1,
+            disable_relative_volume: false,
+        },
+        Packet::SoundEffect {
+            sound_name: "random.click".to_string(),
+            x: 80,
+            y: 512,
+            z: -80,
+            volume: 1.0,
+            pitch: 63,
+        },
+        Packet::Particle {
+            particle_id: 36,
+            long_distance: false,
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+            offset_x: 0.5,
+            offset_y: 0.5,
+            offset_z: 0.5,
+            particle_data: 0.1,
+            count: 10,
+            data: vec![276, 0],
+        },
+        Packet::ChangeGameState {
+            reason: 3,
+            value: 1.0,
+        },
+        Packet::SpawnGlobalEntity {
+            entity_id: 13,
+            entity_type: 1,
+            point: point(),
+        },
+        Packet::OpenWindow {
+            window_id: 1,
+            window_type: "EntityHorse".to_string(),
+            title: chat("Horse"),
+            slot_count: 2,
+            entity_id: Some(14),
+        },
+        Packet::CloseWindow { window_id: 1 },
+        Packet::SetSlot {
+            window_id: 0,
+            slot: 36,
+            item: item(),
+        },
+        Packet::WindowItems {
+            window_id: 0,
+            slots: vec![item(), empty(), empty()],
+        },
+        Packet::WindowProperty {
+            window_id: 1,
+            property: 0,
+            value: 100,
+        },
+        Packet::ConfirmTransaction {
+            window_id: 1,
+            action_number: 12,
+            accepted: true,
+        },
+        Packet::UpdateSign {
+            location: position(),
+            line_1: Box::new(chat("1")),
+            line_2: Box::new(chat("2")),
+            line_3: Box::new(chat("3")),
+            line_4: Box::new(chat("4")),
+        },
+        Packet::Map {
+            item_damage: 0,
+            scale: 2,
+            icons: vec![MapIcon {
+                direction_and_type: 0x10,
+                x: 5,
+                z: -5,
+            }],
+            update: Some(MapUpdate {
+                columns: 2,
+                rows: 2,
+                x: 0,
+                z: 0,
+                data: vec![1, 2, 3, 4],
+            }),
+        },
+        Packet::UpdateBlockEntity {
+            location: position(),
+            action: 1,
+            nbt: Some(nbt()),
+        },
+        Packet::OpenSignEditor {
+            location: position(),
+        },
+        Packet::Statistics {
+            statistics: vec![Statistic {
+                name: "stat.jump".to_string(),
+                value: 42,
+            }],
+        },
+        Packet::PlayerInfo {
+            players: vec![PlayerInfoPlayer {
+                uuid,
+                action: PlayerInfoAction::AddPlayer {
+                    name: "Steve".to_string(),
+                    properties: vec![PlayerInfoProperties {
+                        name: "textures".to_string(),
+                        value: "e30=".to_string(),
+                        signature: Some("c2ln".to_string()),
+                    }],
+                    gamemode: 1,
+                    ping: 20,
+                    display_name: Some(chat("Steve")),
+                },
+            }],
+        },
+        Packet::PlayerAbilities {
+            flags: 0x0F,
+            flying_speed: 0.05,
+            walking_speed: 0.1,
+        },
+        Packet::ServerTabComplete {
+            matches: vec!["/help".to_string(), "/home".to_string()],
+        },
+        Packet::ScoreboardObjective {
+            name: "kills".to_string(),
+            action: ObjectiveAction::Create {
+                value: "Kills".to_string(),
+                objective_type: "integer".to_string(),
+            },
+        },
+        Packet::UpdateScore {
+            score_name: "Steve".to_string(),
+            action: ScoreAction::Update {
+                objective_name: "kills".to_string(),
+                value: 3,
+            },
+        },
+        Packet::DisplayScoreboard {
+            position: 1,
+            score_name: "kills".to_string(),
+        },
+        Packet::Teams {
+            team_name: "red".to_string(),
+            action: TeamAction::Create {
+                info: team_info(),
+                players: vec!["Steve".to_string()],
+            },
+        },
+        Packet::PluginMessage {
+            channel: "MC|Brand".to_string(),
+            data: b"\x08amethyst".to_vec(),
+        },
+        Packet::DisconnectPlay {
+            reason: chat("Kicked"),
+        },
+        Packet::ServerDifficulty { difficulty: 3 },
+        Packet::CombatEvent {
+            event: CombatEventKind::EntityDead {
+                player_id: 5,
+                entity_id: 6,
+                message: "Steve was slain".to_string(),
+            },
+        },
+        Packet::Camera { camera_id: 5 },
+        Packet::WorldBorder {
+            action: WorldBorderAction::Initialize {
+                x: 0.0,
+                z: 0.0,
+                old_radius: 100.0,
+                new_radius: 200.0,
+                speed: 1000,
+                portal_teleport_boundary: 29999984,
+                warning_time: 15,
+                warning_blocks: 5,
+            },
+        },
+        Packet::Title {
+            action: TitleAction::SetTimes {
+                fade_in: 10,
+                stay: 70,
+                fade_out: 20,
+            },
+        },
+        Packet::PlayerListHeaderFooter {
+            header: chat("Header"),
+            footer: chat("Footer"),
+        },
+        Packet::ResourcePackSend {
+            url: "https://example.com/pack.zip".to_string(),
+            hash: "0123456789abcdef0123456789abcdef01234567".to_string(),
+        },
+        Packet::UpdateEntityNBT {
+            entity_id: 5,
+            tag: nbt(),
+        },
+        Packet::ClientChatMessage {
+            message: "Hello".to_string(),
+        },
+        Packet::UseEntity {
+            target: 6,
+            action: UseEntityAction::InteractAt {
+                x: 0.5,
+                y: 1.0,
+                z: 0.5,
+            },
+        },
+        Packet::Player { on_ground: true },
+        Packet::PlayerPosition {
+            x: 0.5,
+            y: 65.0,
+            z: 0.5,
+            on_ground: true,
+        },
+        Packet::PlayerLook {
+            yaw: 180.0,
+            pitch: 45.0,
+            on_ground: false,
+        },
+        Packet::ClientPlayerPositionAndLook {
+            x: 0.5,
+            y: 65.0,
+            z: 0.5,
+            yaw: 180.0,
+            pitch: 45.0,
+            on_ground: true,
+        },
+        Packet::PlayerDigging {
+            status: 0,
+            location: position(),
+            face: 1,
+        },
+        Packet::PlayerBlockPlacement {
+            location: position(),
+            face: 1,
+            held_item: item(),
+            cursor_x: 8,
+            cursor_y: 16,
+            cursor_z: 8,
+        },
+        Packet::ClientHeldItemChange { slot: 3 },
+        Packet::ClientAnimation,
+        Packet::EntityAction {
+            entity_id: 5,
+            action_id: 3,
+            jump_boost: 0,
+        },
+        Packet::SteerVehicle {
+            sideways: 0.98,
+            forward: -0.98,
+            flags: 1,
+        },
+        Packet::ClickWindow {
+            window_id: 0,
+            slot: 36,
+            button: 0,
+            action_number: 1,
+            mode: 0,
+            clicked_item: empty(),
+        },
+        Packet::CreativeInventoryAction {
+            slot: 36,
+            clicked_item: item(),
+        },
+        Packet::EnchantItem {
+            window_id: 1,
+            enchantment: 2,
+        },
+        Packet::ClientTabComplete {
+            text: "/he".to_string(),
+            looked_at_block: Some(position()),
+        },
+        Packet::ClientSettings {
+            locale: "en_US".to_string(),
+            view_distance: 8,
+            chat_mode: 0,
+            chat_colors: true,
+            displayed_skin_parts: 0x7F,
+        },
+        Packet::ClientStatus { action_id: 0 },
+        Packet::Spectate {
+            target_player: uuid,
+        },
+        Packet::ResourcePackStatus {
+            hash: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            result: 0,
+        },
+    ];
+
+    let mut tested = std::collections::HashSet::new();
+    for packet in &packets {
+        for direction in &[Direction::Clientbound, Direction::Serverbound] {
+            let (state, _id) = match packet.registration(*direction) {
+                Some(t) => t,
+                None => continue,
+            };
+            let data = packet.encode(*direction).unwrap();
+
+            let mut reader = DataReader::new(&data);
+            let id = reader.read_varint().unwrap();
+            let decoded = Packet::decode(id, &mut reader, state, *direction).unwrap_or_else(|e| {
+                panic!("{} ({:?}) couldn't be decoded: {:?}", packet.name(), direction, e)
+            });
+            assert_eq!(decoded.name(), packet.name());
+            assert_eq!(reader.remaining(), 0, "{} left bytes unread", packet.name());
+            assert_eq!(
+                decoded.encode(*direction).unwrap(),
+                data,
+                "{} ({:?}) changed after a round trip",
+                packet.name(),
+                direction
+            );
+            tested.insert(packet.name());
+        }
+    }
+
+    for name in Packet::NAMES {
+        assert!(tested.contains(name), "{} has no round trip sample", name);
+    }
+}
+
+#[test]
+fn ids_match_registrations() {
+    let keep_alive = Packet::KeepAlive { id: 1 };
+    assert_eq!(
+        keep_alive.id(ConnectionState::Play, Direction::Clientbound),
+        Some(0x00)
+    );
+    assert_eq!(
+        keep_alive.id(ConnectionState::Play, Direction::Serverbound),
+        Some(0x00)
+    );
+
+    let compression = Packet::SetCompression { threshold: 256 };
+    assert_eq!(
+        compression.id(ConnectionState::Login, Direction::Clientbound),
+        Some(0x03)
+    );
+    assert_eq!(
+        compression.id(ConnectionState::Play, Direction::Clientbound),
+        Some(0x46)
+    );
+    assert_eq!(compression.serialize().unwrap(), vec![0x03, 0x80, 0x02]);
+
+    //Serverbound only
+    assert!(Packet::ClientAnimation.serialize().is_none());
+}
+
+//Serverbound Play packets laid out byte by byte like the vanilla client sends them
+#[test]
+fn decodes_vanilla_serverbound_play_packets() {
+    let read = |data: &[u8]| {
+        let mut reader = DataReader::new(data);
+        let id = reader.read_varint().unwrap();
+        let packet = Packet::read(id, &mut reader, ConnectionState::Play).unwrap();
+        assert_eq!(reader.remaining(), 0, "{} left bytes unread", packet.name());
+        packet
+    };
+
+    //Block placement at (1, 64, -1) holding a diamond sword with Sharpness V
+    let placement = [
+        0x08, 0x00, 0x00, 0x00, 0x41, 0x03, 0xFF, 0xFF, 0xFF, 0x01, 0x01, 0x14, 0x01, 0x00, 0x00,
+        0x0A, 0x00, 0x00, 0x09, 0x00, 0x04, b'e', b'n', b'c', b'h', 0x0A, 0x00, 0x00, 0x00, 0x01,
+        0x02, 0x00, 0x02, b'i', b'd', 0x00, 0x10, 0x02, 0x00, 0x03, b'l', b'v', b'l', 0x00, 0x05,
+        0x00, 0x00, 0x08, 0x10, 0x08,
+    ];
+    match read(&placement) {
+        Packet::PlayerBlockPlacement {
+            location,
+            face,
+            held_item,
+            cursor_x,
+            cursor_y,
+            cursor_z,
+        } => {
+            assert!(location == Position { x: 1, y: 64, z: -1 });
+            assert_eq!(face, 1);
+            assert_eq!(held_item.item_id, 276);
+            assert_eq!(held_item.item_count, Some(1));
+            assert_eq!(held_item.item_damage, Some(0));
+            match held_item.nbt.as_ref().unwrap().get("ench") {
+                Some(NBTTag::List { list, .. }) => match list[0].get("lvl") {
+                    Some(NBTTag::Short { short }) => assert_eq!(*short, 5),
+                    _ => panic!("Missing enchantment level"),
+                },
+                _ => panic!("Missing enchantment list"),
+            }
+            assert_eq!((cursor_x, cursor_y, cursor_z), (8, 16, 8));
+        }
+        _ => panic!("Expected a block placement"),
+    }
+
+    //Placement with an empty hand, used to interact with blocks
+    let placement = [
+        0x08, 0x00, 0x00, 0x00, 0x41, 0x03, 0xFF, 0xFF, 0xFF, 0x05, 0xFF, 0xFF, 0x00, 0x00, 0x00,
+    ];
+    match read(&placement) {
+        Packet::PlayerBlockPlacement {
+            face, held_item, ..
+        } => {
+            assert_eq!(face, 5);
+            assert_eq!(held_item.item_id, -1);
+            assert!(held_item.nbt.is_none());
+        }
+        _ => panic!("Expected a block placement"),
+    }
+
+    match read(&[0x02, 0x2A, 0x01]) {
+        Packet::UseEntity {
+            target,
+            action: UseEntityAction::Attack,
+        } => assert_eq!(target, 42),
+        _ => panic!("Expected an attack"),
+    }
+
+    let mut position = vec![0x04];
+    position.extend_from_slice(&0.5f64.to_be_bytes());
+    position.extend_from_slice(&65.0f64.to_be_bytes());
+    position.extend_from_slice(&(-0.5f64).to_be_bytes());
+    position.push(0x01);
+    match read(&position) {
+        Packet::PlayerPosition { x, y, z, on_ground } => {
+            assert_eq!((x, y, z, on_ground), (0.5, 65.0, -0.5, true))
+        }
+        _ => panic!("Expected a player position"),
+    }
+
+    //Started digging the block below (1, 64, -1)
+    match read(&[
+        0x07, 0x00, 0x00, 0x00, 0x00, 0x41, 0x03, 0xFF, 0xFF, 0xFF, 0x00,
+    ]) {
+        Packet::PlayerDigging {
+            status,
+            location,
+            face,
+        } => {
+            assert_eq!(status, 0);
+            assert!(location == Position { x: 1, y: 64, z: -1 });
+            assert_eq!(face, 0);
+        }
+        _ => panic!("Expected a digging"),
+    }
+
+    match read(&[0x09, 0x00, 0x03]) {
+        Packet::ClientHeldItemChange { slot } => assert_eq!(slot, 3),
+        _ => panic!("Expected a held item change"),
+    }
+
+    assert_eq!(read(&[0x0A]).name(), "ClientAnimation");
+
+    //Shift click on the first hotbar slot
+    match read(&[0x0E, 0x00, 0x00, 0x24, 0x00, 0x00, 0x07, 0x01, 0xFF, 0xFF]) {
+        Packet::ClickWindow {
+            window_id,
+            slot,
+            button,
+            action_number,
+            mode,
+            clicked_item,
+        } => {
+            assert_eq!(
+                (window_id, slot, button, action_number, mode),
+                (0, 36, 0, 7, 1)
+            );
+            assert_eq!(clicked_item.item_id, -1);
+        }
+        _ => panic!("Expected a window click"),
+    }
+
+    let mut settings = vec![0x15, 0x05];
+    settings.extend_from_slice(b"en_US");
+    settings.extend_from_slice(&[0x0A, 0x00, 0x01, 0x7F]);
+    match read(&settings) {
+        Packet::ClientSettings {
+            locale,
+            view_distance,
+            chat_mode,
+            chat_colors,
+            displayed_skin_parts,
+        } => {
+            assert_eq!(locale, "en_US");
+            assert_eq!((view_distance, chat_mode, chat_colors), (10, 0, true));
+            assert_eq!(displayed_skin_parts, 0x7F);
+        }
+        _ => panic!("Expected the client settings"),
+    }
+
+    match read(&[0x14, 0x03, b'/', b'h', b'e', 0x00]) {
+        Packet::ClientTabComplete {
+            text,
+            looked_at_block,
+        } => {
+            assert_eq!(text, "/he");
+            assert!(looked_at_block.is_none());
+        }
+        _ => panic!("Expected a tab complete"),
+    }
+
+    let mut brand = vec![0x17, 0x08];
+    brand.extend_from_slice(b"MC|Brand");
+    brand.extend_from_slice(b"\x07vanilla");
+    match read(&brand) {
+        Packet::PluginMessage { channel, data } => {
+            assert_eq!(channel, "MC|Brand");
+            assert_eq!(data, b"\x07vanilla");
+        }
+        _ => panic!("Expected a plugin message"),
+    }
+
+    match read(&[0x16, 0x02]) {
+        Packet::ClientStatus { action_id } => assert_eq!(action_id, 2),
+        _ => panic!("Expected a client status"),
+    }
+}
+
+#[test]
+fn malformed_packets_report_why() {
+    let read = |id: i32, data: &[u8]| {
+        match Packet::read(id, &mut DataReader::new(data), ConnectionState::Play) {
+            Ok(packet) => panic!("{} was read", packet.name()),
+            Err(e) => e,
+        }
+    };
+
+    //Chat message claiming 5 bytes but carrying 2
+    match read(0x01, &[0x05, b'h', b'i']) {
+        PacketError::UnexpectedEof => {}
+        e => panic!("{:?}", e),
+    }
+    match read(0x01, &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]) {
+        PacketError::NegativeLength(-1) => {}
+        e => panic!("{:?}", e),
+    }
+    //Six byte varint as the length
+    match read(0x01, &[0x80, 0x80, 0x80, 0x80, 0x80, 0x01]) {
+        PacketError::VarIntTooLong => {}
+        e => panic!("{:?}", e),
+    }
+    //Use entity with an action past interact at
+    match read(0x02, &[0x01, 0x07]) {
+        PacketError::InvalidEnum { value: 7, .. } => {}
+        e => panic!("{:?}", e),
+    }
+    //Creative inventory action whose item NBT has a byte array claiming 2^31 - 1 bytes
+    match read(0x10, &[0x00, 0x01, 0x00, 0x01, 0x01, 0x00, 0x00, 0x0A, 0x00, 0x00, 0x07, 0x00, 0x01, b'a', 0x7F, 0xFF, 0xFF, 0xFF]) {
+        PacketError::BadNbt(_) => {}
+        e => panic!("{:?}", e),
+    }
+    match read(0x00, &[0x01, 0x00]) {
+        PacketError::TrailingBytes { count: 1, .. } => {}
+        e => panic!("{:?}", e),
+    }
+    match read(0x7F, &[]) {
+        PacketError::UnknownId { id: 0x7F, .. } => {}
+        e => panic!("{:?}", e),
+    }
+}
@@ -1,19 +1,76 @@
 use crate::data_writer::DataWriter;
 use crate::game::chat::ChatComponent;
-use crate::game::packets::Packet;
+use crate::game::chunk_streaming::ChunkView;
+use crate::game::packets::PlayerInfoProperties;
+use crate::game::player_movement::MovementState;
+use crate::game::world::coords::Point;
 use aes::cipher::StreamCipher;
 use aes::Aes128;
 use cfb8::Cfb8;
 use mio::Token;
 use std::io::Write;
 use std::net::{SocketAddr, TcpStream};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 use uuid::Uuid;
 
 pub struct Player {
     pub token: Token,
     pub uuid: Uuid,
     pub nickname: String,
+    pub entity_id: i32,
+    pub properties: Vec<PlayerInfoProperties>,
+    //Last position the client reported, or where the server last teleported it
+    pub position: Point,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub on_ground: bool,
+    pub dimension: i8,
+    pub gamemode: u8,
+    pub movement: MovementState,
+    pub chunk_view: ChunkView,
+}
+
+#[cfg(test)]
+impl Player {
+    //Steve standing on the ground at the position, with nothing loaded yet
+    pub fn test(token: Token, position: Point) -> Player {
+        Player {
+            token,
+            uuid: Uuid::nil(),
+            nickname: "Steve".to_string(),
+            entity_id: 0,
+            properties: Vec::new(),
+            position: position.clone(),
+            yaw: 0.0,
+            pitch: 0.0,
+            on_ground: true,
+            dimension: 0,
+            gamemode: 0,
+            movement: MovementState::new(position),
+            chunk_view: ChunkView::new(),
+        }
+    }
 }
 
 pub type PlayerList = &'static Mutex<Vec<Player>>;
+
+#[derive(Clone)]
+pub struct PlayerSample {
+    pub uuid: Uuid,
+    pub nickname: String,
+}
+
+//Copy of the player list that the engine publishes whenever someone joins or leaves,
+//the IO thread reads it for the server list without locking the tick
+pub type PlayerListSnapshot = &'static RwLock<Arc<Vec<PlayerSample>>>;
+
+pub fn publish_snapshot(players: &[Player], snapshot: PlayerListSnapshot) {
+    let samples = players
+        .iter()
+        .map(|player| PlayerSample {
+            uuid: player.uuid,
+            nickname: player.nickname.clone(),
+        })
+        .collect();
+    *snapshot.write().unwrap() = Arc::new(samples);
+}
@@ -1,16 +1,11 @@
-use crate::data_writer::DataWriter;
 use crate::game::chat::ChatComponent;
+use crate::game::chunk_streaming;
 use crate::game::engine::SyncEnvironment;
-use crate::game::nbt::NBTTag;
-use crate::game::packets::{Packet, PlayerInfoAction, PlayerInfoPlayer, Slot, WorldBorderAction};
+use crate::game::packets::{Packet, PlayerInfoAction, PlayerInfoPlayer, WorldBorderAction};
 use crate::game::player::Player;
-use crate::game::world::angle::Angle;
-use crate::game::world::block::{Block, Material};
-use crate::game::world::chunk::{ChunkColumn, ChunkPos, ChunkSection};
-use crate::game::world::coords::{Point, Position};
+use crate::game::world::coords::Position;
 use crate::net::network_manager::NetWriter;
-use std::mem::size_of_val;
-use std::time::{Duration, Instant};
+use mio::Token;
 
 /*
 36 - join game
@@ -30,7 +25,7 @@ use std::time::{Duration, Instant};
  */
 
 pub fn handle_join(player: &mut Player, net_writer: &NetWriter, environment: &mut SyncEnvironment) {
-    println!(
+    log!(
         "Player {} ({}) joined the server",
         player.nickname, player.uuid
     );
@@ -39,11 +34,11 @@ pub fn handle_join(player: &mut Player, net_writer: &NetWriter, environment: &mu
     net_writer.send_packet(
         token,
         Packet::JoinGame {
-            entity_id: 0,
-            gamemode: 1,
-            dimension: 0,
-            difficulty: 0,
-            max_players: 255,
+            entity_id: player.entity_id,
+            gamemode: player.gamemode,
+            dimension: player.dimension,
+            difficulty: environment.world.difficulty,
+            max_players: environment.config.max_players.min(255) as u8,
             level_type: environment.world.level_type.to_str().to_string(),
             reduced_debug_info: false,
         },
@@ -55,33 +50,30 @@ pub fn handle_join(player: &mut Player, net_writer: &NetWriter, environment: &mu
         },
     );
     net_writer.send_packet(token, Packet::HeldItemChange { slot: 0 });
-    net_writer.send_packet(
-        token,
-        Packet::PlayerInfo {
-            action_id: 0,
-            players: vec![PlayerInfoPlayer {
-                uuid: player.uuid.clone(),
-                action: PlayerInfoAction::AddPlayer {
-                    name: player.nickname.clone(),
-                    properties: vec![],
-                    gamemode: 0,
-                    ping: 0,
-                    display_name: Option::from(ChatComponent::new_text(player.nickname.clone())),
-                },
-            }],
+    //Everyone else gets the newcomer in their tab list, and the newcomer gets all of them,
+    //the client needs the entries before it can spawn the players
+    environment.broadcast(
+        &Packet::PlayerInfo {
+            players: vec![add_player(player)],
         },
+        net_writer,
     );
+    let mut players: Vec<PlayerInfoPlayer> =
+        environment.players.iter().map(add_player).collect();
+    players.push(add_player(player));
+    net_writer.send_packet(token, Packet::PlayerInfo { players });
     net_writer.send_packet(
         token,
         Packet::PlayerPositionAndLook {
-            x: 0.0,
-            y: 51.0,
-            z: 0.0,
-            yaw: 0.0,
-            pitch: 0.0,
+            x: player.position.x,
+            y: player.position.y,
+            z: player.position.z,
+            yaw: player.yaw,
+            pitch: player.pitch,
             flags: 0,
         },
     );
+    player.movement.pending_teleport = Some(player.position.clone());
     net_writer.send_packet(
         token,
         Packet::WorldBorder {
@@ -91,84 +83,142 @@ pub fn handle_join(player: &mut Player, net_writer: &NetWriter, environment: &mu
     net_writer.send_packet(
         token,
         Packet::TimeUpdate {
-            world_age: 0,
-            time_of_day: 12000,
+            world_age: environment.world.age,
+            time_of_day: environment.world.time_of_day,
         },
     );
 
-    let now = Instant::now();
-    // let mut vec = Vec::with_capacity(16);
-    // for x in environment.world.chunks.values() {
-    //     vec.push(x.write_chunk_data());
-    // }
-    // println!("total {:?}", now.elapsed());
-    // for x in vec {
-    //     net_writer.send_packet(token, x);
-    // }
-    // let packet = environment.world.get_map_bulk_packet(ChunkPos::default(), 0);
-    // net_writer.send_packet(token, environment.world.get_map_bulk_packet(ChunkPos::default(), 0));
-    // println!("{:?}", now.elapsed());
     net_writer.send_packet(token, Packet::KeepAlive { id: 4 });
 
-    let packet = environment.world.get_map_bulk_packet(ChunkPos::default(), 0);
-    net_writer.send_extended_packet(token, packet);
+    chunk_streaming::update_view(player, environment.world, environment.config, net_writer);
+}
 
-    let mut fake_column = ChunkColumn::new(ChunkPos {x: 1, z: 0});
-    let section = fake_column.allocate_section(2);
-    let stone = Block::from_material(Material { id: 1 });
+//Removes the player of the connection, false if it had no player yet
+pub fn handle_leave(
+    token: Token,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) -> bool {
+    let index = match environment
+        .players
+        .iter()
+        .position(|player| player.token.eq(&token))
+    {
+        Some(t) => t,
+        None => return false,
+    };
+    let player = environment.players.remove(index);
+    //The tracker destroys the entity for everyone that could see it
+    environment.world.entities.remove(player.entity_id);
+    environment.broadcast(
+        &Packet::PlayerInfo {
+            players: vec![PlayerInfoPlayer {
+                uuid: player.uuid,
+                action: PlayerInfoAction::RemovePlayer,
+            }],
+        },
+        net_writer,
+    );
+    log!("Player {} disconnected", player.nickname);
+    true
+}
 
-    for x in 0..16 {
-        for y in 0..16 {
-            for z in 0..16 {
-                section.blocks[x][y][z] = stone.get_encoded();
-            }
-        }
+fn add_player(player: &Player) -> PlayerInfoPlayer {
+    PlayerInfoPlayer {
+        uuid: player.uuid,
+        action: PlayerInfoAction::AddPlayer {
+            name: player.nickname.clone(),
+            properties: player.properties.clone(),
+            gamemode: player.gamemode as i32,
+            ping: 0,
+            display_name: Option::from(ChatComponent::new_text(player.nickname.clone())),
+        },
     }
+}
 
-    let packet = fake_column.write_chunk_data();
+#[test]
+fn shows_players_to_each_other() {
+    use crate::config::ServerConfig;
+    use crate::data_reader::DataReader;
+    use crate::game::engine::TestEnvironment;
+    use crate::game::entity_tracker;
+    use crate::game::world::coords::Point;
+    use crate::net::network_manager::NetProtocol;
+    use std::sync::mpsc::{channel, Receiver};
+    use uuid::Uuid;
+
+    let mut test = TestEnvironment::new(ServerConfig {
+        network_compression_threshold: -1,
+        ..Default::default()
+    });
+    let mut environment = test.environment();
+    let (writer, reader) = channel();
+    let net_writer = NetWriter { writer };
+
+    let join = |environment: &mut SyncEnvironment, token: Token, nickname: &str, uuid: Uuid| {
+        let position = Point {
+            x: 0.0,
+            y: 64.0,
+            z: 0.0,
+        };
+        let mut player = Player::test(token, position.clone());
+        player.nickname = nickname.to_string();
+        player.uuid = uuid;
+        player.entity_id = environment.world.entities.spawn_player(uuid, position).id;
+        handle_join(&mut player, &net_writer, environment);
+        environment.players.push(player);
+    };
+    //Player info, spawn player and destroy entities sent since the last call, without the length
+    let sent = |reader: &Receiver<NetProtocol>| {
+        reader
+            .try_iter()
+            .filter_map(|message| match message {
+                NetProtocol::SendPacket { token, packet } => {
+                    Some((token, packet.serialize().unwrap()))
+                }
+                //Uncompressed frames
+                NetProtocol::SendData { token, packet } => {
+                    let mut reader = DataReader::new(&packet);
+                    let length = reader.read_length().unwrap();
+                    Some((token, reader.read_data_fixed(length).unwrap()))
+                }
+                _ => None,
+            })
+            .filter(|(_, data)| [0x38, 0x0C, 0x13].contains(&data[0]))
+            .collect::<Vec<(Token, Vec<u8>)>>()
+    };
 
-    net_writer.send_extended_packet(token, packet);
+    join(&mut environment, Token(1), "Steve", Uuid::from_u128(1));
+    //Add player with one entry, Steve himself
+    let packets = sent(&reader);
+    assert_eq!(packets.len(), 1);
+    assert!(packets[0].0 == Token(1) && packets[0].1.starts_with(&[0x38, 0, 1]));
+    join(&mut environment, Token(2), "Alex", Uuid::from_u128(2));
+    let packets = sent(&reader);
+    assert_eq!(packets.len(), 2);
+    assert!(packets[0].0 == Token(1) && packets[0].1.starts_with(&[0x38, 0, 1]));
+    assert!(packets[1].0 == Token(2) && packets[1].1.starts_with(&[0x38, 0, 2]));
 
-    // net_writer.send_packet(token, Packet::SpawnObject {
-    //     id: 69,
-    //     object: 60,
-    //     point: Point {x: 0f64, y: 60f64, z: 0f64},
-    //     angle: Angle {pitch: 20, yaw: 20},
-    //     data: 0,
-    //     vel_x: None,
-    //     vel_y: None,
-    //     vel_z: None
-    // })
+    entity_tracker::tick(&net_writer, &mut environment);
+    let mut spawned: Vec<Token> = sent(&reader)
+        .into_iter()
+        .map(|(token, data)| {
+            assert_eq!(data[0], 0x0C);
+            token
+        })
+        .collect();
+    spawned.sort_by_key(|token| token.0);
+    assert_eq!(spawned, [Token(1), Token(2)]);
 
-    // let mut id = 256;
-    // for y in 0..16 {
-    //     for z in 0..16 {
-    //         for x in 0..16 {
-    //             blocks[y][z][x] = (id << 4) | 0;
-    //             id += 1;
-    //         }
-    //     }
-    // }
-    //
-    // std::thread::sleep(Duration::from_secs(1));
-    //
-    // let data = vec![0; (4096 * 2) + (4096 + 256)];
+    assert!(handle_leave(Token(2), &net_writer, &mut environment));
+    assert!(!handle_leave(Token(2), &net_writer, &mut environment));
+    let packets = sent(&reader);
+    assert_eq!(packets.len(), 1);
+    assert!(packets[0].0 == Token(1) && packets[0].1.starts_with(&[0x38, 4, 1]));
+    assert_eq!(environment.players.len(), 1);
 
-    // player.connection.send_packet(&Packet::WindowItems {window_id: 0, slots: vec!(
-    //     Slot {
-    //         item_id: 1,
-    //         item_count: Some(1),
-    //         item_damage: Some(69),
-    //         nbt: Some(NBTTag::Compound {
-    //             compound: vec!(CompoundElement {
-    //                 name: "display".to_owned(),
-    //                 tag: NBTTag::Compound {
-    //                     compound: vec!(
-    //                         CompoundElement {name: "Name".to_owned(), tag: NBTTag::String {string: "Pyrocah".to_owned()}},
-    //                     )
-    //                 }
-    //             })
-    //         })
-    //     }
-    // )});
+    entity_tracker::tick(&net_writer, &mut environment);
+    let packets = sent(&reader);
+    assert_eq!(packets.len(), 1);
+    assert!(packets[0].0 == Token(1) && packets[0].1[0] == 0x13);
 }
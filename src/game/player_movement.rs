@@ -0,0 +1,351 @@
+use crate::game::engine::SyncEnvironment;
+use crate::game::packets::Packet;
+use crate::game::player::Player;
+use crate::game::world::block::Material;
+use crate::game::world::coords::Point;
+use crate::game::world::world::World;
+use crate::net::network_manager::NetWriter;
+
+//Moves further than this are attempts to teleport, even for players that can fly
+const MAX_MOVE_DISTANCE: f64 = 100.0;
+//Blocks a move can cover horizontally, sprint jumping is under 1
+const MAX_SPEED: f64 = 1.5;
+const MAX_FLYING_SPEED: f64 = 4.0;
+//Blocks a move can go up without flying, a jump starts at 0.42
+const MAX_RISE: f64 = 1.0;
+//How high above the last ground the player can be without flying, a jump is 1.25
+const MAX_JUMP_HEIGHT: f64 = 1.5;
+//Moves in the air without going down, a jump goes up for about 6 ticks
+const MAX_AIR_TICKS: u32 = 20;
+//Player hitbox
+const HALF_WIDTH: f64 = 0.3;
+const HEIGHT: f64 = 1.8;
+//Water, lava, cobweb, ladder and vines hold the player in the air
+const SUPPORTING_BLOCKS: [u16; 7] = [8, 9, 10, 11, 30, 65, 106];
+
+//What the server checks the moves against
+pub struct MovementState {
+    //Moves are ignored until the client is where the server sent it
+    pub pending_teleport: Option<Point>,
+    //Where the player last stood, the flying ones are sent back here
+    pub last_ground: Point,
+    pub air_ticks: u32,
+}
+
+impl MovementState {
+    pub fn new(position: Point) -> Self {
+        MovementState {
+            pending_teleport: None,
+            last_ground: position,
+            air_ticks: 0,
+        }
+    }
+}
+
+pub enum MoveError {
+    InvalidPosition,
+    Teleport(f64),
+    TooFast(f64),
+    IntoBlock,
+    Flying,
+}
+
+impl MoveError {
+    pub fn reason(&self) -> String {
+        match self {
+            MoveError::InvalidPosition => "sent an invalid position".to_string(),
+            MoveError::Teleport(distance) => format!("tried to teleport {:.2} blocks", distance),
+            MoveError::TooFast(distance) => {
+                format!("moved too quickly ({:.2} blocks in a tick)", distance)
+            }
+            MoveError::IntoBlock => "moved into a block".to_string(),
+            MoveError::Flying => "was flying without being allowed to".to_string(),
+        }
+    }
+}
+
+//Keeps the position, rotation and on ground the client reports, sending it back
+//to the last valid position when a move isn't possible
+pub fn movement_listener(
+    packet: &Packet,
+    player_index: usize,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) {
+    let (position, look, on_ground) = match packet {
+        Packet::Player { on_ground } => (None, None, *on_ground),
+        Packet::PlayerPosition { x, y, z, on_ground } => (
+            Some(Point {
+                x: *x,
+                y: *y,
+                z: *z,
+            }),
+            None,
+            *on_ground,
+        ),
+        Packet::PlayerLook {
+            yaw,
+            pitch,
+            on_ground,
+        } => (None, Some((*yaw, *pitch)), *on_ground),
+        Packet::ClientPlayerPositionAndLook {
+            x,
+            y,
+            z,
+            yaw,
+            pitch,
+            on_ground,
+        } => (
+            Some(Point {
+                x: *x,
+                y: *y,
+                z: *z,
+            }),
+            Some((*yaw, *pitch)),
+            *on_ground,
+        ),
+        _ => return,
+    };
+    let world = &*environment.world;
+    let player = &mut environment.players[player_index];
+
+    //Moves sent before the client got the teleport are still from where it was
+    if let Some(target) = &player.movement.pending_teleport {
+        match &position {
+            Some(position) if distance(position, target) < 0.01 => {
+                player.movement.pending_teleport = None
+            }
+            _ => return,
+        }
+    }
+
+    if let Some((yaw, pitch)) = look {
+        if yaw.is_finite() && pitch.is_finite() {
+            player.yaw = yaw;
+            player.pitch = pitch.clamp(-90.0, 90.0);
+        }
+    }
+
+    let to = position.unwrap_or_else(|| player.position.clone());
+    match check_move(player, &to, on_ground, world) {
+        Ok(()) => {
+            player.position = to;
+            player.on_ground = on_ground;
+        }
+        Err(e) => {
+            log!(
+                "[Movement] {} {} at {:.2}, {:.2}, {:.2}",
+                player.nickname,
+                e.reason(),
+                player.position.x,
+                player.position.y,
+                player.position.z
+            );
+            let back = match e {
+                MoveError::Flying => player.movement.last_ground.clone(),
+                _ => player.position.clone(),
+            };
+            teleport(player, back, net_writer);
+        }
+    }
+}
+
+//Moves the player, ignoring its moves until the client gets there
+pub fn teleport(player: &mut Player, position: Point, net_writer: &NetWriter) {
+    net_writer.send_packet(
+        player.token,
+        Packet::PlayerPositionAndLook {
+            x: position.x,
+            y: position.y,
+            z: position.z,
+            yaw: player.yaw,
+            pitch: player.pitch,
+            flags: 0,
+        },
+    );
+    player.movement.pending_teleport = Some(position.clone());
+    player.movement.last_ground = position.clone();
+    player.movement.air_ticks = 0;
+    player.position = position;
+}
+
+//Checks a move from the player's position, a packet without one is a move to the same place
+pub fn check_move(
+    player: &mut Player,
+    to: &Point,
+    on_ground: bool,
+    world: &World,
+) -> Result<(), MoveError> {
+    if !to.x.is_finite() || !to.y.is_finite() || !to.z.is_finite() {
+        return Err(MoveError::InvalidPosition);
+    }
+    let from = &player.position;
+    let moved = distance(from, to);
+    if moved > MAX_MOVE_DISTANCE {
+        return Err(MoveError::Teleport(moved));
+    }
+
+    //Creative and spectator
+    let can_fly = player.gamemode == 1 || player.gamemode == 3;
+    let horizontal = ((to.x - from.x).powi(2) + (to.z - from.z).powi(2)).sqrt();
+    let rise = to.y - from.y;
+    let max_speed = if can_fly { MAX_FLYING_SPEED } else { MAX_SPEED };
+    if horizontal > max_speed {
+        return Err(MoveError::TooFast(horizontal));
+    }
+    if !can_fly && rise > MAX_RISE {
+        return Err(MoveError::TooFast(rise));
+    }
+
+    //Spectators go through blocks, and anyone already stuck in one can move out of it
+    if player.gamemode != 3 && collides(world, to) && !collides(world, from) {
+        return Err(MoveError::IntoBlock);
+    }
+
+    let movement = &mut player.movement;
+    let grounded = (on_ground && standing(world, to)) || supported(world, to);
+    if can_fly || grounded {
+        movement.last_ground = to.clone();
+        movement.air_ticks = 0;
+        return Ok(());
+    }
+    if rise >= 0.0 {
+        movement.air_ticks += 1;
+    }
+    if movement.air_ticks > MAX_AIR_TICKS || to.y - movement.last_ground.y > MAX_JUMP_HEIGHT {
+        return Err(MoveError::Flying);
+    }
+    Ok(())
+}
+
+fn distance(a: &Point, b: &Point) -> f64 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt()
+}
+
+//If a full cube is inside the hitbox, which is shrunk a bit so touching one isn't colliding
+fn collides(world: &World, position: &Point) -> bool {
+    let margin = 0.001;
+    any_block(
+        world,
+        (
+            position.x - HALF_WIDTH + margin,
+            position.y + margin,
+            position.z - HALF_WIDTH + margin,
+        ),
+        (
+            position.x + HALF_WIDTH - margin,
+            position.y + HEIGHT - margin,
+            position.z + HALF_WIDTH - margin,
+        ),
+        |id| Material { id }.is_solid(),
+    )
+}
+
+//If there's any block right under the feet, slabs and the like included
+fn standing(world: &World, position: &Point) -> bool {
+    any_block(
+        world,
+        (
+            position.x - HALF_WIDTH,
+            position.y - 0.5,
+            position.z - HALF_WIDTH,
+        ),
+        (position.x + HALF_WIDTH, position.y, position.z + HALF_WIDTH),
+        |id| id != 0,
+    )
+}
+
+//If the player is in a liquid or climbing
+fn supported(world: &World, position: &Point) -> bool {
+    any_block(
+        world,
+        (position.x - HALF_WIDTH, position.y, position.z - HALF_WIDTH),
+        (
+            position.x + HALF_WIDTH,
+            position.y + HEIGHT,
+            position.z + HALF_WIDTH,
+        ),
+        |id| SUPPORTING_BLOCKS.contains(&id),
+    )
+}
+
+fn any_block(
+    world: &World,
+    min: (f64, f64, f64),
+    max: (f64, f64, f64),
+    test: fn(u16) -> bool,
+) -> bool {
+    let min_y = (min.1.floor() as i32).max(0);
+    let max_y = (max.1.floor() as i32).min(255);
+    for x in min.0.floor() as i32..=max.0.floor() as i32 {
+        for y in min_y..=max_y {
+            for z in min.2.floor() as i32..=max.2.floor() as i32 {
+                if test(world.get_block(x, y as i16, z).material.id) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+#[test]
+fn rejects_illegal_moves() {
+    use crate::game::world::block::Block;
+    use crate::game::world::world::LevelType;
+
+    let mut world = World::new("test".to_string(), 0, LevelType::Default);
+    let stone = Block::from_material(Material { id: 1 });
+    for x in -4..4 {
+        for z in -4..8 {
+            world.set_block(stone, x, 63, z);
+        }
+    }
+    world.set_block(stone, 0, 64, 3);
+    world.set_block(stone, 0, 65, 3);
+
+    let start = Point {
+        x: 0.5,
+        y: 64.0,
+        z: 0.5,
+    };
+    let mut player = Player::test(mio::Token(1), start);
+    let step = |player: &mut Player, x: f64, y: f64, z: f64, on_ground: bool| {
+        let to = Point { x, y, z };
+        let result = check_move(player, &to, on_ground, &world);
+        if result.is_ok() {
+            player.position = to;
+        }
+        result
+    };
+
+    assert!(step(&mut player, 0.5, 64.0, 1.5, true).is_ok());
+    assert!(matches!(
+        step(&mut player, 0.5, 64.0, 5.0, true),
+        Err(MoveError::TooFast(_))
+    ));
+    assert!(matches!(
+        step(&mut player, 0.5, 64.0, 300.0, true),
+        Err(MoveError::Teleport(_))
+    ));
+    assert!(matches!(
+        step(&mut player, 0.5, f64::NAN, 1.5, true),
+        Err(MoveError::InvalidPosition)
+    ));
+
+    //The wall at z 3
+    assert!(step(&mut player, 0.5, 64.0, 2.5, true).is_ok());
+    assert!(matches!(
+        step(&mut player, 0.5, 64.0, 3.2, true),
+        Err(MoveError::IntoBlock)
+    ));
+
+    //A jump is fine, going higher isn't
+    assert!(step(&mut player, 0.5, 65.0, 2.5, false).is_ok());
+    assert!(matches!(
+        step(&mut player, 0.5, 65.6, 2.5, false),
+        Err(MoveError::Flying)
+    ));
+    player.gamemode = 1;
+    assert!(step(&mut player, 0.5, 65.6, 2.5, false).is_ok());
+}
@@ -0,0 +1,284 @@
+use crate::game::engine::SyncEnvironment;
+use crate::net::network_manager::NetWriter;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+//Threads running the async jobs
+const WORKERS: usize = 4;
+
+pub type Task = Box<dyn FnMut(&NetWriter, &mut SyncEnvironment)>;
+//What an async job hands back to the tick thread
+pub type Completion = Box<dyn FnOnce(&NetWriter, &mut SyncEnvironment) + Send>;
+type Job = Box<dyn FnOnce() -> Completion + Send>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TaskHandle(u64);
+
+struct ScheduledTask {
+    handle: TaskHandle,
+    //Tick it runs next
+    tick: u64,
+    //Ticks between runs, None runs it only once
+    period: Option<u64>,
+    task: Task,
+}
+
+//Jobs for the tick thread, run by the engine after handling the packets of each tick
+pub struct Scheduler {
+    tick: u64,
+    next_handle: u64,
+    tasks: Vec<ScheduledTask>,
+    //Due this tick and not done yet, they're out of tasks while the tick's tasks run
+    running: Vec<TaskHandle>,
+    jobs: Sender<Job>,
+    completions: Receiver<Completion>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        let (jobs, job_reader) = channel::<Job>();
+        let (completion_writer, completions) = channel();
+
+        //The workers share the job queue and take whichever comes next
+        let job_reader = Arc::new(Mutex::new(job_reader));
+        for i in 0..WORKERS {
+            let job_reader = job_reader.clone();
+            let completion_writer = completion_writer.clone();
+            std::thread::Builder::new()
+                .name(format!("Worker Thread {}", i))
+                .spawn(move || loop {
+                    let job = match job_reader.lock().unwrap().recv() {
+                        Ok(t) => t,
+                        Err(_) => return,
+                    };
+                    //A panicking job loses its result but not the worker
+                    let completion = match catch_unwind(AssertUnwindSafe(job)) {
+                        Ok(t) => t,
+                        Err(_) => {
+                            log!("An async job panicked on Worker Thread {}", i);
+                            continue;
+                        }
+                    };
+                    if completion_writer.send(completion).is_err() {
+                        return;
+                    }
+                })
+                .expect("Couldn't spawn a worker thread");
+        }
+
+        Scheduler {
+            tick: 0,
+            next_handle: 0,
+            tasks: Vec::new(),
+            running: Vec::new(),
+            jobs,
+            completions,
+        }
+    }
+
+    //Runs the task once, delay ticks from now. With 0 it runs the next time tasks do
+    //Nothing in the server needs a one shot task yet, it's here for game logic to build on
+    #[allow(dead_code)]
+    pub fn run_later<F>(&mut self, delay: u64, task: F) -> TaskHandle
+    where
+        F: FnOnce(&NetWriter, &mut SyncEnvironment) + 'static,
+    {
+        let mut task = Some(task);
+        self.schedule(
+            delay,
+            None,
+            Box::new(move |net_writer, environment| {
+                if let Some(task) = task.take() {
+                    task(net_writer, environment);
+                }
+            }),
+        )
+    }
+
+    //Runs the task delay ticks from now and then every period ticks, until it's cancelled
+    pub fn run_repeating<F>(&mut self, delay: u64, period: u64, task: F) -> TaskHandle
+    where
+        F: FnMut(&NetWriter, &mut SyncEnvironment) + 'static,
+    {
+        self.schedule(delay, Some(period.max(1)), Box::new(task))
+    }
+
+    fn schedule(&mut self, delay: u64, period: Option<u64>, task: Task) -> TaskHandle {
+        let handle = TaskHandle(self.next_handle);
+        self.next_handle += 1;
+        self.tasks.push(ScheduledTask {
+            handle,
+            tick: self.tick + delay,
+            period,
+            task,
+        });
+        handle
+    }
+
+    //False if the task already ran or was cancelled
+    //The autosave is never cancelled, this is for tasks that belong to a player or an entity
+    #[allow(dead_code)]
+    pub fn cancel(&mut self, handle: TaskHandle) -> bool {
+        if let Some(index) = self.tasks.iter().position(|task| task.handle == handle) {
+            self.tasks.remove(index);
+            return true;
+        }
+        //Due this tick, it's skipped, or not put back if it's the repeating one running now
+        match self.running.iter().position(|t| *t == handle) {
+            Some(index) => {
+                self.running.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    //Runs the job on a worker thread, then passes its result to then on the tick thread.
+    //For disk IO, lookups and anything else too slow for a tick, nothing uses it yet
+    #[allow(dead_code)]
+    pub fn run_async<T, J, F>(&self, job: J, then: F)
+    where
+        T: Send + 'static,
+        J: FnOnce() -> T + Send + 'static,
+        F: FnOnce(T, &NetWriter, &mut SyncEnvironment) + Send + 'static,
+    {
+        let job: Job = Box::new(move || {
+            let result = job();
+            Box::new(
+                move |net_writer: &NetWriter, environment: &mut SyncEnvironment| {
+                    then(result, net_writer, environment)
+                },
+            )
+        });
+        self.jobs.send(job).expect("The worker threads stopped");
+    }
+}
+
+//Runs the finished async jobs and the tasks due this tick, then advances the scheduler a tick
+pub fn run_tasks(net_writer: &NetWriter, environment: &mut SyncEnvironment) {
+    let completions: Vec<Completion> = environment.scheduler.completions.try_iter().collect();
+    for completion in completions {
+        completion(net_writer, environment);
+    }
+
+    //The tasks are taken out while they run, so they can schedule and cancel others
+    let tick = environment.scheduler.tick;
+    let (due, waiting): (Vec<ScheduledTask>, _) = std::mem::take(&mut environment.scheduler.tasks)
+        .into_iter()
+        .partition(|task| task.tick <= tick);
+    environment.scheduler.tasks = waiting;
+    environment.scheduler.running = due.iter().map(|task| task.handle).collect();
+
+    for mut task in due {
+        let scheduler = &mut *environment.scheduler;
+        let index = match scheduler.running.iter().position(|t| *t == task.handle) {
+            Some(t) => t,
+            //Cancelled by a task that ran before it
+            None => continue,
+        };
+        //Once it starts, a task that runs once is done
+        if task.period.is_none() {
+            scheduler.running.remove(index);
+        }
+        (task.task)(net_writer, environment);
+
+        if let Some(period) = task.period {
+            let running = &mut environment.scheduler.running;
+            if let Some(index) = running.iter().position(|t| *t == task.handle) {
+                running.remove(index);
+                task.tick = tick + period;
+                environment.scheduler.tasks.push(task);
+            }
+        }
+    }
+
+    environment.scheduler.tick += 1;
+}
+
+#[test]
+fn runs_tasks_on_their_ticks() {
+    use crate::config::ServerConfig;
+    use crate::game::engine::TestEnvironment;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut test = TestEnvironment::new(ServerConfig::default());
+    let mut environment = test.environment();
+    let net_writer = NetWriter {
+        writer: channel().0,
+    };
+
+    let ran = Rc::new(RefCell::new(Vec::new()));
+    let log = ran.clone();
+    environment
+        .scheduler
+        .run_later(2, move |_, _| log.borrow_mut().push("later"));
+    let log = ran.clone();
+    let repeating = environment
+        .scheduler
+        .run_repeating(0, 3, move |_, environment| {
+            log.borrow_mut().push("repeating");
+            //Tasks can schedule others while they run
+            let log = log.clone();
+            environment
+                .scheduler
+                .run_later(0, move |_, _| log.borrow_mut().push("nested"));
+        });
+    let cancelled = environment
+        .scheduler
+        .run_later(1, |_, _| panic!("cancelled"));
+    assert!(environment.scheduler.cancel(cancelled));
+
+    for _ in 0..4 {
+        run_tasks(&net_writer, &mut environment);
+    }
+    assert_eq!(*ran.borrow(), ["repeating", "nested", "later", "repeating"]);
+    assert!(environment.scheduler.cancel(repeating));
+    assert!(!environment.scheduler.cancel(repeating));
+
+    //Cancelling a task due the same tick, and a repeating task cancelling itself
+    let cancels = Rc::new(RefCell::new(Vec::new()));
+    let (skipped, own) = (Rc::new(RefCell::new(None)), Rc::new(RefCell::new(None)));
+    let (log, handle) = (cancels.clone(), skipped.clone());
+    environment.scheduler.run_later(0, move |_, environment| {
+        let cancelled = environment.scheduler.cancel(handle.borrow().unwrap());
+        log.borrow_mut().push(cancelled);
+    });
+    *skipped.borrow_mut() = Some(
+        environment
+            .scheduler
+            .run_later(0, |_, _| panic!("cancelled")),
+    );
+    let (log, handle) = (cancels.clone(), own.clone());
+    *own.borrow_mut() = Some(
+        environment
+            .scheduler
+            .run_repeating(0, 1, move |_, environment| {
+                let cancelled = environment.scheduler.cancel(handle.borrow().unwrap());
+                log.borrow_mut().push(cancelled);
+            }),
+    );
+    run_tasks(&net_writer, &mut environment);
+    run_tasks(&net_writer, &mut environment);
+    assert_eq!(*cancels.borrow(), [true, true]);
+
+    //Workers outlive the jobs that panic
+    for _ in 0..WORKERS {
+        environment
+            .scheduler
+            .run_async(|| panic!("job"), |_: (), _, _| {});
+    }
+    environment.scheduler.run_async(
+        || 40 + 2,
+        |result, _, environment| environment.world.age = result,
+    );
+    for _ in 0..100 {
+        run_tasks(&net_writer, &mut environment);
+        if environment.world.age == 42 {
+            return;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    panic!("The async job never finished");
+}
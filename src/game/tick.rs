@@ -0,0 +1,113 @@
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+pub const TICKS_PER_SECOND: u32 = 20;
+pub const TICK_DURATION: Duration = Duration::from_millis(50);
+//Further behind than this the missed ticks are skipped instead of run back to back
+const MAX_LAG: Duration = Duration::from_secs(2);
+//1, 5 and 15 minutes, in seconds
+const WINDOWS: [f64; 3] = [60.0, 300.0, 900.0];
+
+#[derive(Copy, Clone)]
+pub struct TickStats {
+    //Ticks per second and milliseconds per tick, averaged over the last 1, 5 and 15 minutes
+    pub tps: [f64; 3],
+    pub mspt: [f64; 3],
+}
+
+impl Default for TickStats {
+    fn default() -> Self {
+        TickStats {
+            tps: [TICKS_PER_SECOND as f64; 3],
+            mspt: [0.0; 3],
+        }
+    }
+}
+
+//Copy of the stats that the engine publishes every second, for the IO thread
+pub type TickStatsSnapshot = &'static RwLock<TickStats>;
+
+//Keeps the tick loop at 20 TPS, sleeping only what's left of the 50 ms after each tick
+pub struct TickTimer {
+    next_tick: Instant,
+    tick_start: Instant,
+    //Start of the second the TPS is being measured over
+    second_start: Instant,
+    ticks: u32,
+    pub stats: TickStats,
+    snapshot: TickStatsSnapshot,
+}
+
+impl TickTimer {
+    pub fn new(snapshot: TickStatsSnapshot) -> Self {
+        let now = Instant::now();
+        TickTimer {
+            next_tick: now,
+            tick_start: now,
+            second_start: now,
+            ticks: 0,
+            stats: TickStats::default(),
+            snapshot,
+        }
+    }
+
+    pub fn start_tick(&mut self) {
+        self.tick_start = Instant::now();
+    }
+
+    //Records how long the tick took and waits for the next one
+    pub fn finish_tick(&mut self) {
+        let now = Instant::now();
+        let mspt = (now - self.tick_start).as_secs_f64() * 1000.0;
+        for (average_mspt, window) in self.stats.mspt.iter_mut().zip(WINDOWS.iter()) {
+            *average_mspt = average(*average_mspt, mspt, TICK_DURATION.as_secs_f64(), *window);
+        }
+
+        self.ticks += 1;
+        if self.ticks.is_multiple_of(TICKS_PER_SECOND) {
+            let elapsed = (now - self.second_start).as_secs_f64();
+            //Catching up runs more than 20 ticks in a second, but that's not a faster server
+            let tps = (TICKS_PER_SECOND as f64 / elapsed).min(TICKS_PER_SECOND as f64);
+            for (average_tps, window) in self.stats.tps.iter_mut().zip(WINDOWS.iter()) {
+                *average_tps = average(*average_tps, tps, elapsed, *window);
+            }
+            self.second_start = now;
+            *self.snapshot.write().unwrap() = self.stats;
+        }
+
+        self.next_tick += TICK_DURATION;
+        if now < self.next_tick {
+            std::thread::sleep(self.next_tick - now);
+        } else if now - self.next_tick > MAX_LAG {
+            let behind = now - self.next_tick;
+            log!(
+                "Can't keep up! Is the server overloaded? Running {} ms behind, skipping {} ticks",
+                behind.as_millis(),
+                behind.as_millis() / TICK_DURATION.as_millis()
+            );
+            self.next_tick = now;
+        }
+    }
+}
+
+//Exponential moving average, a sample weights as much as the share of the window it covers
+fn average(current: f64, sample: f64, elapsed: f64, window: f64) -> f64 {
+    let weight = (elapsed / window).min(1.0);
+    current + (sample - current) * weight
+}
+
+#[test]
+fn averages_follow_the_samples() {
+    let mut tps = 20.0;
+    for _ in 0..60 {
+        tps = average(tps, 10.0, 1.0, WINDOWS[0]);
+    }
+    //After a whole window it's most of the way to the new value
+    assert!(tps > 10.0 && tps < 14.0, "{}", tps);
+
+    let mut slow = 20.0;
+    for _ in 0..60 {
+        slow = average(slow, 10.0, 1.0, WINDOWS[2]);
+    }
+    assert!(slow > tps);
+}
@@ -3,4 +3,5 @@ pub mod block;
 pub mod chunk;
 pub mod coords;
 pub mod generator;
+pub mod region;
 pub mod world;
@@ -3,3 +3,17 @@ pub struct Angle {
     pub pitch: u8,
     pub yaw: u8,
 }
+
+impl Angle {
+    pub fn from_degrees(yaw: f32, pitch: f32) -> Angle {
+        Angle {
+            pitch: to_steps(pitch),
+            yaw: to_steps(yaw),
+        }
+    }
+}
+
+//A full turn is 256 steps, wrapping around like the client does
+pub fn to_steps(degrees: f32) -> u8 {
+    (degrees * 256.0 / 360.0).floor() as i32 as u8
+}
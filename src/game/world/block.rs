@@ -48,3 +48,74 @@ impl Clone for Block {
 pub struct Material {
     pub id: u16,
 }
+
+const FULL_CUBES: [u16; 78] = [
+    1, 2, 3, 4, 5, 7, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 35, 41, 42, 43, 45,
+    46, 47, 48, 49, 52, 56, 57, 58, 61, 62, 73, 74, 79, 80, 82, 84, 86, 87, 89, 91, 95, 97, 98, 99,
+    100, 103, 110, 112, 121, 123, 124, 125, 129, 133, 137, 138, 152, 153, 155, 158, 159, 161, 162,
+    165, 166, 168, 169, 170, 172, 173, 174, 179, 181,
+];
+
+//Names of the 1.8 blocks, indexed by id
+pub const MATERIAL_NAMES: [&str; 198] = [
+    "air", "stone", "grass", "dirt", "cobblestone", "planks", "sapling", "bedrock", "flowing_water",
+    "water", "flowing_lava", "lava", "sand", "gravel", "gold_ore", "iron_ore", "coal_ore", "log",
+    "leaves", "sponge", "glass", "lapis_ore", "lapis_block", "dispenser", "sandstone", "noteblock",
+    "bed", "golden_rail", "detector_rail", "sticky_piston", "web", "tallgrass", "deadbush",
+    "piston", "piston_head", "wool", "piston_extension", "yellow_flower", "red_flower",
+    "brown_mushroom", "red_mushroom", "gold_block", "iron_block", "double_stone_slab", "stone_slab",
+    "brick_block", "tnt", "bookshelf", "mossy_cobblestone", "obsidian", "torch", "fire",
+    "mob_spawner", "oak_stairs", "chest", "redstone_wire", "diamond_ore", "diamond_block",
+    "crafting_table", "wheat", "farmland", "furnace", "lit_furnace", "standing_sign", "wooden_door",
+    "ladder", "rail", "stone_stairs", "wall_sign", "lever", "stone_pressure_plate", "iron_door",
+    "wooden_pressure_plate", "redstone_ore", "lit_redstone_ore", "unlit_redstone_torch",
+    "redstone_torch", "stone_button", "snow_layer", "ice", "snow", "cactus", "clay", "reeds",
+    "jukebox", "fence", "pumpkin", "netherrack", "soul_sand", "glowstone", "portal", "lit_pumpkin",
+    "cake", "unpowered_repeater", "powered_repeater", "stained_glass", "trapdoor", "monster_egg",
+    "stonebrick", "brown_mushroom_block", "red_mushroom_block", "iron_bars", "glass_pane",
+    "melon_block", "pumpkin_stem", "melon_stem", "vine", "fence_gate", "brick_stairs",
+    "stone_brick_stairs", "mycelium", "waterlily", "nether_brick", "nether_brick_fence",
+    "nether_brick_stairs", "nether_wart", "enchanting_table", "brewing_stand", "cauldron",
+    "end_portal", "end_portal_frame", "end_stone", "dragon_egg", "redstone_lamp",
+    "lit_redstone_lamp", "double_wooden_slab", "wooden_slab", "cocoa", "sandstone_stairs",
+    "emerald_ore", "ender_chest", "tripwire_hook", "tripwire", "emerald_block", "spruce_stairs",
+    "birch_stairs", "jungle_stairs", "command_block", "beacon", "cobblestone_wall", "flower_pot",
+    "carrots", "potatoes", "wooden_button", "skull", "anvil", "trapped_chest",
+    "light_weighted_pressure_plate", "heavy_weighted_pressure_plate", "unpowered_comparator",
+    "powered_comparator", "daylight_detector", "redstone_block", "quartz_ore", "hopper",
+    "quartz_block", "quartz_stairs", "activator_rail", "dropper", "stained_hardened_clay",
+    "stained_glass_pane", "leaves2", "log2", "acacia_stairs", "dark_oak_stairs", "slime", "barrier",
+    "iron_trapdoor", "prismarine", "sea_lantern", "hay_block", "carpet", "hardened_clay",
+    "coal_block", "packed_ice", "double_plant", "standing_banner", "wall_banner",
+    "daylight_detector_inverted", "red_sandstone", "red_sandstone_stairs", "double_stone_slab2",
+    "stone_slab2", "spruce_fence_gate", "birch_fence_gate", "jungle_fence_gate",
+    "dark_oak_fence_gate", "acacia_fence_gate", "spruce_fence", "birch_fence", "jungle_fence",
+    "dark_oak_fence", "acacia_fence", "spruce_door", "birch_door", "jungle_door", "acacia_door",
+    "dark_oak_door",
+];
+
+impl Material {
+    pub fn name(&self) -> Option<&'static str> {
+        MATERIAL_NAMES.get(self.id as usize).copied()
+    }
+
+    //Full cubes, the only blocks movement is checked against. Slabs, stairs, fences and the
+    //like are partial and skipped, as are blocks that change shape like doors
+    pub fn is_solid(&self) -> bool {
+        FULL_CUBES.contains(&self.id)
+    }
+
+    //Accepts the name with or without the minecraft: namespace, or the numeric id
+    pub fn from_name(name: &str) -> Option<Material> {
+        let name = name.strip_prefix("minecraft:").unwrap_or(name);
+        if let Ok(id) = name.parse::<u16>() {
+            return MATERIAL_NAMES
+                .get(id as usize)
+                .map(|_| Material { id });
+        }
+        MATERIAL_NAMES
+            .iter()
+            .position(|t| *t == name)
+            .map(|id| Material { id: id as u16 })
+    }
+}
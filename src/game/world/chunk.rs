@@ -6,6 +6,8 @@ use std::mem::size_of_val;
 use std::str::from_boxed_utf8_unchecked;
 use std::time::{Duration, Instant};
 use regex::internal::Inst;
+use fxhash::FxHashMap;
+use crate::game::nbt::NBTTag;
 
 #[derive(Copy, Clone, Default, Eq, PartialEq, Hash)]
 pub struct ChunkPos {
@@ -31,6 +33,11 @@ pub struct ChunkSection {
 pub struct ChunkColumn {
     chunk_pos: ChunkPos,
     pub sections: [Option<Box<ChunkSection>>; 16],
+    pub biomes: [u8; 256],
+    //Set whenever the column changes, so saving only rewrites what was modified
+    pub dirty: bool,
+    //Level tags we don't handle yet (entities, tile entities...), kept to be saved back untouched
+    pub extra_nbt: FxHashMap<String, NBTTag>,
 }
 
 impl ChunkSection {
@@ -57,6 +64,9 @@ impl ChunkColumn {
         ChunkColumn {
             chunk_pos,
             sections: Default::default(),
+            biomes: [0; 256],
+            dirty: true,
+            extra_nbt: FxHashMap::default(),
         }
     }
 
@@ -65,6 +75,7 @@ impl ChunkColumn {
             return self.sections[section].as_mut().unwrap();
         }
         self.sections[section] = Some(Box::new(ChunkSection::new()));
+        self.dirty = true;
         self.sections[section].as_mut().unwrap()
     }
 
@@ -72,6 +83,7 @@ impl ChunkColumn {
         let section = self.allocate_section((y / 16) as usize);
 
         section.blocks[(y % 16) as usize][z as usize][x as usize] = block.get_encoded();
+        self.dirty = true;
     }
 
     pub fn get_block(&self, y: u8, z: u8, x: u8) -> Block {
@@ -118,7 +130,7 @@ impl ChunkColumn {
         iterator.clone().for_each(|x| data.extend_from_slice(&x.block_light));
         iterator.clone().for_each(|x| data.extend_from_slice(&x.sky_light));
 
-        data.extend_from_slice(&[0u8; 256]);
+        data.extend_from_slice(&self.biomes);
     }
 
     #[inline]
@@ -11,6 +11,15 @@ impl Position {
             | ((self.y as i64 & 0xFFF) << 26)
             | (self.z as i64 & 0x3FFFFFF)
     }
+
+    //Inverse of encode, the shifts keep the sign of each component
+    pub const fn decode(value: i64) -> Position {
+        Position {
+            x: (value >> 38) as i32,
+            y: ((value << 26) >> 52) as i16,
+            z: ((value << 38) >> 38) as i32,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq)]
@@ -17,5 +17,5 @@ pub fn generate(world: &mut World) {
     world.set_block(stone, -1, 50, 0);
     let now = Instant::now();
     world.flood_fill_light(15, 0, 50, 0);
-    println!("{:?}", now.elapsed());
+    log!("{:?}", now.elapsed());
 }
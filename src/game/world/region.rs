@@ -0,0 +1,653 @@
+use crate::game::nbt::{NBTParseError, NBTTag};
+use crate::game::world::chunk::{ChunkColumn, ChunkPos, ChunkSection};
+use crate::game::world::world::World;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use fxhash::FxHashMap;
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+//Anvil files are split in 4KiB sectors, the first two are the location and timestamp tables
+pub const SECTOR_SIZE: usize = 4096;
+pub const CHUNKS_PER_REGION: usize = 1024;
+
+pub const COMPRESSION_GZIP: u8 = 1;
+pub const COMPRESSION_ZLIB: u8 = 2;
+
+#[derive(Copy, Clone, Default, Eq, PartialEq, Hash, Debug)]
+pub struct RegionPos {
+    pub x: i32,
+    pub z: i32,
+}
+
+impl RegionPos {
+    pub fn from_chunk_pos(chunk_pos: &ChunkPos) -> Self {
+        RegionPos {
+            x: chunk_pos.x >> 5,
+            z: chunk_pos.z >> 5,
+        }
+    }
+
+    //Parses names like r.-1.2.mca
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let mut split = name.split('.');
+        if split.next()? != "r" {
+            return None;
+        }
+        let x = split.next()?.parse().ok()?;
+        let z = split.next()?.parse().ok()?;
+        if split.next()? != "mca" || split.next().is_some() {
+            return None;
+        }
+        Some(RegionPos { x, z })
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("r.{}.{}.mca", self.x, self.z)
+    }
+
+    //Index of a chunk inside the location and timestamp tables
+    #[inline]
+    pub fn chunk_index(chunk_pos: &ChunkPos) -> usize {
+        ((chunk_pos.x & 31) + (chunk_pos.z & 31) * 32) as usize
+    }
+}
+
+pub struct RegionFile {
+    pub pos: RegionPos,
+    data: Vec<u8>,
+}
+
+impl RegionFile {
+    pub fn open(path: &Path, pos: RegionPos) -> Result<Self, RegionError> {
+        let data = std::fs::read(path)?;
+
+        if data.len() < SECTOR_SIZE * 2 {
+            return Err(RegionError::InvalidRegion("Region file smaller than its header"));
+        }
+
+        Ok(RegionFile { pos, data })
+    }
+
+    //Offset in sectors and size in sectors, None if the chunk was never generated
+    pub fn location(&self, index: usize) -> Option<(usize, usize)> {
+        let entry = (&self.data[index * 4..index * 4 + 4]).read_u32::<BigEndian>().ok()?;
+        let offset = (entry >> 8) as usize;
+        let sectors = (entry & 0xFF) as usize;
+
+        if offset == 0 || sectors == 0 {
+            return None;
+        }
+        Some((offset, sectors))
+    }
+
+    pub fn timestamp(&self, index: usize) -> u32 {
+        let start = SECTOR_SIZE + index * 4;
+        (&self.data[start..start + 4]).read_u32::<BigEndian>().unwrap_or(0)
+    }
+
+    //Returns the compression scheme and the still compressed chunk payload
+    pub fn raw_chunk(&self, index: usize) -> Result<Option<(u8, &[u8])>, RegionError> {
+        let (offset, sectors) = match self.location(index) {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+
+        let start = offset * SECTOR_SIZE;
+        if start + 5 > self.data.len() {
+            return Err(RegionError::InvalidRegion("Chunk offset outside of the region file"));
+        }
+
+        let mut header = &self.data[start..start + 5];
+        let length = header.read_u32::<BigEndian>()? as usize;
+        let compression = header.read_u8()?;
+
+        //The length includes the compression byte
+        if length == 0 || length > sectors * SECTOR_SIZE || start + 4 + length > self.data.len() {
+            return Err(RegionError::InvalidRegion("Chunk length outside of its sectors"));
+        }
+
+        Ok(Some((compression, &self.data[start + 5..start + 4 + length])))
+    }
+
+    pub fn read_chunk_nbt(&self, index: usize) -> Result<Option<NBTTag>, RegionError> {
+        let (compression, payload) = match self.raw_chunk(index)? {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+
+        let mut decompressed = Vec::with_capacity(payload.len() * 4);
+        match compression {
+            COMPRESSION_GZIP => {
+                libflate::gzip::Decoder::new(payload)?.read_to_end(&mut decompressed)?;
+            }
+            COMPRESSION_ZLIB => {
+                libflate::zlib::Decoder::new(payload)?.read_to_end(&mut decompressed)?;
+            }
+            x => return Err(RegionError::InvalidCompression(x)),
+        };
+
+        let (nbt, _) = NBTTag::read(&mut Cursor::new(decompressed), true, None)?;
+        Ok(Some(nbt))
+    }
+
+    pub fn read_chunk(&self, index: usize) -> Result<Option<ChunkColumn>, RegionError> {
+        match self.read_chunk_nbt(index)? {
+            Some(nbt) => Ok(Some(decode_chunk(nbt)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+//Decodes the 1.8 "Level" compound into a column
+pub fn decode_chunk(nbt: NBTTag) -> Result<ChunkColumn, RegionError> {
+    let mut level = match nbt {
+        NBTTag::Compound { mut compound } => match compound.remove("Level") {
+            Some(NBTTag::Compound { compound }) => compound,
+            _ => return Err(RegionError::InvalidChunk("Missing Level compound")),
+        },
+        _ => return Err(RegionError::InvalidChunk("Chunk root is not a compound")),
+    };
+
+    let x = match level.remove("xPos") {
+        Some(NBTTag::Int { int }) => int,
+        _ => return Err(RegionError::InvalidChunk("Missing xPos")),
+    };
+    let z = match level.remove("zPos") {
+        Some(NBTTag::Int { int }) => int,
+        _ => return Err(RegionError::InvalidChunk("Missing zPos")),
+    };
+
+    let mut column = ChunkColumn::new(ChunkPos { x, z });
+
+    if let Some(NBTTag::ByteArray { bytes }) = level.remove("Biomes") {
+        if bytes.len() == column.biomes.len() {
+            column.biomes.copy_from_slice(&bytes);
+        }
+    }
+
+    let sections = match level.remove("Sections") {
+        Some(NBTTag::List { list, .. }) => list,
+        //Empty chunks may be saved without sections
+        None => Vec::new(),
+        _ => return Err(RegionError::InvalidChunk("Sections is not a list")),
+    };
+
+    for nbt_section in &sections {
+        let y = match nbt_section.get("Y") {
+            Some(NBTTag::Byte { byte }) if (0..16).contains(byte) => *byte as usize,
+            _ => return Err(RegionError::InvalidChunk("Invalid section Y")),
+        };
+
+        let blocks = byte_array(nbt_section, "Blocks", 4096)?;
+        let data = byte_array(nbt_section, "Data", 2048)?;
+        let add = match nbt_section.get("Add") {
+            Some(_) => Some(byte_array(nbt_section, "Add", 2048)?),
+            None => None,
+        };
+
+        let section = column.allocate_section(y);
+        decode_section(section, blocks, data, add);
+        section
+            .block_light
+            .copy_from_slice(byte_array(nbt_section, "BlockLight", 2048)?);
+        section
+            .sky_light
+            .copy_from_slice(byte_array(nbt_section, "SkyLight", 2048)?);
+    }
+
+    //Recalculated when saving
+    level.remove("HeightMap");
+    level.remove("LastUpdate");
+
+    column.extra_nbt = level;
+    column.dirty = false;
+    Ok(column)
+}
+
+fn decode_section(section: &mut ChunkSection, blocks: &[u8], data: &[u8], add: Option<&[u8]>) {
+    //Anvil indexes blocks as YZX, same order of our section arrays
+    let encoded = bytemuck::cast_slice_mut::<_, u16>(&mut section.blocks);
+
+    for (index, block) in encoded.iter_mut().enumerate() {
+        let mut id = blocks[index] as u16;
+        if let Some(add) = add {
+            id |= (nibble(add, index) as u16) << 8;
+        }
+        *block = id << 4 | nibble(data, index) as u16;
+    }
+}
+
+//Nibble arrays store the even index in the lower half of the byte
+#[inline]
+pub fn nibble(array: &[u8], index: usize) -> u8 {
+    if index.is_multiple_of(2) {
+        array[index / 2] & 0x0F
+    } else {
+        array[index / 2] >> 4
+    }
+}
+
+fn byte_array<'a>(nbt: &'a NBTTag, name: &'static str, length: usize) -> Result<&'a [u8], RegionError> {
+    match nbt.get(name) {
+        Some(NBTTag::ByteArray { bytes }) if bytes.len() == length => Ok(bytes),
+        _ => Err(RegionError::MissingArray(name)),
+    }
+}
+
+//Loads every r.x.z.mca inside the directory into the world, returns how many chunks were loaded
+pub fn load_world(world: &mut World, directory: &Path) -> Result<usize, RegionError> {
+    let mut loaded = 0usize;
+
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        let pos = match path
+            .file_name()
+            .and_then(|x| x.to_str())
+            .and_then(RegionPos::from_file_name)
+        {
+            Some(t) => t,
+            None => continue,
+        };
+
+        let region = RegionFile::open(&path, pos)?;
+        for index in 0..CHUNKS_PER_REGION {
+            let column = match region.read_chunk(index) {
+                Ok(Some(t)) => t,
+                Ok(None) => continue,
+                Err(e) => {
+                    log!(
+                        "Skipping corrupted chunk {} of region {}: {}",
+                        index,
+                        region.pos.file_name(),
+                        e.reason()
+                    );
+                    continue;
+                }
+            };
+
+            world.chunks.insert(*column.get_chunk_pos(), column);
+            loaded += 1;
+        }
+    }
+
+    Ok(loaded)
+}
+
+//Encodes a column as the 1.8 chunk compound, the inverse of decode_chunk
+pub fn encode_chunk(column: &ChunkColumn, last_update: i64) -> NBTTag {
+    let mut level = FxHashMap::default();
+
+    //Unknown tags first, so the ones we own always win
+    for (name, tag) in column.extra_nbt.iter() {
+        level.insert(name.clone(), tag.clone());
+    }
+
+    let pos = column.get_chunk_pos();
+    level.insert("xPos".to_string(), NBTTag::Int { int: pos.x });
+    level.insert("zPos".to_string(), NBTTag::Int { int: pos.z });
+    level.insert("LastUpdate".to_string(), NBTTag::Long { long: last_update });
+    level.insert(
+        "Biomes".to_string(),
+        NBTTag::ByteArray {
+            bytes: column.biomes.to_vec(),
+        },
+    );
+    level.insert(
+        "HeightMap".to_string(),
+        NBTTag::IntArray {
+            array: height_map(column),
+        },
+    );
+
+    let mut sections = Vec::new();
+    for (y, section) in column.sections.iter().enumerate() {
+        let section = match section {
+            Some(t) => t,
+            None => continue,
+        };
+        sections.push(encode_section(section, y as i8));
+    }
+    level.insert(
+        "Sections".to_string(),
+        NBTTag::List {
+            list: sections,
+            type_id: 10,
+        },
+    );
+
+    for name in ["Entities", "TileEntities"].iter() {
+        if !level.contains_key(*name) {
+            level.insert(
+                name.to_string(),
+                NBTTag::List {
+                    list: Vec::new(),
+                    type_id: 10,
+                },
+            );
+        }
+    }
+    for name in ["TerrainPopulated", "LightPopulated"].iter() {
+        if !level.contains_key(*name) {
+            level.insert(name.to_string(), NBTTag::Byte { byte: 1 });
+        }
+    }
+
+    let mut root = FxHashMap::default();
+    root.insert("Level".to_string(), NBTTag::Compound { compound: level });
+    NBTTag::Compound { compound: root }
+}
+
+fn encode_section(section: &ChunkSection, y: i8) -> NBTTag {
+    let encoded = bytemuck::cast_slice::<_, u16>(&section.blocks);
+
+    let mut blocks = vec![0u8; 4096];
+    let mut data = vec![0u8; 2048];
+    let mut add = vec![0u8; 2048];
+    let mut has_add = false;
+
+    for (index, block) in encoded.iter().enumerate() {
+        let id = block >> 4;
+        blocks[index] = id as u8;
+        set_nibble(&mut data, index, (block & 0xF) as u8);
+        if id > 0xFF {
+            set_nibble(&mut add, index, (id >> 8) as u8);
+            has_add = true;
+        }
+    }
+
+    let mut compound = FxHashMap::default();
+    compound.insert("Y".to_string(), NBTTag::Byte { byte: y });
+    compound.insert("Blocks".to_string(), NBTTag::ByteArray { bytes: blocks });
+    compound.insert("Data".to_string(), NBTTag::ByteArray { bytes: data });
+    if has_add {
+        compound.insert("Add".to_string(), NBTTag::ByteArray { bytes: add });
+    }
+    compound.insert(
+        "BlockLight".to_string(),
+        NBTTag::ByteArray {
+            bytes: section.block_light.to_vec(),
+        },
+    );
+    compound.insert(
+        "SkyLight".to_string(),
+        NBTTag::ByteArray {
+            bytes: section.sky_light.to_vec(),
+        },
+    );
+    NBTTag::Compound { compound }
+}
+
+#[inline]
+fn set_nibble(array: &mut [u8], index: usize, value: u8) {
+    if index.is_multiple_of(2) {
+        array[index / 2] = (array[index / 2] & 0xF0) | (value & 0x0F);
+    } else {
+        array[index / 2] = (array[index / 2] & 0x0F) | (value << 4);
+    }
+}
+
+//One above the highest non-air block of every XZ column, indexed z * 16 + x
+fn height_map(column: &ChunkColumn) -> Vec<i32> {
+    let mut heights = vec![0i32; 256];
+
+    for z in 0..16u8 {
+        for x in 0..16u8 {
+            for y in (0..=255u8).rev() {
+                if column.get_block(y, z, x).material.id != 0 {
+                    heights[z as usize * 16 + x as usize] = y as i32 + 1;
+                    break;
+                }
+            }
+        }
+    }
+
+    heights
+}
+
+pub fn compress_chunk(nbt: &NBTTag) -> Result<Vec<u8>, RegionError> {
+    let mut data = Vec::with_capacity(16384);
+    nbt.write(&mut data, Some(&String::new()), true);
+
+    let mut encoder = libflate::zlib::Encoder::new(Vec::with_capacity(data.len() / 4))?;
+    encoder.write_all(&data)?;
+    Ok(encoder.finish().into_result()?)
+}
+
+//Builds a region file in memory, chunks are laid out sector aligned after both tables
+pub struct RegionWriter {
+    locations: [u32; CHUNKS_PER_REGION],
+    timestamps: [u32; CHUNKS_PER_REGION],
+    sectors: Vec<u8>,
+}
+
+impl RegionWriter {
+    pub fn new() -> Self {
+        RegionWriter {
+            locations: [0; CHUNKS_PER_REGION],
+            timestamps: [0; CHUNKS_PER_REGION],
+            sectors: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, index: usize, compression: u8, payload: &[u8], timestamp: u32) -> Result<(), RegionError> {
+        let length = payload.len() + 5;
+        let sector_count = length.div_ceil(SECTOR_SIZE);
+        if sector_count > 0xFF {
+            return Err(RegionError::InvalidChunk("Chunk bigger than 255 sectors"));
+        }
+
+        let offset = 2 + self.sectors.len() / SECTOR_SIZE;
+        self.locations[index] = (offset as u32) << 8 | sector_count as u32;
+        self.timestamps[index] = timestamp;
+
+        self.sectors.write_u32::<BigEndian>(payload.len() as u32 + 1)?;
+        self.sectors.write_u8(compression)?;
+        self.sectors.extend_from_slice(payload);
+        self.sectors
+            .resize(self.sectors.len() + sector_count * SECTOR_SIZE - length, 0);
+        Ok(())
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(SECTOR_SIZE * 2 + self.sectors.len());
+        for location in self.locations.iter() {
+            data.extend_from_slice(&location.to_be_bytes());
+        }
+        for timestamp in self.timestamps.iter() {
+            data.extend_from_slice(&timestamp.to_be_bytes());
+        }
+        data.extend_from_slice(&self.sectors);
+        data
+    }
+}
+
+//Rewrites every region holding a dirty chunk, returns how many chunks were saved
+pub fn save_world(world: &mut World, directory: &Path) -> Result<usize, RegionError> {
+    let mut regions: HashMap<RegionPos, Vec<ChunkPos>> = HashMap::new();
+    for (pos, column) in world.chunks.iter() {
+        if column.dirty {
+            regions
+                .entry(RegionPos::from_chunk_pos(pos))
+                .or_default()
+                .push(*pos);
+        }
+    }
+
+    if regions.is_empty() {
+        return Ok(0);
+    }
+
+    std::fs::create_dir_all(directory)?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    let mut saved = 0usize;
+
+    for (region_pos, dirty) in regions {
+        let path = directory.join(region_pos.file_name());
+        let old = if path.is_file() {
+            Some(RegionFile::open(&path, region_pos)?)
+        } else {
+            None
+        };
+
+        let mut dirty_chunks: [Option<&ChunkColumn>; CHUNKS_PER_REGION] = [None; CHUNKS_PER_REGION];
+        for pos in dirty.iter() {
+            dirty_chunks[RegionPos::chunk_index(pos)] = world.chunks.get(pos);
+        }
+
+        let mut writer = RegionWriter::new();
+        for (index, column) in dirty_chunks.iter().enumerate() {
+            match column {
+                Some(column) => {
+                    let compressed = compress_chunk(&encode_chunk(column, now as i64))?;
+                    writer.push(index, COMPRESSION_ZLIB, &compressed, now as u32)?;
+                }
+                //Untouched chunks are copied still compressed
+                None => {
+                    if let Some(old) = &old {
+                        if let Some((compression, payload)) = old.raw_chunk(index)? {
+                            writer.push(index, compression, payload, old.timestamp(index))?;
+                        }
+                    }
+                }
+            }
+        }
+
+        //Write to a temporary file first so a crash mid save doesn't corrupt the region
+        let temporary = path.with_extension("mca.tmp");
+        std::fs::write(&temporary, writer.finish())?;
+        std::fs::rename(&temporary, &path)?;
+
+        for pos in dirty.iter() {
+            if let Some(column) = world.chunks.get_mut(pos) {
+                column.dirty = false;
+            }
+        }
+        saved += dirty.len();
+    }
+
+    Ok(saved)
+}
+
+#[derive(Debug)]
+pub enum RegionError {
+    IOError(std::io::Error),
+    NBTError(NBTParseError),
+    InvalidCompression(u8),
+    InvalidRegion(&'static str),
+    InvalidChunk(&'static str),
+    MissingArray(&'static str),
+}
+
+impl RegionError {
+    pub fn reason(&self) -> String {
+        match self {
+            RegionError::IOError(e) => e.to_string(),
+            RegionError::NBTError(e) => format!("Invalid NBT: {}", e.reason()),
+            RegionError::InvalidCompression(compression) => {
+                format!("Unknown compression type {}", compression)
+            }
+            RegionError::InvalidRegion(reason) => reason.to_string(),
+            RegionError::InvalidChunk(reason) => reason.to_string(),
+            RegionError::MissingArray(name) => format!("Missing {} array", name),
+        }
+    }
+}
+
+impl From<std::io::Error> for RegionError {
+    fn from(e: std::io::Error) -> Self {
+        RegionError::IOError(e)
+    }
+}
+
+impl From<NBTParseError> for RegionError {
+    fn from(e: NBTParseError) -> Self {
+        RegionError::NBTError(e)
+    }
+}
+
+#[test]
+fn chunks_round_trip() {
+    use crate::game::world::block::{Block, Material};
+    use crate::game::world::world::LevelType;
+
+    let build = || {
+        let mut column = ChunkColumn::new(ChunkPos { x: -3, z: 40 });
+        column.set_block(Block::from_material_and_metadata(Material { id: 35 }, 14), 70, 3, 9);
+        //Ids past 255 need the Add array
+        column.set_block(Block::from_material(Material { id: 300 }), 0, 15, 0);
+        let section = column.allocate_section(4);
+        section.block_light[100] = 0xA5;
+        section.sky_light[2047] = 0xF0;
+        column.biomes[17] = 4;
+        column
+            .extra_nbt
+            .insert("InhabitedTime".to_string(), NBTTag::Long { long: 1234 });
+        column
+    };
+    let check = |decoded: &ChunkColumn| {
+        let column = build();
+        assert!(decoded.get_chunk_pos() == column.get_chunk_pos());
+        for (section, decoded) in column.sections.iter().zip(decoded.sections.iter()) {
+            match (section, decoded) {
+                (Some(section), Some(decoded)) => {
+                    assert!(section.blocks == decoded.blocks);
+                    assert!(section.block_light == decoded.block_light);
+                    assert!(section.sky_light == decoded.sky_light);
+                }
+                (None, None) => {}
+                _ => panic!("Sections don't match"),
+            }
+        }
+        assert!(column.biomes == decoded.biomes);
+        assert!(matches!(
+            decoded.extra_nbt.get("InhabitedTime"),
+            Some(NBTTag::Long { long: 1234 })
+        ));
+        assert!(!decoded.dirty);
+    };
+
+    let nbt = encode_chunk(&build(), 0);
+    match nbt.get("Level").and_then(|level| level.get("HeightMap")) {
+        Some(NBTTag::IntArray { array }) => {
+            assert_eq!(array[3 * 16 + 9], 71);
+            assert_eq!(array[15 * 16], 1);
+            assert_eq!(array[0], 0);
+        }
+        _ => panic!("Missing HeightMap"),
+    }
+    check(&decode_chunk(nbt).unwrap());
+
+    //Through a region file on disk
+    let directory = std::env::temp_dir().join(format!("amethyst-region-{}", std::process::id()));
+    let mut world = World::new("test".to_string(), 0, LevelType::Default);
+    world.chunks.insert(ChunkPos { x: -3, z: 40 }, build());
+    assert_eq!(save_world(&mut world, &directory).unwrap(), 1);
+    assert_eq!(save_world(&mut world, &directory).unwrap(), 0);
+
+    let mut loaded = World::new("test".to_string(), 0, LevelType::Default);
+    let count = load_world(&mut loaded, &directory);
+    assert_eq!(count.unwrap(), 1);
+    check(&loaded.chunks[&ChunkPos { x: -3, z: 40 }]);
+
+    //Vanilla only writes zlib, gzip chunks are read all the same
+    let pos = ChunkPos { x: -3, z: 40 };
+    let mut data = Vec::new();
+    encode_chunk(&build(), 0).write(&mut data, Some(&String::new()), true);
+    let mut encoder = libflate::gzip::Encoder::new(Vec::new()).unwrap();
+    encoder.write_all(&data).unwrap();
+    let mut writer = RegionWriter::new();
+    let index = RegionPos::chunk_index(&pos);
+    let payload = encoder.finish().into_result().unwrap();
+    writer.push(index, COMPRESSION_GZIP, &payload, 0).unwrap();
+    let path = directory.join("gzip.mca");
+    std::fs::write(&path, writer.finish()).unwrap();
+    let region = RegionFile::open(&path, RegionPos::from_chunk_pos(&pos));
+    std::fs::remove_dir_all(&directory).unwrap();
+    check(&region.unwrap().read_chunk(index).unwrap().unwrap());
+}
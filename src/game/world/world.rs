@@ -1,5 +1,6 @@
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use crate::game::entity::Entities;
 use crate::game::world::block::Block;
 use crate::game::world::chunk::{ChunkColumn, ChunkPos, ChunkSection};
 use crate::game::world::coords::Position;
@@ -13,6 +14,10 @@ pub struct World {
     pub difficulty: u8,
     pub level_type: LevelType,
     pub chunks: HashMap<ChunkPos, ChunkColumn>,
+    //Ticks since the world was created, and the time of the day cycle (0 is sunrise, 24000 a full day)
+    pub age: i64,
+    pub time_of_day: i64,
+    pub entities: Entities,
 }
 
 impl World {
@@ -22,6 +27,9 @@ impl World {
             difficulty,
             level_type,
             chunks: HashMap::new(),
+            age: 0,
+            time_of_day: 12000,
+            entities: Entities::new(),
         }
     }
 
@@ -29,6 +37,7 @@ impl World {
         let chunk_pos = ChunkPos::from_block_coords(x, z);
 
         let chunk = self.allocate_chunk(chunk_pos);
+        chunk.dirty = true;
         let section = chunk.allocate_section((y / 16) as usize);
 
         section.blocks[(y.abs() % 16) as usize][(z.rem_euclid(16)) as usize]
@@ -36,6 +45,9 @@ impl World {
     }
 
     pub fn get_block(&self, x: i32, y: i16, z: i32) -> Block {
+        if !(0..=255).contains(&y) {
+            return Block::default();
+        }
         let chunk_pos = ChunkPos::from_block_coords(x, z);
 
         return match self.get_chunk(chunk_pos) {
@@ -45,7 +57,8 @@ impl World {
                 match &t.sections[section_pos as usize] {
                     None => Block::default(),
                     Some(section) => Block::from_encoded(
-                        section.blocks[(y % 16) as usize][z as usize][x as usize],
+                        section.blocks[(y % 16) as usize][z.rem_euclid(16) as usize]
+                            [x.rem_euclid(16) as usize],
                     ),
                 }
             }
@@ -56,6 +69,7 @@ impl World {
         let chunk_pos = ChunkPos::from_block_coords(x, z);
 
         let chunk = self.allocate_chunk(chunk_pos);
+        chunk.dirty = true;
         let section = chunk.allocate_section((y / 16) as usize);
 
         let x = (x.rem_euclid(16)) as usize;
@@ -76,6 +90,7 @@ impl World {
         let chunk_pos = ChunkPos::from_block_coords(x, z);
 
         let chunk = self.allocate_chunk(chunk_pos);
+        chunk.dirty = true;
         let section = chunk.allocate_section((y / 16) as usize);
 
         let x = (x.rem_euclid(16)) as usize;
@@ -131,15 +146,20 @@ impl World {
         }
     }
 
-    pub fn get_map_bulk_packet(&self, center: ChunkPos, radius: u8) -> ExtendedPacket {
-        let mut chunks = Vec::with_capacity(self.chunks.len());
+    //Chunks that aren't in the world are left out
+    pub fn get_map_bulk_packet(&self, positions: &[ChunkPos]) -> ExtendedPacket {
+        let columns: Vec<&ChunkColumn> = positions
+            .iter()
+            .filter_map(|pos| self.chunks.get(pos))
+            .collect();
+        let mut chunks = Vec::with_capacity(columns.len());
         let mut data_size = 0usize;
 
-        for (pos, chunk) in self.chunks.iter() {
+        for chunk in columns.iter() {
             let bitmask = chunk.bitmask();
 
             chunks.push(ChunkMeta {
-                pos: *pos,
+                pos: *chunk.get_chunk_pos(),
                 bitmask: bitmask.0
             });
 
@@ -148,12 +168,13 @@ impl World {
 
         let mut data = Vec::with_capacity(data_size);
 
-        self.chunks.values().for_each(|chunk| chunk.write(&mut data));
+        columns.iter().for_each(|chunk| chunk.write(&mut data));
 
         ExtendedPacket::MapChunkBulk {sky_light: true, chunks, data}
     }
 }
 
+#[derive(Copy, Clone)]
 pub enum LevelType {
     Default,
     Flat,
@@ -163,6 +184,17 @@ pub enum LevelType {
 }
 
 impl LevelType {
+    pub fn from_name(name: &str) -> Option<LevelType> {
+        match name.to_ascii_lowercase().as_str() {
+            "default" => Some(LevelType::Default),
+            "flat" => Some(LevelType::Flat),
+            "largebiomes" => Some(LevelType::LargeBiomes),
+            "amplified" => Some(LevelType::Amplified),
+            "default_1_1" => Some(LevelType::Default11),
+            _ => None,
+        }
+    }
+
     pub fn to_str(&self) -> &str {
         match self {
             LevelType::Default => "default",
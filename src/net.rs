@@ -1,3 +1,4 @@
+pub mod compression;
 pub mod https;
 pub mod login_handler;
 pub mod network_manager;
@@ -0,0 +1,115 @@
+use crate::data_reader::DataReader;
+use crate::data_writer::DataWriter;
+use std::io::{Read, Write};
+
+//Biggest uncompressed packet the vanilla client accepts
+pub const MAX_UNCOMPRESSED_LENGTH: usize = 2097152;
+
+//Wraps a serialized packet (id + fields) in its frame
+//Without a threshold it's the plain length prefixed format, otherwise the data length varint
+//is added and the body is zlib compressed when it reaches the threshold
+pub fn frame(mut data: Vec<u8>, threshold: Option<usize>) -> Vec<u8> {
+    let threshold = match threshold {
+        Some(t) => t,
+        None => {
+            data.splice(0..0, DataWriter::var_num(data.len() as u64));
+            return data;
+        }
+    };
+
+    if data.len() < threshold {
+        //Data length 0 means not compressed
+        data.insert(0, 0);
+        data.splice(0..0, DataWriter::var_num(data.len() as u64));
+        return data;
+    }
+
+    let data_length = DataWriter::var_num(data.len() as u64);
+    let compressed = compress(&data);
+
+    let mut writer = DataWriter::with_capacity(compressed.len() + 10);
+    writer.write_varint((data_length.len() + compressed.len()) as i32);
+    writer.write_data(&data_length);
+    writer.write_data(&compressed);
+    writer.data
+}
+
+//Reads the body of a frame (everything after the packet length), returning the id + fields
+pub fn unframe(body: &[u8], compressed: bool) -> Option<Vec<u8>> {
+    if !compressed {
+        return Some(body.to_vec());
+    }
+
+    let mut reader = DataReader::new(body);
+    let data_length = reader.read_varint().ok()?;
+    let rest = &body[reader.cursor..];
+
+    if data_length == 0 {
+        return Some(rest.to_vec());
+    }
+
+    if data_length < 0 || data_length as usize > MAX_UNCOMPRESSED_LENGTH {
+        return None;
+    }
+
+    let data = decompress(rest, data_length as usize)?;
+    if data.len() != data_length as usize {
+        return None;
+    }
+    Some(data)
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = libflate::zlib::Encoder::new(Vec::with_capacity(data.len() / 2))
+        .expect("Couldn't create zlib encoder");
+    encoder
+        .write_all(data)
+        .expect("Writing to a vector can't fail");
+    encoder
+        .finish()
+        .into_result()
+        .expect("Writing to a vector can't fail")
+}
+
+//Never decompresses more than length + 1 bytes, so a wrong data length can't blow the memory
+pub fn decompress(data: &[u8], length: usize) -> Option<Vec<u8>> {
+    let decoder = libflate::zlib::Decoder::new(data).ok()?;
+    let mut decompressed = Vec::with_capacity(length);
+    decoder
+        .take(length as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .ok()?;
+    Some(decompressed)
+}
+
+#[test]
+fn frames_by_threshold() {
+    let packet = vec![0x01, 0x02, 0x03];
+    //Without compression only the length is added
+    assert_eq!(frame(packet.clone(), None), [3, 1, 2, 3]);
+    assert_eq!(unframe(&[1, 2, 3], false).unwrap(), packet);
+
+    //Below the threshold the data length is 0 and the packet is left as is
+    let framed = frame(packet.clone(), Some(256));
+    assert_eq!(framed, [4, 0, 1, 2, 3]);
+    assert_eq!(unframe(&framed[1..], true).unwrap(), packet);
+
+    //From the threshold up it's compressed, the data length is the uncompressed one
+    let big = vec![7u8; 1000];
+    let framed = frame(big.clone(), Some(1000));
+    let mut reader = DataReader::new(&framed);
+    let length = reader.read_varint().unwrap() as usize;
+    let body = &framed[reader.cursor..];
+    assert_eq!(length, body.len());
+    assert!(body.len() < big.len());
+    assert_eq!(DataReader::new(body).read_varint().unwrap(), 1000);
+    assert_eq!(unframe(body, true).unwrap(), big);
+
+    //Data lengths past the limit or not matching what's inflated are rejected
+    let mut body = DataWriter::var_num(MAX_UNCOMPRESSED_LENGTH as u64 + 1).to_vec();
+    body.extend_from_slice(&compress(&[0; 16]));
+    assert!(unframe(&body, true).is_none());
+    let mut body = DataWriter::var_num(10).to_vec();
+    body.extend_from_slice(&compress(&[0; 20]));
+    assert!(unframe(&body, true).is_none());
+}
@@ -1,31 +1,259 @@
-use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use openssl::ssl::{SslConnector, SslMethod};
 use std::io::{Read, Write};
-use std::net::TcpStream;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
 
-pub struct HttpsConnection {}
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+//Minimal blocking HTTP client, enough for the session server requests
+//https urls go through openssl, plain http is accepted so tests can use a local mock
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+struct Url {
+    secure: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Option<Url> {
+        let (secure, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return None;
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+
+        let default_port = if secure { 443 } else { 80 };
+        let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+            //IPv6 literal, its colons are inside the brackets and the port comes after them
+            let end = rest.find(']')?;
+            let port = match &rest[end + 1..] {
+                "" => default_port,
+                t => t.strip_prefix(':')?.parse().ok()?,
+            };
+            (&rest[..end], port)
+        } else {
+            match authority.rfind(':') {
+                Some(index) => (&authority[..index], authority[index + 1..].parse().ok()?),
+                None => (authority, default_port),
+            }
+        };
+
+        if host.is_empty() {
+            return None;
+        }
+
+        Some(Url {
+            secure,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    //IPv6 addresses go back in brackets
+    fn host_header(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        }
+    }
+}
+
+pub fn get(url: &str) -> Result<HttpResponse, HttpError> {
+    let url = Url::parse(url).ok_or(HttpError::InvalidUrl)?;
+
+    let addr = (url.host.as_str(), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or(HttpError::InvalidUrl)?;
+    let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: Amethyst\r\nAccept: application/json\r\nConnection: close\r\n\r\n",
+        url.path,
+        url.host_header()
+    );
+
+    let raw = if url.secure {
+        let connector = SslConnector::builder(SslMethod::tls_client())
+            .map_err(|e| HttpError::SslError(e.to_string()))?
+            .build();
+        let mut stream = connector
+            .connect(&url.host, stream)
+            .map_err(|e| HttpError::SslError(e.to_string()))?;
+        exchange(&mut stream, request.as_bytes())?
+    } else {
+        let mut stream = stream;
+        exchange(&mut stream, request.as_bytes())?
+    };
+
+    parse_response(&raw).ok_or(HttpError::InvalidResponse)
+}
+
+fn exchange<S: Read + Write>(stream: &mut S, request: &[u8]) -> Result<Vec<u8>, HttpError> {
+    stream.write_all(request)?;
+    stream.flush()?;
+
+    let mut response = Vec::with_capacity(4096);
+    let mut buffer = [0u8; 4096];
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => response.extend_from_slice(&buffer[..read]),
+            //Some servers close the TLS connection without notifying, what we got may still be complete
+            Err(_) if parse_response(&response).is_some() => break,
+            Err(e) => return Err(HttpError::IOError(e)),
+        }
+    }
+
+    Ok(response)
+}
+
+fn parse_response(raw: &[u8]) -> Option<HttpResponse> {
+    let header_end = raw.windows(4).position(|x| x == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..header_end]).ok()?;
+    let body = &raw[header_end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines.next()?.split(' ').nth(1)?.parse().ok()?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    for line in lines {
+        let mut split = line.splitn(2, ':');
+        let name = split.next()?.trim().to_ascii_lowercase();
+        let value = split.next().unwrap_or("").trim();
+        match name.as_str() {
+            "content-length" => content_length = value.parse::<usize>().ok(),
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            _ => {}
+        }
+    }
+
+    let body = if chunked {
+        dechunk(body)?
+    } else if let Some(length) = content_length {
+        if body.len() < length {
+            return None;
+        }
+        body[..length].to_vec()
+    } else {
+        body.to_vec()
+    };
+
+    Some(HttpResponse { status, body })
+}
+
+fn dechunk(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::with_capacity(data.len());
+    loop {
+        let line_end = data.windows(2).position(|x| x == b"\r\n")?;
+        let size = std::str::from_utf8(&data[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        data = &data[line_end + 2..];
+
+        if size == 0 {
+            return Some(body);
+        }
+        if data.len() < size + 2 {
+            return None;
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[size + 2..];
+    }
+}
+
+//Percent encodes everything but the unreserved characters, for query values
+pub fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    InvalidUrl,
+    InvalidResponse,
+    IOError(std::io::Error),
+    SslError(String),
+}
+
+impl HttpError {
+    pub fn reason(&self) -> String {
+        match self {
+            HttpError::InvalidUrl => "Invalid URL".to_string(),
+            HttpError::InvalidResponse => "Invalid HTTP response".to_string(),
+            HttpError::IOError(e) => e.to_string(),
+            HttpError::SslError(e) => format!("SSL error: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for HttpError {
+    fn from(e: std::io::Error) -> Self {
+        HttpError::IOError(e)
+    }
+}
 
 #[test]
+fn parses_urls() {
+    let url =
+        Url::parse("https://sessionserver.mojang.com/session/minecraft/hasJoined?a=b").unwrap();
+    assert!(url.secure);
+    assert_eq!(
+        (url.host.as_str(), url.port),
+        ("sessionserver.mojang.com", 443)
+    );
+    assert_eq!(url.path, "/session/minecraft/hasJoined?a=b");
+
+    let url = Url::parse("http://127.0.0.1:8080").unwrap();
+    assert_eq!(
+        (url.host.as_str(), url.port, url.path.as_str()),
+        ("127.0.0.1", 8080, "/")
+    );
+
+    let url = Url::parse("http://[::1]:8080/profile").unwrap();
+    assert_eq!((url.host.as_str(), url.port), ("::1", 8080));
+    assert_eq!(url.host_header(), "[::1]");
+    let url = Url::parse("https://[2001:db8::1]/").unwrap();
+    assert_eq!((url.host.as_str(), url.port), ("2001:db8::1", 443));
+
+    assert!(Url::parse("http://[::1:8080/").is_none());
+    assert!(Url::parse("http://[::1]8080/").is_none());
+    assert!(Url::parse("ftp://example.com").is_none());
+}
+
+//Talks to the live Mojang session server, run it with --ignored
+#[test]
+#[ignore]
 pub fn test() {
-    let mut connector = SslConnector::builder(SslMethod::tls_client()).unwrap();
-    connector.set_verify(SslVerifyMode::NONE);
-    let connector = connector.build();
-
-    let mut stream = connector
-        .connect(
-            "sessionserver.mojang.com",
-            TcpStream::connect("sessionserver.mojang.com:443").unwrap(),
-        )
-        .unwrap();
-
-    println!("{:?}", stream);
-
-    let get = b"GET /session/minecraft/profile/0ecda3389eeb413e962958bd0d552e5e HTTP/1.0\
-    \nHost: sessionserver.mojang.com\
-    \nConnection: keep-alive\
-    \nDate: Tue, 02 Mar 2021 20:39:17
-    \r\n\r\n";
-    stream.write_all(get).unwrap();
-    let mut res = vec![];
-    stream.read_to_end(&mut res).unwrap();
-    println!("{}", String::from_utf8_lossy(&res));
+    let response = get(
+        "https://sessionserver.mojang.com/session/minecraft/profile/0ecda3389eeb413e962958bd0d552e5e",
+    )
+    .unwrap();
+
+    log!("{}", response.status);
+    log!("{}", String::from_utf8_lossy(&response.body));
 }
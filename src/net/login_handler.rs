@@ -1,70 +1,143 @@
-use crate::data_reader::DataReader;
+use crate::config::ServerConfig;
+use crate::data_reader::{DataReader, PacketError};
 use crate::game::chat::ChatComponent;
-use crate::game::packets::Packet;
+use crate::game::packets::{Packet, PlayerInfoProperties};
+use crate::game::player::PlayerListSnapshot;
+use crate::game::tick::TickStatsSnapshot;
+use crate::net::https;
+use crate::net::https::url_encode;
 use crate::net::network_manager::{ConnectionState, PlayerLoginClient, RawPacket};
 use aes::cipher::NewStreamCipher;
 use aes::Aes128;
 use cfb8::Cfb8;
 use json::number::Number;
 use json::JsonValue;
+use openssl::hash::{hash, MessageDigest};
 use openssl::pkey::Private;
 use openssl::rsa::{Padding, Rsa};
 use openssl::sha::Sha1;
 use rand::{thread_rng, Rng};
 use regex::Regex;
+use rand::seq::SliceRandom;
+use rustc_serialize::base64::{ToBase64, STANDARD};
 use rustc_serialize::hex::ToHex;
+use std::path::Path;
 use std::str::FromStr;
 use uuid::Uuid;
 
 pub enum HandleResult {
-    Disconnect(&'static str),
+    Disconnect(String),
     Login,
+    //The session server has to be asked if the player joined with this server hash
+    Authenticate(String),
     None,
 }
 
-pub fn handle(packets: Vec<RawPacket>, client: &mut PlayerLoginClient) -> HandleResult {
+pub struct GameProfile {
+    pub uuid: Uuid,
+    pub name: String,
+    pub properties: Vec<PlayerInfoProperties>,
+}
+
+//The version advertised in the server list
+pub const PROTOCOL_VERSION: i32 = 47;
+pub const VERSION_NAME: &str = "1.8.9";
+
+pub struct ProtocolVersion {
+    pub id: i32,
+    pub name: &'static str,
+}
+
+//Every version clients can join with, oldest first. Serving another one starts by adding it here
+pub const SUPPORTED_VERSIONS: &[ProtocolVersion] = &[ProtocolVersion {
+    id: PROTOCOL_VERSION,
+    name: VERSION_NAME,
+}];
+
+//Looks up the protocol a client handshaked with, giving the vanilla kick message when it isn't served
+pub fn check_version(protocol: i32) -> Result<&'static ProtocolVersion, String> {
+    if let Some(version) = SUPPORTED_VERSIONS.iter().find(|x| x.id == protocol) {
+        return Ok(version);
+    }
+
+    let oldest = &SUPPORTED_VERSIONS[0];
+    let newest = &SUPPORTED_VERSIONS[SUPPORTED_VERSIONS.len() - 1];
+    if protocol < oldest.id {
+        Err(format!("Outdated client! Please use {}", newest.name))
+    } else {
+        Err(format!("Outdated server! I'm still on {}", newest.name))
+    }
+}
+
+pub const FAVICON_FILE: &str = "server-icon.png";
+
+//What the IO thread hands to the login handler
+pub struct LoginContext {
+    pub config: &'static ServerConfig,
+    pub players: PlayerListSnapshot,
+    pub tick_stats: TickStatsSnapshot,
+    //server-icon.png already as a data url
+    pub favicon: Option<String>,
+}
+
+pub fn handle(
+    packets: Vec<RawPacket>,
+    client: &mut PlayerLoginClient,
+    context: &LoginContext,
+) -> Result<HandleResult, PacketError> {
+    let config = context.config;
+
     let mut result = HandleResult::None;
 
     for raw in packets {
-        let packet = match Packet::read(raw.id, &mut DataReader::new(raw.data), client.state) {
-            Some(t) => t,
-            None => continue,
-        };
+        let packet = Packet::read(raw.id, &mut DataReader::new(&raw.data), client.state)?;
         match packet {
             Packet::Handshake {
                 next_state,
                 protocol_version,
                 server_address,
                 server_port,
-            } => match next_state {
-                1 => client.state = ConnectionState::Status,
-                2 => client.state = ConnectionState::Login,
-                _ => {
-                    result = HandleResult::Disconnect("Invalid handshake next state");
-                    break;
+            } => {
+                client.protocol_version = protocol_version;
+                match next_state {
+                    //The status answers every version, the client compares the protocol itself
+                    1 => client.state = ConnectionState::Status,
+                    2 => {
+                        client.state = ConnectionState::Login;
+                        if let Err(reason) = check_version(protocol_version) {
+                            result = HandleResult::Disconnect(reason);
+                            break;
+                        }
+                    }
+                    _ => {
+                        result =
+                            HandleResult::Disconnect("Invalid handshake next state".to_string());
+                        break;
+                    }
                 }
-            },
-            Packet::StatusRequest => {
-                let mut json = JsonValue::new_object();
-                let mut version = JsonValue::new_object();
-                version["name"] = JsonValue::String("1.8.9".to_owned());
-                version["protocol"] = JsonValue::Number(Number::from(47 as u8));
-                json["version"] = version;
-                let mut players = JsonValue::new_object();
-                players["max"] = JsonValue::Number(Number::from(10 as u8));
-                players["online"] = JsonValue::Number(Number::from(0 as u8));
-                json["players"] = players;
-                json["description"] =
-                    ChatComponent::new_text("Amethyst Minecraft Server".to_owned()).to_json();
-                client.write(Packet::StatusResponse { json });
             }
+            Packet::StatusRequest => client.write(Packet::StatusResponse {
+                json: status_json(context),
+            }),
             Packet::Ping { ping } => client.write(Packet::Pong { pong: ping }),
             Packet::LoginStart { nickname } => {
+                //Offline mode goes straight to the login success, without encryption
+                if !config.online_mode {
+                    let profile = GameProfile {
+                        uuid: offline_uuid(&nickname),
+                        name: nickname,
+                        properties: Vec::new(),
+                    };
+                    finish_login(client, profile, config);
+                    result = HandleResult::Login;
+                    break;
+                }
+
                 client.verify_token = Some(thread_rng().gen::<[u8; 4]>());
                 client.write(Packet::EncryptionRequest {
                     server: String::new(),
                     public_key: get_publick_key().clone(),
-                    verify_token: client.verify_token.unwrap().clone(),
+                    verify_token: client.verify_token.unwrap().to_vec(),
                 });
                 client.connection.identifier = nickname.clone();
                 client.nickname = Some(nickname)
@@ -82,13 +155,21 @@ pub fn handle(packets: Vec<RawPacket>, client: &mut PlayerLoginClient) -> Handle
                 ) {
                     Ok(_t) => {}
                     Err(_e) => {
-                        result = HandleResult::Disconnect("Invalid verify token");
+                        result = HandleResult::Disconnect("Invalid verify token".to_string());
                         break;
                     }
                 };
 
-                if !decrypted_verify_token[0..4].eq(&client.verify_token.unwrap()) {
-                    result = HandleResult::Disconnect("Wrong verify token");
+                let expected_verify_token = match client.verify_token.take() {
+                    Some(t) => t,
+                    None => {
+                        result =
+                            HandleResult::Disconnect("Unexpected encryption response".to_string());
+                        break;
+                    }
+                };
+                if !decrypted_verify_token[0..4].eq(&expected_verify_token) {
+                    result = HandleResult::Disconnect("Wrong verify token".to_string());
                     break;
                 }
 
@@ -100,7 +181,7 @@ pub fn handle(packets: Vec<RawPacket>, client: &mut PlayerLoginClient) -> Handle
                 ) {
                     Ok(t) => t,
                     Err(_e) => {
-                        result = HandleResult::Disconnect("Invalid shared secret");
+                        result = HandleResult::Disconnect("Invalid shared secret".to_string());
                         break;
                     }
                 };
@@ -116,63 +197,204 @@ pub fn handle(packets: Vec<RawPacket>, client: &mut PlayerLoginClient) -> Handle
                 sha1.update(&shared_secret);
                 sha1.update(&rsa.public_key_to_der().unwrap());
 
-                // let response = match reqwest::blocking::Client::new().get(&format!("https://sessionserver.mojang.com/session/minecraft/hasJoined?username={}&serverId={}", client.nickname.as_ref().unwrap(), hex_digest(sha1)))
-                //     .send() {
-                //     Ok(ok) => ok,
-                //     Err(e) => {
-                //         println!("Error while contacting sessionserver.mojang.com to login a player: {}, {}", client.nickname.as_ref().unwrap(), e);
-                //         return HandleResult::Disconnect("Couldn't decrypt shared secret")
-                //     }
-                // };
-                // let response_code = response.status().as_u16();
-                // if response_code == 204 {
-                //     return HandleResult::Disconnect("Client not authenticated.")
-                // }
-                // let json = match response.text() {
-                //     Ok(ok) => {
-                //         match json::parse(&ok) {
-                //             Ok(ok) => ok,
-                //             Err(e) => {
-                //                 println!("Error while parsing login response to json: {}, {}", client.nickname.as_ref().unwrap(), e);
-                //                 return HandleResult::Disconnect("An error occured while contacting Mojang.")
-                //             }
-                //         }
-                //     },
-                //     Err(e) => {
-                //         println!("Error while parsing login response to text: {}, {}", client.nickname.as_ref().unwrap(), e);
-                //         return HandleResult::Disconnect("An error occured while contacting Mojang.")
-                //     }
-                // };
-                //
-                // let data = match parse_json(json) {
-                //     Some(t) => t,
-                //     None => {
-                //         println!("Error while parsing login response data to text: {}", client.nickname.as_ref().unwrap());
-                //         return HandleResult::Disconnect("An error occured while contacting Mojang.")
-                //     }
-                // };
-
-                client.uuid = Some(Uuid::default());
-                client.nickname = Some("britney bitch".to_string());
-
-                client.write(Packet::LoginSuccess {
-                    uuid: client.uuid.clone().unwrap(),
-                    nickname: client.nickname.clone().unwrap(),
-                });
-                result = HandleResult::Login;
+                result = HandleResult::Authenticate(hex_digest(sha1));
                 break;
             }
             _ => {
-                result = HandleResult::Disconnect("Unknown packet for this connection state");
+                result = HandleResult::Disconnect(
+                    "Unknown packet for this connection state".to_string(),
+                );
                 break;
             }
         }
     }
 
-    result
+    Ok(result)
+}
+
+//Server list response, built from the player list the engine published last
+pub fn status_json(context: &LoginContext) -> JsonValue {
+    let online = context.players.read().unwrap().clone();
+
+    let mut json = JsonValue::new_object();
+    let mut version = JsonValue::new_object();
+    version["name"] = JsonValue::String(VERSION_NAME.to_owned());
+    version["protocol"] = JsonValue::Number(Number::from(PROTOCOL_VERSION));
+    json["version"] = version;
+
+    let mut players = JsonValue::new_object();
+    players["max"] = JsonValue::Number(Number::from(context.config.max_players));
+    players["online"] = JsonValue::Number(Number::from(online.len()));
+    let sample = online
+        .choose_multiple(&mut thread_rng(), context.config.player_sample_size)
+        .map(|player| {
+            let mut entry = JsonValue::new_object();
+            entry["name"] = JsonValue::String(player.nickname.clone());
+            entry["id"] = JsonValue::String(player.uuid.to_hyphenated().to_string());
+            entry
+        })
+        .collect();
+    players["sample"] = JsonValue::Array(sample);
+    json["players"] = players;
+
+    json["description"] = ChatComponent::new_text(context.config.motd.clone()).to_json();
+
+    //Not read by the client, for tools that monitor the server
+    let stats = *context.tick_stats.read().unwrap();
+    let mut performance = JsonValue::new_object();
+    performance["tps"] = stats.tps.iter().map(|t| round(*t)).collect::<Vec<f64>>().into();
+    performance["mspt"] = stats.mspt.iter().map(|t| round(*t)).collect::<Vec<f64>>().into();
+    json["performance"] = performance;
+
+    if let Some(favicon) = &context.favicon {
+        json["favicon"] = JsonValue::String(favicon.clone());
+    }
+    json
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+//Reads the server icon as the data url the server list expects, it has to be a 64x64 PNG
+pub fn load_favicon(path: &Path) -> Option<String> {
+    let data = std::fs::read(path).ok()?;
+
+    //PNG signature followed by the IHDR chunk, which starts with the width and height
+    if data.len() < 24 || &data[..8] != b"\x89PNG\r\n\x1a\n" || &data[12..16] != b"IHDR" {
+        log!("{} is not a PNG image, ignoring it", path.display());
+        return None;
+    }
+    let width = u32::from_be_bytes([data[16], data[17], data[18], data[19]]);
+    let height = u32::from_be_bytes([data[20], data[21], data[22], data[23]]);
+    if width != 64 || height != 64 {
+        log!("{} must be 64x64 pixels, ignoring it", path.display());
+        return None;
+    }
+
+    Some(format!("data:image/png;base64,{}", data.to_base64(STANDARD)))
 }
 
-fn parse_json(mut json: JsonValue) -> Option<(Uuid, String)> {
+//Answer to the pre-netty server list ping (0xFE), a kick packet whose reason carries the status
+//Beta clients only send 0xFE and expect the short § delimited format, everything newer
+//sends 0xFE 0x01 (0xFA ... since 1.6) and gets the §1 format with the protocol and version
+pub fn legacy_ping_response(context: &LoginContext, data: &[u8]) -> Vec<u8> {
+    let online = context.players.read().unwrap().len();
+    let max = context.config.max_players;
+
+    let reason = if data.len() == 1 {
+        format!("{}\u{a7}{}\u{a7}{}", context.config.motd.replace('\u{a7}', ""), online, max)
+    } else {
+        format!(
+            "\u{a7}1\u{0}{}\u{0}{}\u{0}{}\u{0}{}\u{0}{}",
+            PROTOCOL_VERSION, VERSION_NAME, context.config.motd, online, max
+        )
+    };
+
+    let reason: Vec<u16> = reason.encode_utf16().collect();
+    let mut response = Vec::with_capacity(3 + reason.len() * 2);
+    response.push(0xFF);
+    response.extend_from_slice(&(reason.len() as u16).to_be_bytes());
+    for c in reason {
+        response.extend_from_slice(&c.to_be_bytes());
+    }
+    response
+}
+
+//Finishes the login of an authenticated client, next packets are already in the Play state
+pub fn finish_login(client: &mut PlayerLoginClient, profile: GameProfile, config: &ServerConfig) {
+    //Every packet after this one uses the compressed format
+    if let Some(threshold) = config.compression_threshold() {
+        client.write(Packet::SetCompression {
+            threshold: threshold as i32,
+        });
+        client.compression = Some(threshold);
+    }
+
+    client.write(Packet::LoginSuccess {
+        uuid: profile.uuid,
+        nickname: profile.name.clone(),
+    });
+
+    client.connection.identifier = profile.name.clone();
+    client.uuid = Some(profile.uuid);
+    client.nickname = Some(profile.name);
+    client.properties = profile.properties;
+}
+
+//Asks the session server if the player really joined this server, this blocks, don't call it in the IO thread
+pub fn has_joined(
+    session_server: &str,
+    nickname: &str,
+    server_hash: &str,
+) -> Result<GameProfile, &'static str> {
+    let url = format!(
+        "{}/session/minecraft/hasJoined?username={}&serverId={}",
+        session_server,
+        url_encode(nickname),
+        url_encode(server_hash)
+    );
+
+    let response = match https::get(&url) {
+        Ok(t) => t,
+        Err(e) => {
+            log!(
+                "Error while contacting the session server to login {}: {}",
+                nickname,
+                e.reason()
+            );
+            return Err("Authentication servers are down. Please try again later, sorry!");
+        }
+    };
+
+    //204 means the client didn't join, anything else but 200 is an error
+    if response.status != 200 {
+        if response.status != 204 {
+            log!(
+                "Session server answered {} while logging in {}",
+                response.status, nickname
+            );
+        }
+        return Err("Failed to verify username!");
+    }
+
+    let json = match std::str::from_utf8(&response.body)
+        .ok()
+        .and_then(|x| json::parse(x).ok())
+    {
+        Some(t) => t,
+        None => {
+            log!("Error while parsing login response to json: {}", nickname);
+            return Err("Failed to verify username!");
+        }
+    };
+
+    match parse_json(json) {
+        Some(t) => Ok(t),
+        None => {
+            log!("Error while parsing login response data: {}", nickname);
+            Err("Failed to verify username!")
+        }
+    }
+}
+
+//Same UUID vanilla gives in offline mode, a version 3 UUID of "OfflinePlayer:<name>"
+//Vanilla hashes it without a namespace, so Uuid::new_v3 can't be used
+pub fn offline_uuid(nickname: &str) -> Uuid {
+    let digest = hash(
+        MessageDigest::md5(),
+        format!("OfflinePlayer:{}", nickname).as_bytes(),
+    )
+    .expect("Couldn't hash the offline player name");
+
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest);
+    bytes[6] = (bytes[6] & 0x0F) | 0x30;
+    bytes[8] = (bytes[8] & 0x3F) | 0x80;
+    Uuid::from_bytes(bytes)
+}
+
+fn parse_json(mut json: JsonValue) -> Option<GameProfile> {
     let uuid = match json["id"].as_str() {
         Some(t) => t,
         None => return None,
@@ -187,7 +409,21 @@ fn parse_json(mut json: JsonValue) -> Option<(Uuid, String)> {
         None => return None,
     };
 
-    return Some((uuid, name));
+    //Skin and cape textures, signed by Mojang
+    let mut properties = Vec::new();
+    for property in json["properties"].members_mut() {
+        properties.push(PlayerInfoProperties {
+            name: property["name"].take_string()?,
+            value: property["value"].take_string()?,
+            signature: property["signature"].take_string(),
+        });
+    }
+
+    Some(GameProfile {
+        uuid,
+        name,
+        properties,
+    })
 }
 
 pub static mut RSA: Option<Rsa<Private>> = None;
@@ -228,7 +464,148 @@ fn two_complement(bytes: &mut [u8; 20]) {
         bytes[i] = !bytes[i] & 0xff;
         if carry {
             carry = bytes[i] == 0xff;
-            bytes[i] = bytes[i] + 1;
+            bytes[i] = bytes[i].wrapping_add(1);
         }
     }
 }
+
+#[test]
+fn hex_digest_matches_vanilla() {
+    let digest = |name: &[u8]| {
+        let mut sha1 = Sha1::new();
+        sha1.update(name);
+        hex_digest(sha1)
+    };
+
+    assert_eq!(digest(b"Notch"), "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48");
+    assert_eq!(digest(b"jeb_"), "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1");
+    assert_eq!(digest(b"simon"), "88e16a1019277b15d58faf0541e11910eb756f6");
+    //Negative and ending in a 0x00 byte, the carry goes past the last byte
+    assert_eq!(digest(b"Player1157"), "-62ed7b8cdad8c282bcf90da4ddc152fddd5a9200");
+}
+
+#[test]
+fn has_joined_mock_session_server() {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = [0u8; 1024];
+        let read = stream.read(&mut request).unwrap();
+        let request = String::from_utf8_lossy(&request[..read]).to_string();
+
+        let body = r#"{"id":"069a79f444e94726a5befca90e38aaf5","name":"Notch","properties":[{"name":"textures","value":"dGV4dHVyZXM=","signature":"c2lnbmF0dXJl"}]}"#;
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        request
+    });
+
+    let profile = has_joined(&url, "Notch", "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1").unwrap();
+    let request = server.join().unwrap();
+
+    assert!(request.starts_with(
+        "GET /session/minecraft/hasJoined?username=Notch&serverId=-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1 HTTP/1.1"
+    ));
+    assert_eq!(
+        profile.uuid,
+        Uuid::from_str("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap()
+    );
+    assert_eq!(profile.name, "Notch");
+    assert_eq!(profile.properties.len(), 1);
+    assert_eq!(profile.properties[0].name, "textures");
+    assert_eq!(profile.properties[0].signature.as_deref(), Some("c2lnbmF0dXJl"));
+}
+
+#[test]
+fn has_joined_rejects_unauthenticated() {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        //The request is ignored, only its arrival matters
+        let _ = stream.read(&mut [0u8; 1024]).unwrap();
+        stream
+            .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+            .unwrap();
+    });
+
+    assert!(has_joined(&url, "Notch", "0").is_err());
+    server.join().unwrap();
+}
+
+#[test]
+fn offline_uuid_matches_vanilla() {
+    assert_eq!(
+        offline_uuid("Notch"),
+        Uuid::from_str("b50ad385-829d-3141-a216-7e7d7539ba7f").unwrap()
+    );
+}
+
+#[test]
+fn legacy_ping_response_carries_the_status() {
+    use crate::game::player::PlayerSample;
+    use std::sync::{Arc, RwLock};
+
+    let config = ServerConfig {
+        motd: "A \u{a7}6server".to_string(),
+        max_players: 20,
+        ..Default::default()
+    };
+    let sample = PlayerSample {
+        uuid: Uuid::nil(),
+        nickname: "Steve".to_string(),
+    };
+    let context = LoginContext {
+        config: Box::leak(Box::new(config)),
+        players: Box::leak(Box::new(RwLock::new(Arc::new(vec![sample])))),
+        tick_stats: Box::leak(Box::new(RwLock::new(Default::default()))),
+        favicon: None,
+    };
+    //Kick packet id, length in characters and the UTF-16 reason
+    let reason = |response: Vec<u8>| {
+        assert_eq!(response[0], 0xFF);
+        let length = u16::from_be_bytes([response[1], response[2]]) as usize;
+        assert_eq!(response.len(), 3 + length * 2);
+        let chars: Vec<u16> = response[3..]
+            .chunks(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        String::from_utf16(&chars).unwrap()
+    };
+
+    //Beta format, the motd can't keep its § since it's the delimiter
+    assert_eq!(
+        reason(legacy_ping_response(&context, &[0xFE])),
+        "A 6server\u{a7}1\u{a7}20"
+    );
+    assert_eq!(
+        reason(legacy_ping_response(&context, &[0xFE, 0x01, 0xFA])),
+        "\u{a7}1\u{0}47\u{0}1.8.9\u{0}A \u{a7}6server\u{0}1\u{0}20"
+    );
+}
+
+#[test]
+fn check_version_rejects_other_protocols() {
+    assert_eq!(check_version(47).unwrap().name, "1.8.9");
+    assert_eq!(
+        check_version(5).err().unwrap(),
+        "Outdated client! Please use 1.8.9"
+    );
+    assert_eq!(
+        check_version(340).err().unwrap(),
+        "Outdated server! I'm still on 1.8.9"
+    );
+}
@@ -1,56 +1,143 @@
-use crate::data_reader::DataReader;
+use crate::config::ServerConfig;
+use crate::data_reader::{DataReader, PacketError};
 use crate::data_writer::DataWriter;
 use crate::game::chat::ChatComponent;
-use crate::game::packets::{ExtendedPacket, Packet};
+use crate::game::packets::{ExtendedPacket, Packet, PlayerInfoProperties};
+use crate::net::compression;
 use crate::net::login_handler;
-use crate::net::login_handler::HandleResult;
-use crate::net::network_manager::DisconnectReason::{IOError, Timeout};
+use crate::game::player::PlayerListSnapshot;
+use crate::game::tick::TickStatsSnapshot;
+use crate::net::login_handler::{GameProfile, HandleResult, LoginContext};
+use crate::net::network_manager::DisconnectReason::{
+    IOError, Kicked, MalformedPacket, SlowConnection, Timeout,
+};
 use aes::cipher::StreamCipher;
 use aes::Aes128;
 use cfb8::Cfb8;
 use mio::event::Source;
 use mio::net::{TcpListener, TcpStream};
-use mio::{Events, Interest, Poll, Token};
+use mio::{Events, Interest, Poll, Registry, Token};
 use openssl::rsa::Rsa;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::io::{ErrorKind, Read, Write};
-use std::net::{Shutdown, SocketAddr};
-use std::sync::mpsc::{Receiver, Sender};
+use std::path::Path;
+use std::net::{Shutdown, SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use regex::internal::Inst;
 use uuid::Uuid;
 
-//Server address
-const ADDR: &str = "127.0.0.1:25565";
-
 //Token for epoll identification
 const SERVER_TOKEN: Token = Token(0);
 
 const BUFFER_SIZE: usize = 4096;
 
+//Biggest frame a client may send, the same limit of the 3 bytes length varint vanilla uses
+pub const MAX_FRAME_LENGTH: usize = 2097151;
+
 pub struct Connection {
     pub token: Token,
     pub stream: TcpStream,
     pub addr: SocketAddr,
     pub identifier: String,
+    //Decrypted bytes of frames that didn't fully arrive yet
+    pub buffer: Vec<u8>,
+    //Encrypted bytes waiting for the socket to accept them
+    pub outbound: VecDeque<u8>,
+    //If WRITABLE is registered in the epoll, only while there is something queued
+    pub writable_interest: bool,
+    //Set when writing fails, the client gets removed in the next sweep
+    pub closed: bool,
+    //Shut down by the sweep once everything queued is written, nothing more is read meanwhile
+    pub closing: bool,
+}
+
+impl Connection {
+    pub fn new(token: Token, stream: TcpStream, addr: SocketAddr) -> Self {
+        Connection {
+            token,
+            stream,
+            addr,
+            identifier: addr.ip().to_string(),
+            buffer: Vec::new(),
+            outbound: VecDeque::new(),
+            writable_interest: false,
+            closed: false,
+            closing: false,
+        }
+    }
+
+    //Queues bytes that are ready for the wire and writes as much as the socket accepts now,
+    //the rest is written when the epoll tells the socket is writable again
+    pub fn queue(&mut self, data: &[u8]) {
+        self.outbound.extend(data);
+        self.flush();
+    }
+
+    pub fn flush(&mut self) {
+        while !self.outbound.is_empty() && !self.closed {
+            let written = match self.stream.write(self.outbound.as_slices().0) {
+                Ok(0) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(t) => t,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    log!(
+                        "An error occured while writing to {}'s stream: {}",
+                        self.identifier, e
+                    );
+                    self.closed = true;
+                    break;
+                }
+            };
+            self.outbound.drain(..written);
+        }
+    }
+
+    pub fn update_interest(&mut self, registry: &Registry) {
+        let writable = !self.outbound.is_empty();
+        if writable == self.writable_interest {
+            return;
+        }
+
+        let interest = if writable {
+            Interest::READABLE | Interest::WRITABLE
+        } else {
+            Interest::READABLE
+        };
+        //The connection is closed the next time the clients are checked
+        if let Err(e) = registry.reregister(&mut self.stream, self.token, interest) {
+            log!("Couldn't update the interest of {}: {}", self.identifier, e);
+            self.closed = true;
+            return;
+        }
+        self.writable_interest = writable;
+    }
 }
 
 pub struct PlayerLoginClient {
     pub connection: Connection,
     pub state: ConnectionState,
+    //Sent in the handshake, only the ones in login_handler::SUPPORTED_VERSIONS reach the login
+    pub protocol_version: i32,
     pub nickname: Option<String>,
     pub verify_token: Option<[u8; 4]>,
     pub encode: Option<Cfb8<Aes128>>,
     pub decode: Option<Cfb8<Aes128>>,
     pub uuid: Option<Uuid>,
+    pub properties: Vec<PlayerInfoProperties>,
+    pub compression: Option<usize>,
 }
 
 impl PlayerLoginClient {
     pub fn write(&mut self, packet: Packet) {
         //Serialize
-        let mut data = match packet.serialize_length() {
-            Some(t) => t,
+        let mut data = match packet.serialize() {
+            Some(t) => compression::frame(t, self.compression),
             None => return,
         };
         //Encrypt
@@ -59,27 +146,20 @@ impl PlayerLoginClient {
             None => {}
         }
         //Write
-        self.connection.stream.write(&data);
+        self.connection.queue(&data);
     }
 
     pub fn write_dc(&mut self, reason: String) {
-        //Serialize
-        let packet = Packet::DisconnectLogin {
+        self.write(Packet::DisconnectLogin {
             reason: ChatComponent::new_text(reason),
-        };
-        let mut serialized = match packet.serialize_length() {
-            Some(t) => t,
-            None => return,
-        };
-        //Write
-        self.connection.stream.write(&serialized);
+        });
     }
 
     pub fn shutdown(&mut self, reason: String, poll: &Poll) {
-        if let ConnectionState::Status = self.state {
+        if let ConnectionState::Login = self.state {
             self.write_dc(reason);
         }
-        self.connection.stream.flush();
+        self.connection.flush();
         poll.registry().deregister(&mut self.connection.stream);
         self.connection.stream.shutdown(Shutdown::Both);
     }
@@ -87,45 +167,69 @@ impl PlayerLoginClient {
 
 pub struct PlayerClient {
     connection: Connection,
-    encode: Cfb8<Aes128>,
-    decode: Cfb8<Aes128>,
+    //Both are None when the server is in offline mode
+    encode: Option<Cfb8<Aes128>>,
+    decode: Option<Cfb8<Aes128>>,
     keep_alive: Instant,
+    compression: Option<usize>,
 }
 
 impl PlayerClient {
     pub fn write(&mut self, packet: Packet) {
         //Serialize
-        let mut data = match packet.serialize_length() {
-            Some(t) => t,
+        let mut data = match packet.serialize() {
+            Some(t) => compression::frame(t, self.compression),
             None => return,
         };
         //Encrypt
-        self.encode.encrypt(&mut data);
+        if let Some(encode) = &mut self.encode {
+            encode.encrypt(&mut data);
+        }
         //Write
-        self.connection.stream.write(&data);
-        self.connection.stream.flush();
+        self.connection.queue(&data);
     }
 
     pub fn write_data_ref(&mut self, data: &Vec<u8>) {
         let mut data = data.clone();
         //Encrypt
-        self.encode.encrypt(&mut data);
+        if let Some(encode) = &mut self.encode {
+            encode.encrypt(&mut data);
+        }
         //Write
-        self.connection.stream.write(&data);
+        self.connection.queue(&data);
     }
 
     pub fn write_data_owned(&mut self, mut data: Vec<u8>) {
         //Encrypt
-        self.encode.encrypt(&mut data);
+        if let Some(encode) = &mut self.encode {
+            encode.encrypt(&mut data);
+        }
         //Write
-        self.connection.stream.write(&data);
+        self.connection.queue(&data);
     }
 
     pub fn write_mut_slice(&mut self, data: &mut [u8]) {
         //Encrypt
-        self.encode.encrypt(data);
+        if let Some(encode) = &mut self.encode {
+            encode.encrypt(data);
+        }
         //Write
-        self.connection.stream.write(&data);
+        self.connection.queue(data);
+    }
+
+    //Writes a packet whose id and fields are split in two buffers, avoiding to copy big payloads
+    //when the frame doesn't need to be compressed
+    pub fn write_split(&mut self, header: Vec<u8>, mut body: Vec<u8>) {
+        if self.compression.is_some() {
+            let mut data = header;
+            data.append(&mut body);
+            self.write_data_owned(compression::frame(data, self.compression));
+            return;
+        }
+
+        self.write_mut_slice(DataWriter::var_num((header.len() + body.len()) as u64).as_mut_slice());
+        self.write_data_owned(header);
+        self.write_data_owned(body);
     }
 
     pub fn shutdown(&mut self, reason: String, poll: &Poll) {
@@ -136,7 +240,7 @@ impl PlayerClient {
     }
 
     pub fn shutdown_connection(&mut self, poll: &Poll) {
-        self.connection.stream.flush();
+        self.connection.flush();
         poll.registry().deregister(&mut self.connection.stream);
         self.connection.stream.shutdown(Shutdown::Both);
     }
@@ -150,10 +254,22 @@ pub enum ConnectionState {
     Play,
 }
 
-pub fn start(net_writer: Sender<GameProtocol>, net_reader: Receiver<NetProtocol>) {
+pub fn start(
+    config: &'static ServerConfig,
+    player_snapshot: PlayerListSnapshot,
+    tick_stats: TickStatsSnapshot,
+    net_writer: Sender<GameProtocol>,
+    net_reader: Receiver<NetProtocol>,
+) {
     //Open server
-    let mut server = TcpListener::bind(ADDR.parse().unwrap())
-        .expect("An error occured while binding the server");
+    let address = config
+        .address()
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut x| x.next())
+        .expect("Invalid server-ip or server-port");
+    let mut server =
+        TcpListener::bind(address).expect("An error occured while binding the server");
 
     //Initialize epoll
     let mut poll = Poll::new().expect("An error occured while initializing the epoll");
@@ -168,7 +284,10 @@ pub fn start(net_writer: Sender<GameProtocol>, net_reader: Receiver<NetProtocol>
     let mut play_clients: HashMap<Token, PlayerClient> = HashMap::new();
     let mut token_counter = 1usize;
 
-    println!("Waiting for connections on {}", ADDR);
+    //Session server answers, sent back from the authentication threads
+    let (auth_writer, auth_reader) = channel::<(Token, Result<GameProfile, &'static str>)>();
+
+    log!("Waiting for connections on {}", address);
 
     std::thread::Builder::new()
         .name("IO Network Thread".to_string())
@@ -183,6 +302,13 @@ pub fn start(net_writer: Sender<GameProtocol>, net_reader: Receiver<NetProtocol>
                 login_handler::RSA = Some(rsa);
             }
 
+            let login_context = LoginContext {
+                config,
+                players: player_snapshot,
+                tick_stats,
+                favicon: login_handler::load_favicon(Path::new(login_handler::FAVICON_FILE)),
+            };
+
             let mut last_keepalive = Instant::now();
 
             loop {
@@ -204,18 +330,20 @@ pub fn start(net_writer: Sender<GameProtocol>, net_reader: Receiver<NetProtocol>
                                 //Got a client
                                 Ok(mut client) => {
                                     let mut login_client = PlayerLoginClient {
-                                        connection: Connection {
-                                            token: Token(token_counter),
-                                            stream: client.0,
-                                            addr: client.1,
-                                            identifier: client.1.ip().to_string(),
-                                        },
+                                        connection: Connection::new(
+                                            Token(token_counter),
+                                            client.0,
+                                            client.1,
+                                        ),
                                         state: ConnectionState::Handshaking,
+                                        protocol_version: 0,
                                         nickname: None,
                                         verify_token: None,
                                         encode: None,
                                         decode: None,
                                         uuid: None,
+                                        properties: Vec::new(),
+                                        compression: None,
                                     };
 
                                     //Check if client is already logging
@@ -250,7 +378,7 @@ pub fn start(net_writer: Sender<GameProtocol>, net_reader: Receiver<NetProtocol>
 
                                 //Check for another error
                                 Err(e) => {
-                                    println!("An error occured while accepting a client: {}", e)
+                                    log!("An error occured while accepting a client: {}", e)
                                 }
                             }
                         }
@@ -271,20 +399,25 @@ pub fn start(net_writer: Sender<GameProtocol>, net_reader: Receiver<NetProtocol>
                         {
                             Some(t) => t,
                             None => {
-                                println!("Token not found in epoll event: {}", token.0);
+                                log!("Token not found in epoll event: {}", token.0);
                                 continue;
                             }
                         };
 
                         let mut disconnect = false;
 
+                        //Write what was waiting for the socket
+                        if event.is_writable() {
+                            connection.flush();
+                        }
+
                         //Check for connection states first, this may not trigger in some platforms,
                         //thats why we still keep track on EOF and read 0 while reading the stream
                         if event.is_read_closed() {
                             disconnect = true
                         } else if event.is_error() {
                             disconnect = true;
-                            println!(
+                            log!(
                                 "An error occured in client {} socket, told by the epoll",
                                 connection.identifier
                             )
@@ -315,7 +448,7 @@ pub fn start(net_writer: Sender<GameProtocol>, net_reader: Receiver<NetProtocol>
 
                                     //Check another error
                                     Err(e) => {
-                                        println!(
+                                        log!(
                                             "An error occured while reading {}'s stream: {}",
                                             connection.identifier, e
                                         );
@@ -346,15 +479,66 @@ pub fn start(net_writer: Sender<GameProtocol>, net_reader: Receiver<NetProtocol>
                             continue;
                         }
 
-                        //If it is play client, then decrypt the data first
-                        if let Some(ref mut client) = play_client {
-                            client.decode.decrypt(&mut vec);
+                        //Pre-netty server list ping, it isn't length prefixed so it's answered before framing
+                        if let Some(client) = &mut login_client {
+                            if client.connection.closing {
+                                continue;
+                            }
+                            if let ConnectionState::Handshaking = client.state {
+                                if client.connection.buffer.is_empty() && vec.first() == Some(&0xFE) {
+                                    let response = login_handler::legacy_ping_response(&login_context, &vec);
+                                    //Shutting down now could drop what the socket didn't take yet
+                                    client.connection.queue(&response);
+                                    client.connection.closing = true;
+                                    continue;
+                                }
+                            }
                         }
 
-                        //Read packets length, id and separe them
-                        let raw_packets = match read_packets(&vec) {
-                            Some(t) => t,
+                        //Decrypt the new data and append it to what was left from the last reads
+                        let (buffer, compressed) = match play_client {
+                            Some(ref mut client) => {
+                                if let Some(decode) = &mut client.decode {
+                                    decode.decrypt(&mut vec);
+                                }
+                                (&mut client.connection.buffer, client.compression.is_some())
+                            }
                             None => {
+                                let client = login_client.as_mut().unwrap();
+                                if let Some(decode) = &mut client.decode {
+                                    decode.decrypt(&mut vec);
+                                }
+                                (&mut client.connection.buffer, client.compression.is_some())
+                            }
+                        };
+                        buffer.extend_from_slice(&vec);
+
+                        //Read packets length, id and separe them, incomplete frames are kept for the next read
+                        let raw_packets = match read_packets(buffer, compressed) {
+                            Ok(t) => t,
+                            Err(e) => {
+                                if let Some(client) = play_client {
+                                    log!(
+                                        "Kicking {} for sending a malformed packet: {}",
+                                        client.connection.identifier,
+                                        e.reason()
+                                    );
+                                    client.shutdown(e.reason(), &poll);
+                                    let _ = net_writer.send(GameProtocol::ForcedDisconnect {
+                                        token,
+                                        reason: MalformedPacket,
+                                    });
+                                    play_clients.remove(&token);
+                                } else {
+                                    let client = login_client.unwrap();
+                                    log!(
+                                        "Kicking {} for sending a malformed packet: {}",
+                                        client.connection.identifier,
+                                        e.reason()
+                                    );
+                                    client.shutdown(e.reason(), &poll);
+                                    login_clients.remove(&token);
+                                }
                                 continue;
                             }
                         };
@@ -362,31 +546,50 @@ pub fn start(net_writer: Sender<GameProtocol>, net_reader: Receiver<NetProtocol>
                         //Handle the login
                         match login_client {
                             Some(client) => {
-                                let result = login_handler::handle(raw_packets, client);
+                                let result = match login_handler::handle(
+                                    raw_packets,
+                                    client,
+                                    &login_context,
+                                ) {
+                                    Ok(t) => t,
+                                    Err(e) => {
+                                        log!(
+                                            "Kicking {} for sending a malformed packet: {}",
+                                            client.connection.identifier,
+                                            e.reason()
+                                        );
+                                        client.shutdown(e.reason(), &poll);
+                                        login_clients.remove(&token);
+                                        continue;
+                                    }
+                                };
                                 match result {
                                     HandleResult::Disconnect(reason) => {
-                                        client.shutdown(reason.to_string(), &poll);
+                                        client.shutdown(reason, &poll);
                                         login_clients.remove(&token);
-                                        break;
+                                        continue;
                                     }
                                     HandleResult::Login => {
                                         //Player is ready to go to Play connection state
                                         let client = login_clients.remove(&token).unwrap();
-
-                                        let play_client = PlayerClient {
-                                            connection: client.connection,
-                                            encode: client.encode.unwrap(),
-                                            decode: client.decode.unwrap(),
-                                            keep_alive: now,
-                                        };
-
-                                        play_clients
-                                            .insert(play_client.connection.token, play_client);
-                                        net_writer.send(GameProtocol::Login {
-                                            token,
-                                            uuid: client.uuid.unwrap(),
-                                            nickname: client.nickname.unwrap(),
-                                        });
+                                        enter_play(client, now, &mut play_clients, &net_writer);
+                                    }
+                                    HandleResult::Authenticate(server_hash) => {
+                                        //The session server request blocks, so it goes to another thread
+                                        let nickname = client.nickname.clone().unwrap();
+                                        let auth_writer = auth_writer.clone();
+                                        std::thread::Builder::new()
+                                            .name("Authentication Thread".to_string())
+                                            .spawn(move || {
+                                                let result = login_handler::has_joined(
+                                                    &config.session_server,
+                                                    &nickname,
+                                                    &server_hash,
+                                                );
+                                                //The network thread only stops with the server
+                                                let _ = auth_writer.send((token, result));
+                                            })
+                                            .expect("Couldn't spawn an authentication thread");
                                     }
                                     HandleResult::None => {}
                                 }
@@ -398,27 +601,44 @@ pub fn start(net_writer: Sender<GameProtocol>, net_reader: Receiver<NetProtocol>
                         //Read packets
                         match play_client {
                             Some(player) => {
+                                let mut malformed = None;
                                 for raw_packet in raw_packets {
-                                    let packet = Packet::read(
+                                    let packet = match Packet::read(
                                         raw_packet.id,
-                                        &mut DataReader::new(raw_packet.data),
+                                        &mut DataReader::new(&raw_packet.data),
                                         ConnectionState::Play,
-                                    );
+                                    ) {
+                                        Ok(t) => t,
+                                        Err(e) => {
+                                            malformed = Some(e);
+                                            break;
+                                        }
+                                    };
+
+                                    //Send packets to be processed by the tick thread
                                     match packet {
-                                        Some(packet) => {
-                                            //Send packets to be processed by the tick thread
-                                            match packet {
-                                                Packet::KeepAlive { id } => player.keep_alive = now,
-                                                _ => {
-                                                    net_writer.send(GameProtocol::Packet {
-                                                        token,
-                                                        packet,
-                                                    });
-                                                }
-                                            };
+                                        Packet::KeepAlive { .. } => player.keep_alive = now,
+                                        _ => {
+                                            //Only fails once the tick thread is gone
+                                            let _ = net_writer
+                                                .send(GameProtocol::Packet { token, packet });
                                         }
-                                        None => {}
-                                    }
+                                    };
+                                }
+
+                                //Nothing after a malformed packet can be trusted
+                                if let Some(e) = malformed {
+                                    log!(
+                                        "Kicking {} for sending a malformed packet: {}",
+                                        player.connection.identifier,
+                                        e.reason()
+                                    );
+                                    player.shutdown(e.reason(), &poll);
+                                    let _ = net_writer.send(GameProtocol::ForcedDisconnect {
+                                        token,
+                                        reason: MalformedPacket,
+                                    });
+                                    play_clients.remove(&token);
                                 }
                             }
                             None => {}
@@ -426,6 +646,27 @@ pub fn start(net_writer: Sender<GameProtocol>, net_reader: Receiver<NetProtocol>
                     }
                 }
 
+                //Finish the logins the session server answered
+                for (token, result) in auth_reader.try_iter() {
+                    //The client may have disconnected while waiting
+                    let client = match login_clients.get_mut(&token) {
+                        Some(t) => t,
+                        None => continue,
+                    };
+
+                    match result {
+                        Ok(profile) => {
+                            login_handler::finish_login(client, profile, config);
+                            let client = login_clients.remove(&token).unwrap();
+                            enter_play(client, now, &mut play_clients, &net_writer);
+                        }
+                        Err(reason) => {
+                            client.shutdown(reason.to_string(), &poll);
+                            login_clients.remove(&token);
+                        }
+                    }
+                }
+
                 //Starts to read the game messages
                 for message in net_reader.try_iter() {
                     match message {
@@ -465,10 +706,58 @@ pub fn start(net_writer: Sender<GameProtocol>, net_reader: Receiver<NetProtocol>
                             };
                             client.shutdown_connection(&poll);
                             play_clients.remove(&token);
+                            //Let the engine forget the player too
+                            let _ = net_writer.send(GameProtocol::ForcedDisconnect {
+                                token,
+                                reason: Kicked,
+                            });
                         }
                     }
                 }
 
+                //Drop clients that stopped reading or failed writing, then ask for WRITABLE
+                //events only for who still has something queued
+                play_clients.retain(|token, player| {
+                    let connection = &mut player.connection;
+                    if connection.closed || connection.outbound.len() > config.outbound_high_water_mark {
+                        let reason = if connection.closed {
+                            IOError
+                        } else {
+                            log!(
+                                "Disconnecting {}, it has {} bytes waiting to be written",
+                                connection.identifier,
+                                connection.outbound.len()
+                            );
+                            SlowConnection
+                        };
+                        player.shutdown_connection(&poll);
+                        let _ = net_writer.send(GameProtocol::ForcedDisconnect {
+                            token: *token,
+                            reason,
+                        });
+                        return false;
+                    }
+
+                    connection.update_interest(poll.registry());
+                    true
+                });
+                login_clients.retain(|_, client| {
+                    let connection = &mut client.connection;
+                    //The socket is dropped right after, errors closing it change nothing
+                    if connection.closed {
+                        let _ = poll.registry().deregister(&mut connection.stream);
+                        return false;
+                    }
+                    if connection.closing && connection.outbound.is_empty() {
+                        let _ = poll.registry().deregister(&mut connection.stream);
+                        let _ = connection.stream.shutdown(Shutdown::Both);
+                        return false;
+                    }
+
+                    client.connection.update_interest(poll.registry());
+                    true
+                });
+
                 if send_keepalive {
                     last_keepalive = now;
 
@@ -488,7 +777,10 @@ pub fn start(net_writer: Sender<GameProtocol>, net_reader: Receiver<NetProtocol>
                     });
 
                     //Send keep alive packets
-                    let keep_alive = Packet::KeepAlive { id: 0 }.serialize_length().unwrap();
+                    let keep_alive = compression::frame(
+                        Packet::KeepAlive { id: 0 }.serialize().unwrap(),
+                        config.compression_threshold(),
+                    );
                     for player in play_clients.values_mut() {
                         player.write_data_ref(&keep_alive);
                     }
@@ -497,6 +789,31 @@ pub fn start(net_writer: Sender<GameProtocol>, net_reader: Receiver<NetProtocol>
         });
 }
 
+//Moves a client that finished the login to the Play state and tells the engine about it
+fn enter_play(
+    client: PlayerLoginClient,
+    now: Instant,
+    play_clients: &mut HashMap<Token, PlayerClient>,
+    net_writer: &Sender<GameProtocol>,
+) {
+    let token = client.connection.token;
+    let play_client = PlayerClient {
+        connection: client.connection,
+        encode: client.encode,
+        decode: client.decode,
+        keep_alive: now,
+        compression: client.compression,
+    };
+
+    play_clients.insert(token, play_client);
+    let _ = net_writer.send(GameProtocol::Login {
+        token,
+        uuid: client.uuid.unwrap(),
+        nickname: client.nickname.unwrap(),
+        properties: client.properties,
+    });
+}
+
 pub enum NetProtocol {
     SendPacket { token: Token, packet: Packet },
     SendExtendedPacket { token: Token, packet: ExtendedPacket },
@@ -510,6 +827,7 @@ pub enum GameProtocol {
         token: Token,
         nickname: String,
         uuid: Uuid,
+        properties: Vec<PlayerInfoProperties>,
     },
     ForcedDisconnect {
         token: Token,
@@ -519,11 +837,19 @@ pub enum GameProtocol {
         token: Token,
         packet: Packet,
     },
+    //Command typed in the server console, without its /
+    Console {
+        line: String,
+    },
 }
 
 pub enum DisconnectReason {
     Timeout,
     IOError,
+    MalformedPacket,
+    SlowConnection,
+    //The engine asked to disconnect the player
+    Kicked,
 }
 
 pub struct NetWriter {
@@ -563,59 +889,99 @@ impl Clone for NetWriter {
     }
 }
 
-pub struct RawPacket<'a> {
+pub struct RawPacket {
     pub id: i32,
-    pub data: &'a [u8],
+    pub data: Vec<u8>,
 }
 
-fn read_varint<'a>(slice: &[u8], index: &mut usize) -> Option<i32> {
-    let mut result: i32 = 0;
-    let mut read: u8;
+//Reads a frame length, None if its bytes didn't arrive yet
+fn read_frame_length(slice: &[u8]) -> Result<Option<(usize, usize)>, PacketError> {
+    let mut result = 0usize;
     for i in 0..5 {
-        read = *slice.get(i)?;
-        result += (((read as i8) & 0b01111111) as i32) << (7 * i);
-        *index += 1;
+        let read = match slice.get(i) {
+            Some(t) => *t,
+            None => return Ok(None),
+        };
+        result |= ((read & 0b01111111) as usize) << (7 * i);
 
         if (read & 0b10000000) == 0 {
-            return Some(result);
+            return Ok(Some((result, i + 1)));
         }
     }
 
-    return None;
+    Err(PacketError::VarIntTooLong)
 }
 
-fn read_packets(data: &Vec<u8>) -> Option<Vec<RawPacket>> {
+//Splits every complete frame of the buffer in packets, removing them from it
+fn read_packets(buffer: &mut Vec<u8>, compressed: bool) -> Result<Vec<RawPacket>, PacketError> {
     let mut raw_packets = Vec::new();
     let mut index = 0usize;
-    while index < data.len() {
-        //Check if it has no space for packet length reading
-        if index >= data.len() {
-            println!("Index bigger 1 {} {} {:?}", index, data.len(), data);
-            return None;
+    while index < buffer.len() {
+        let (length, length_size) = match read_frame_length(&buffer[index..])? {
+            Some(t) => t,
+            None => break,
         };
-        let mut length = read_varint(&data[index..], &mut index)? as usize;
-        let mut id_length = 0usize;
 
-        //Check if it has no space for id length reading
-        if index >= data.len() {
-            println!("Index bigger 2 {} {} {:?}", index, data.len(), data);
-            return None;
-        };
-        let id = read_varint(&data[index..], &mut id_length)?;
-        index += id_length as usize;
-        length -= id_length;
-
-        //Check if it has no space for reading the rest of the packet
-        if index + length > data.len() {
-            println!("Bigger {} {} {} {:?}", index, length, data.len(), data);
-            return None;
-        };
+        if length > MAX_FRAME_LENGTH {
+            return Err(PacketError::FrameTooLong(length));
+        }
+
+        //Wait for the rest of the frame
+        let start = index + length_size;
+        if start + length > buffer.len() {
+            break;
+        }
+
+        //Decompress if needed, then split the id from the fields
+        let body = compression::unframe(&buffer[start..start + length], compressed)
+            .ok_or(PacketError::BadCompression)?;
+        index = start + length;
+
+        let mut reader = DataReader::new(&body);
+        let id = reader.read_varint()?;
         raw_packets.push(RawPacket {
             id,
-            data: &data[index..index + length],
+            data: body[reader.cursor..].to_vec(),
         });
-        index += length;
     }
 
-    Some(raw_packets)
+    buffer.drain(0..index);
+    Ok(raw_packets)
+}
+
+#[test]
+fn reads_packets_across_reads() {
+    //Keep alive with id 0 and value 300, then a chat message saying hi
+    let keep_alive = [0x03, 0x00, 0xAC, 0x02];
+    let chat = [0x04, 0x01, 0x02, b'h', b'i'];
+
+    //Two packets in the same read
+    let mut buffer = [&keep_alive[..], &chat[..]].concat();
+    let packets = read_packets(&mut buffer, false).unwrap();
+    assert_eq!(packets.len(), 2);
+    assert_eq!((packets[0].id, &packets[0].data[..]), (0x00, &[0xAC, 0x02][..]));
+    assert_eq!((packets[1].id, &packets[1].data[..]), (0x01, &[0x02, b'h', b'i'][..]));
+    assert!(buffer.is_empty());
+
+    //A packet split across three reads, the second also starting the next one
+    let mut buffer = keep_alive[..1].to_vec();
+    assert!(read_packets(&mut buffer, false).unwrap().is_empty());
+    buffer.extend_from_slice(&keep_alive[1..]);
+    buffer.extend_from_slice(&chat[..2]);
+    let packets = read_packets(&mut buffer, false).unwrap();
+    assert_eq!(packets.len(), 1);
+    assert_eq!(packets[0].id, 0x00);
+    assert_eq!(buffer, &chat[..2]);
+    buffer.extend_from_slice(&chat[2..]);
+    let packets = read_packets(&mut buffer, false).unwrap();
+    assert_eq!(packets.len(), 1);
+    assert_eq!(packets[0].data, [0x02, b'h', b'i']);
+    assert!(buffer.is_empty());
+
+    //Compressed frames below the threshold
+    let mut buffer = compression::frame(vec![0x00, 0xAC, 0x02], Some(256));
+    buffer.extend(compression::frame(vec![0x01, 0x02, b'h', b'i'], Some(256)));
+    let packets = read_packets(&mut buffer, true).unwrap();
+    assert_eq!(packets.len(), 2);
+    assert_eq!(packets[1].data, [0x02, b'h', b'i']);
 }
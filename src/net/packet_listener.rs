@@ -1,8 +1,26 @@
-use crate::game::{engine::SyncEnvironment, packets::Packet, player::Player};
+use crate::game::{
+    engine::SyncEnvironment,
+    packets::{Packet, PacketKind},
+};
+use crate::net::network_manager::NetWriter;
 
-pub type PacketListener = fn(&Packet, usize, &mut SyncEnvironment);
+pub type PacketListener = fn(&Packet, usize, &NetWriter, &mut SyncEnvironment);
 
 pub struct PacketListenerStruct {
-    pub packet_id: i32,
+    pub kind: PacketKind,
     pub listener: PacketListener,
 }
+
+//Runs every listener registered for the kind of the packet, in registration order
+pub fn dispatch(
+    listeners: &[PacketListenerStruct],
+    packet: &Packet,
+    player_index: usize,
+    net_writer: &NetWriter,
+    environment: &mut SyncEnvironment,
+) {
+    let kind = packet.kind();
+    for listener in listeners.iter().filter(|listener| listener.kind == kind) {
+        (listener.listener)(packet, player_index, net_writer, environment);
+    }
+}